@@ -42,6 +42,28 @@ mod eager {
             divan::black_box(entity);
         }
     }
+
+    #[divan::bench(max_time = std::time::Duration::from_secs(30))]
+    fn parallel_entities_mirage() {
+        let raw_bytes = include_bytes!("../testfiles/mirage.dem");
+
+        let container = csdemo::Container::parse(divan::black_box(raw_bytes.as_slice())).unwrap();
+
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let demo = csdemo::parser::parse_parallel(
+            container.inner,
+            csdemo::parser::EntityFilter::all,
+            threads,
+        )
+        .unwrap();
+
+        for event in demo.events {
+            divan::black_box(event);
+        }
+        for entity in demo.entity_states.ticks {
+            divan::black_box(entity);
+        }
+    }
 }
 
 mod lazy {
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let container = match csdemo::Container::parse_lenient(data) {
+        Ok(c) => c,
+        Err(_) => return,
+    };
+
+    let frames = csdemo::FrameIterator::parse(container.inner);
+    let _ = csdemo::parser::parse(frames, csdemo::parser::EntityFilter::all());
+});
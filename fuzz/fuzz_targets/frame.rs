@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut buffer = Vec::new();
+    for frame in csdemo::FallibleFrameIterator::parse(data).resync(true) {
+        if let Ok((_, frame)) = frame {
+            let _ = frame.decompress_with_buf(&mut buffer);
+        }
+    }
+
+    // Every frame on its own is also decoded by the engine, as if it was part of a demo
+    let mut engine = csdemo::parser::Engine::new(csdemo::parser::EntityFilter::all());
+    for frame in csdemo::FrameIterator::parse(data) {
+        if engine.handle_frame(&frame, &mut Nothing).is_err() {
+            break;
+        }
+    }
+});
+
+struct Nothing;
+impl csdemo::parser::DemoHandler for Nothing {}
@@ -0,0 +1,921 @@
+//! Rewriting demos, to replace the identities of all the players with pseudonyms.
+//!
+//! The following places are rewritten:
+//! * the `userinfo` string table, both in the StringTables/FullPacket frames and in the string
+//!   table messages of packets
+//! * the `CS_UM_EndOfMatchAllPlayersData` and `CS_UM_ServerRankUpdate` messages
+//! * game events, like `player_connect` or `player_disconnect`, with keys for names, xuids,
+//!   network ids or addresses
+//! * chat messages, whose text is removed entirely
+//! * the names and steamIDs of the `CCSPlayerController` entities, whose clan tags are removed
+//!   together with the clan names of the `CCSTeam` entities
+//!
+//! The entity props are rewritten in place in the `svc_PacketEntities` messages, which is why
+//! all the entities of the demo have to be decoded while anonymizing it. The baselines of the
+//! entities are kept as they are, as they are shared by all entities of a class.
+
+use crate::{
+    bitreader::{BitReadError, Bitreader},
+    bitwriter::Bitwriter,
+    netmessagetypes::NetmessageType,
+    parser::{decoder::Decoder, fieldpath, FirstPassError, Paths, Variant},
+    stringtable::StringTableInfo,
+    Container, DemoCommand, DemoWriter, FallibleFrameIterator, FrameDecompressError, FrameError,
+};
+
+/// The SteamID64 of the first individual account, which is used as the base for pseudonym xuids
+const STEAMID64_BASE: u64 = 76561197960265728;
+
+const PLAYER_NAME: &str = "CCSPlayerController.m_iszPlayerName";
+const SANITIZED_PLAYER_NAME: &str = "CCSPlayerController.m_sSanitizedPlayerName";
+const STEAM_ID: &str = "CCSPlayerController.m_steamID";
+const CLAN_TAG: &str = "CCSPlayerController.m_szClan";
+const CLAN_TEAM_NAME: &str = "CCSTeam.m_szClanTeamname";
+
+/// The entity props that are rewritten
+const ENTITY_PROPS: &[&str] = &[
+    PLAYER_NAME,
+    SANITIZED_PLAYER_NAME,
+    STEAM_ID,
+    CLAN_TAG,
+    CLAN_TEAM_NAME,
+];
+
+#[derive(Debug)]
+pub enum AnonymizeError {
+    Frame(FrameError),
+    DecompressFrame(FrameDecompressError),
+    DecodeProtobuf(prost::DecodeError),
+    Bitreader(BitReadError),
+    /// Decoding the entities, whose props are rewritten, failed
+    Parse(crate::parser::ParseError),
+    /// Finding the entity props to rewrite in a `svc_PacketEntities` message failed
+    EntityProps(FirstPassError),
+    Io(std::io::Error),
+}
+
+impl From<prost::DecodeError> for AnonymizeError {
+    fn from(value: prost::DecodeError) -> Self {
+        Self::DecodeProtobuf(value)
+    }
+}
+impl From<BitReadError> for AnonymizeError {
+    fn from(value: BitReadError) -> Self {
+        Self::Bitreader(value)
+    }
+}
+impl From<std::io::Error> for AnonymizeError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// A single player and the pseudonym they were replaced with
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerMapping {
+    pub name: Option<String>,
+    pub xuid: Option<u64>,
+    pub pseudonym: String,
+    pub pseudonym_xuid: u64,
+}
+
+/// Replaces the identities of players with pseudonyms.
+///
+/// The same Anonymizer can be used for multiple demos, in which case every player keeps the
+/// same pseudonym across all of them
+pub struct Anonymizer {
+    players: Vec<PlayerMapping>,
+    by_xuid: std::collections::HashMap<u64, usize>,
+    by_name: std::collections::HashMap<String, usize>,
+
+    // The state of the demo currently being rewritten
+    event_keys: std::collections::HashMap<i32, Vec<String>>,
+    string_tables: Vec<StringTableInfo>,
+    entities: SpanDecoder,
+}
+
+/// The entities are only decoded to find the props to rewrite, so nothing needs to be handled
+struct Entities;
+
+impl crate::parser::DemoHandler for Entities {}
+
+/// The location of the value of a prop in the entity data of a `svc_PacketEntities` message
+#[derive(Debug, Clone, PartialEq)]
+struct PropSpan {
+    entity_id: i32,
+    prop_name: std::sync::Arc<str>,
+    decoder: Decoder,
+    value: Variant,
+    /// The bits of the entity data the value is stored in
+    bits: std::ops::Range<usize>,
+}
+
+/// Finds the [`PropSpan`]s of the [`ENTITY_PROPS`], by decoding the entity data the same way
+/// the engine does while keeping track of where every value is stored.
+///
+/// The messages are decoded before the engine handles their frame, so the engine still has the
+/// state the entity data is based on
+struct SpanDecoder {
+    engine: crate::parser::Engine,
+    paths: Paths,
+    /// The classes of the entities created in the current frame, which the engine does not
+    /// know about yet
+    created: std::collections::HashMap<i32, u32>,
+}
+
+impl SpanDecoder {
+    fn new() -> Self {
+        Self {
+            engine: crate::parser::Engine::new(crate::parser::EntityFilter::all()),
+            paths: Paths::new(),
+            created: std::collections::HashMap::new(),
+        }
+    }
+
+    fn handle_frame(&mut self, frame: &crate::Frame<'_>) -> Result<(), AnonymizeError> {
+        self.created.clear();
+        self.engine
+            .handle_frame(frame, &mut Entities)
+            .map_err(AnonymizeError::Parse)
+    }
+
+    fn spans(
+        &mut self,
+        raw: &crate::csgo_proto::CsvcMsgPacketEntities,
+    ) -> Result<Vec<PropSpan>, FirstPassError> {
+        let data_bits = raw.entity_data().len() * 8;
+        let mut bitreader = Bitreader::new(raw.entity_data());
+        let (entity_ctx, prop_controller, qf_mapper) = self.engine.entity_decoding();
+
+        let mut spans = Vec::new();
+        let mut entity_id: i32 = -1;
+        for _ in 0..raw.updated_entries() {
+            entity_id = entity_id.wrapping_add(1 + bitreader.read_u_bit_var()? as i32);
+
+            let cls = match bitreader.read_nbits(2)? {
+                0b01 => continue,
+                0b11 => {
+                    self.created.remove(&entity_id);
+                    continue;
+                }
+                0b10 => {
+                    let cls = bitreader.read_nbits(8)?;
+                    let _serial = bitreader.read_nbits(17)?;
+                    let _unknown = bitreader.read_varint()?;
+                    self.created.insert(entity_id, cls);
+                    cls
+                }
+                0b00 => {
+                    if raw.has_pvs_vis_bits() > 0 && bitreader.read_nbits(2)? & 0x01 == 1 {
+                        continue;
+                    }
+                    match self.created.get(&entity_id) {
+                        Some(cls) => *cls,
+                        None => match entity_ctx.entities.get(&entity_id) {
+                            Some(entity) => entity.cls,
+                            None => return Err(FirstPassError::UnknownEntity(entity_id)),
+                        },
+                    }
+                }
+                unknown => return Err(FirstPassError::InvalidEntityOperation(unknown)),
+            };
+            let class = match entity_ctx.cls_to_class.get(&cls) {
+                Some(c) => c,
+                None => return Err(FirstPassError::UnknownClass(cls)),
+            };
+
+            let n_updates = fieldpath::parse_paths(&mut bitreader, &mut self.paths)?;
+            for path in self.paths.paths().take(n_updates) {
+                let field = path.find(&class.serializer)?;
+                let field_info = field.get_propinfo(path);
+                let decoder = field.get_decoder()?;
+                let start = bitreader.bits_remaining().unwrap_or(0);
+                let value = decoder.decode(&mut bitreader, qf_mapper)?;
+                let end = bitreader.bits_remaining().unwrap_or(0);
+
+                let prop_name = field_info
+                    .and_then(|fi| prop_controller.prop_infos.get(&fi.prop_id))
+                    .map(|prop_info| &prop_info.prop_name)
+                    .filter(|name| ENTITY_PROPS.contains(&name.as_ref()));
+                if let Some(prop_name) = prop_name {
+                    spans.push(PropSpan {
+                        entity_id,
+                        prop_name: prop_name.clone(),
+                        decoder,
+                        value,
+                        bits: data_bits - start..data_bits - end,
+                    });
+                }
+            }
+        }
+
+        Ok(spans)
+    }
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Self {
+            players: Vec::new(),
+            by_xuid: std::collections::HashMap::new(),
+            by_name: std::collections::HashMap::new(),
+
+            event_keys: std::collections::HashMap::new(),
+            string_tables: Vec::new(),
+            entities: SpanDecoder::new(),
+        }
+    }
+
+    /// All the players encountered so far and their pseudonyms
+    pub fn mappings(&self) -> &[PlayerMapping] {
+        &self.players
+    }
+
+    /// Writes the mappings as tab separated values, with the columns `name`, `xuid`,
+    /// `pseudonym` and `pseudonym_xuid`
+    pub fn write_report<W>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        writeln!(writer, "name\txuid\tpseudonym\tpseudonym_xuid")?;
+        for player in self.players.iter() {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                player.name.as_deref().unwrap_or(""),
+                player.xuid.map(|x| x.to_string()).unwrap_or_default(),
+                player.pseudonym,
+                player.pseudonym_xuid
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes an anonymized copy of the demo
+    pub fn anonymize<W>(
+        &mut self,
+        container: &Container<'_>,
+        mut writer: DemoWriter<W>,
+    ) -> Result<W, AnonymizeError>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        self.event_keys.clear();
+        self.string_tables.clear();
+
+        self.entities = SpanDecoder::new();
+
+        let mut buffer = Vec::new();
+        for frame in FallibleFrameIterator::parse(container.inner) {
+            let (_, frame) = frame.map_err(AnonymizeError::Frame)?;
+
+            let rewritten = match frame.cmd {
+                DemoCommand::SignonPacket | DemoCommand::Packet => {
+                    let data = frame
+                        .decompress_with_buf(&mut buffer)
+                        .map_err(AnonymizeError::DecompressFrame)?;
+                    let mut raw: crate::csgo_proto::CDemoPacket = prost::Message::decode(data)?;
+
+                    match self.rewrite_packet(raw.data())? {
+                        Some(data) => {
+                            raw.data = Some(data);
+                            Some(prost::Message::encode_to_vec(&raw))
+                        }
+                        None => None,
+                    }
+                }
+                DemoCommand::FullPacket => {
+                    let data = frame
+                        .decompress_with_buf(&mut buffer)
+                        .map_err(AnonymizeError::DecompressFrame)?;
+                    let mut raw: crate::csgo_proto::CDemoFullPacket = prost::Message::decode(data)?;
+
+                    let mut changed = false;
+                    if let Some(tables) = raw.string_table.as_mut() {
+                        changed |= self.rewrite_string_tables(tables)?;
+                    }
+                    if let Some(packet) = raw.packet.as_mut() {
+                        if let Some(data) = self.rewrite_packet(packet.data())? {
+                            packet.data = Some(data);
+                            changed = true;
+                        }
+                    }
+
+                    changed.then(|| prost::Message::encode_to_vec(&raw))
+                }
+                DemoCommand::StringTables => {
+                    let data = frame
+                        .decompress_with_buf(&mut buffer)
+                        .map_err(AnonymizeError::DecompressFrame)?;
+                    let mut raw: crate::csgo_proto::CDemoStringTables =
+                        prost::Message::decode(data)?;
+
+                    self.rewrite_string_tables(&mut raw)?
+                        .then(|| prost::Message::encode_to_vec(&raw))
+                }
+                _ => None,
+            };
+            self.entities.handle_frame(&frame)?;
+
+            match rewritten {
+                Some(data) => writer.write_data(frame.cmd, frame.tick, &data)?,
+                None => writer.write_frame(&frame)?,
+            };
+        }
+
+        writer.finish().map_err(AnonymizeError::Io)
+    }
+
+    /// Returns the index of the player with the given name and/or xuid, adding them if they
+    /// were not seen before
+    fn player(&mut self, name: Option<&str>, xuid: Option<u64>) -> usize {
+        let name = name.filter(|n| !n.is_empty());
+        let xuid = xuid.filter(|x| *x != 0);
+
+        let existing = xuid
+            .and_then(|x| self.by_xuid.get(&x))
+            .or_else(|| name.and_then(|n| self.by_name.get(n)))
+            .copied();
+
+        let idx = match existing {
+            Some(idx) => idx,
+            None => {
+                let idx = self.players.len();
+                self.players.push(PlayerMapping {
+                    name: None,
+                    xuid: None,
+                    pseudonym: format!("Player {}", idx + 1),
+                    pseudonym_xuid: STEAMID64_BASE + idx as u64 + 1,
+                });
+                idx
+            }
+        };
+
+        let player = &mut self.players[idx];
+        if let Some(name) = name {
+            player.name.get_or_insert_with(|| name.to_owned());
+            self.by_name.insert(name.to_owned(), idx);
+        }
+        if let Some(xuid) = xuid {
+            player.xuid.get_or_insert(xuid);
+            self.by_xuid.insert(xuid, idx);
+        }
+
+        idx
+    }
+
+    fn pseudonym_xuid(&self, idx: usize, xuid: u64) -> u64 {
+        // Keep the 0 xuid of bots, as it does not identify anyone
+        if xuid == 0 {
+            return 0;
+        }
+        self.players[idx].pseudonym_xuid
+    }
+
+    fn rewrite_packet(&mut self, data: &[u8]) -> Result<Option<Vec<u8>>, AnonymizeError> {
+        let mut bitreader = Bitreader::new(data);
+        let mut writer = Bitwriter::new();
+        let mut changed = false;
+
+        while bitreader.bits_remaining().unwrap_or(0) > 8 {
+            let msg_type = bitreader.read_u_bit_var()?;
+            let size = bitreader.read_varint()?;
+            let msg_bytes = bitreader.read_n_bytes(size as usize)?;
+
+            let rewritten = match NetmessageType::try_from(msg_type as i32) {
+                Ok(net_msg_type) => self.rewrite_message(net_msg_type, &msg_bytes)?,
+                Err(_) => None,
+            };
+            changed |= rewritten.is_some();
+            let msg_bytes = rewritten.unwrap_or(msg_bytes);
+
+            writer.write_u_bit_var(msg_type);
+            writer.write_varint(msg_bytes.len() as u32);
+            writer.write_bytes(&msg_bytes);
+        }
+
+        Ok(changed.then(|| writer.into_bytes()))
+    }
+
+    /// Returns the rewritten message, or `None` if the message did not need to be changed
+    fn rewrite_message(
+        &mut self,
+        msg_type: NetmessageType,
+        msg_bytes: &[u8],
+    ) -> Result<Option<Vec<u8>>, AnonymizeError> {
+        match msg_type {
+            NetmessageType::GE_Source1LegacyGameEventList => {
+                let raw: crate::csgo_proto::CsvcMsgGameEventList =
+                    prost::Message::decode(msg_bytes)?;
+
+                self.event_keys.clear();
+                for event in raw.descriptors {
+                    self.event_keys.insert(
+                        event.eventid(),
+                        event.keys.iter().map(|k| k.name().to_owned()).collect(),
+                    );
+                }
+
+                Ok(None)
+            }
+            NetmessageType::GE_Source1LegacyGameEvent => {
+                let mut raw: crate::csgo_proto::CMsgSource1LegacyGameEvent =
+                    prost::Message::decode(msg_bytes)?;
+
+                Ok(self
+                    .rewrite_game_event(&mut raw)
+                    .then(|| prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::UM_SayText2 => {
+                let mut raw: crate::csgo_proto::CUserMessageSayText2 =
+                    prost::Message::decode(msg_bytes)?;
+
+                // The first parameter is the name of the player and the second one the message
+                if let Some(name) = raw.param1.as_mut() {
+                    let idx = self.player(Some(name), None);
+                    *name = self.players[idx].pseudonym.clone();
+                }
+                raw.param2 = raw.param2.as_ref().map(|_| String::new());
+
+                Ok(Some(prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::UM_TextMsg => {
+                let mut raw: crate::csgo_proto::CUserMessageTextMsg =
+                    prost::Message::decode(msg_bytes)?;
+
+                let mut changed = false;
+                for param in raw.param.iter_mut() {
+                    if let Some(idx) = self.by_name.get(param.as_str()) {
+                        *param = self.players[*idx].pseudonym.clone();
+                        changed = true;
+                    }
+                }
+
+                Ok(changed.then(|| prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::CS_UM_EndOfMatchAllPlayersData => {
+                let mut raw: crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData =
+                    prost::Message::decode(msg_bytes)?;
+
+                for data in raw.allplayerdata.iter_mut() {
+                    let idx = self.player(data.name.as_deref(), data.xuid);
+                    if data.name.is_some() {
+                        data.name = Some(self.players[idx].pseudonym.clone());
+                    }
+                    if let Some(xuid) = data.xuid {
+                        data.xuid = Some(self.pseudonym_xuid(idx, xuid));
+                    }
+                }
+
+                Ok(Some(prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::CS_UM_ServerRankUpdate => {
+                let mut raw: crate::csgo_proto::CcsUsrMsgServerRankUpdate =
+                    prost::Message::decode(msg_bytes)?;
+
+                // The account id is the lower part of the xuid
+                for update in raw.rank_update.iter_mut() {
+                    if let Some(account_id) = update.account_id {
+                        let xuid = STEAMID64_BASE + account_id as u32 as u64;
+                        let idx = self.player(None, Some(xuid));
+                        update.account_id =
+                            Some((self.pseudonym_xuid(idx, xuid) - STEAMID64_BASE) as i32);
+                    }
+                }
+
+                Ok(Some(prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::svc_PacketEntities => {
+                let mut raw: crate::csgo_proto::CsvcMsgPacketEntities =
+                    prost::Message::decode(msg_bytes)?;
+
+                let spans = self
+                    .entities
+                    .spans(&raw)
+                    .map_err(AnonymizeError::EntityProps)?;
+                if spans.is_empty() {
+                    return Ok(None);
+                }
+                raw.entity_data = Some(self.rewrite_entity_data(raw.entity_data(), &spans)?);
+
+                Ok(Some(prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::svc_ClearAllStringTables => {
+                self.string_tables.clear();
+                Ok(None)
+            }
+            NetmessageType::svc_CreateStringTable => {
+                let mut raw: crate::csgo_proto::CsvcMsgCreateStringTable =
+                    prost::Message::decode(msg_bytes)?;
+
+                let info = StringTableInfo::from_create(&raw);
+                let is_userinfo = info.name == "userinfo";
+                self.string_tables.push(info);
+
+                if !is_userinfo {
+                    return Ok(None);
+                }
+
+                let data = StringTableInfo::create_data(&raw)
+                    .map_err(|_| AnonymizeError::Bitreader(BitReadError::MalformedMessage))?;
+                let data = match self.rewrite_userinfo_update(
+                    self.string_tables.len() - 1,
+                    &data,
+                    raw.num_entries() as usize,
+                )? {
+                    Some(d) => d,
+                    None => return Ok(None),
+                };
+
+                raw.uncompressed_size = Some(data.len() as i32);
+                raw.data_compressed = Some(false);
+                raw.string_data = Some(data);
+
+                Ok(Some(prost::Message::encode_to_vec(&raw)))
+            }
+            NetmessageType::svc_UpdateStringTable => {
+                let mut raw: crate::csgo_proto::CsvcMsgUpdateStringTable =
+                    prost::Message::decode(msg_bytes)?;
+
+                let table_idx = raw.table_id() as usize;
+                if self.string_tables.get(table_idx).map(|t| t.name.as_str()) != Some("userinfo") {
+                    return Ok(None);
+                }
+
+                match self.rewrite_userinfo_update(
+                    table_idx,
+                    raw.string_data(),
+                    raw.num_changed_entries() as usize,
+                )? {
+                    Some(data) => {
+                        raw.string_data = Some(data);
+                        Ok(Some(prost::Message::encode_to_vec(&raw)))
+                    }
+                    None => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn rewrite_game_event(
+        &mut self,
+        raw: &mut crate::csgo_proto::CMsgSource1LegacyGameEvent,
+    ) -> bool {
+        let names = match self.event_keys.get(&raw.eventid()) {
+            Some(names) => names,
+            None => return false,
+        };
+
+        let value_of = |key: &str| {
+            names
+                .iter()
+                .position(|n| n == key)
+                .and_then(|i| raw.keys.get(i))
+        };
+        let name = value_of("name").and_then(|k| k.val_string.clone());
+        let xuid = value_of("xuid").and_then(|k| k.val_uint64);
+        if name.is_none() && xuid.is_none() {
+            return false;
+        }
+
+        let names = names.clone();
+        let idx = self.player(name.as_deref(), xuid);
+        let pseudonym = self.players[idx].pseudonym.clone();
+        let pseudonym_xuid = self.pseudonym_xuid(idx, xuid.unwrap_or(0));
+
+        for (key_name, key) in names.iter().zip(raw.keys.iter_mut()) {
+            match key_name.as_str() {
+                "name" => key.val_string = Some(pseudonym.clone()),
+                "xuid" => key.val_uint64 = Some(pseudonym_xuid),
+                // Bots use "BOT" as their network id, which is kept
+                "networkid" if key.val_string.as_deref() != Some("BOT") => {
+                    key.val_string = Some(format!(
+                        "[U:1:{}]",
+                        pseudonym_xuid.saturating_sub(STEAMID64_BASE)
+                    ));
+                }
+                "address" => key.val_string = Some(String::new()),
+                _ => {}
+            };
+        }
+
+        true
+    }
+
+    fn rewrite_userinfo_update(
+        &mut self,
+        table_idx: usize,
+        data: &[u8],
+        n_entries: usize,
+    ) -> Result<Option<Vec<u8>>, AnonymizeError> {
+        let info = self.string_tables[table_idx].clone();
+        let mut entries = info.parse_entries(data, n_entries)?;
+
+        let mut changed = false;
+        for entry in entries.iter_mut() {
+            if let Some(value) = entry.value.as_mut() {
+                changed |= self.rewrite_player_info(value)?;
+            }
+        }
+
+        Ok(changed.then(|| info.write_entries(&entries)))
+    }
+
+    /// Replaces the values of the props at the spans, while copying all the other bits as they
+    /// are
+    fn rewrite_entity_data(
+        &mut self,
+        data: &[u8],
+        spans: &[PropSpan],
+    ) -> Result<Vec<u8>, AnonymizeError> {
+        let mut bitreader = Bitreader::new(data);
+        let mut writer = Bitwriter::new();
+        let mut position = 0;
+
+        for entity in spans.chunk_by(|a, b| a.entity_id == b.entity_id) {
+            let prop = |name: &str| {
+                entity
+                    .iter()
+                    .find(|span| span.prop_name.as_ref() == name)
+                    .map(|span| &span.value)
+            };
+            let name = match prop(PLAYER_NAME).or(prop(SANITIZED_PLAYER_NAME)) {
+                Some(Variant::String(name)) => Some(name.clone()),
+                _ => None,
+            };
+            let xuid = match prop(STEAM_ID) {
+                Some(Variant::U64(xuid)) => Some(*xuid),
+                _ => None,
+            };
+            let player = (name.as_ref().is_some_and(|n| !n.is_empty())
+                || xuid.is_some_and(|x| x != 0))
+            .then(|| self.player(name.as_deref(), xuid));
+
+            for span in entity {
+                let value = match (span.prop_name.as_ref(), &span.value, player) {
+                    (PLAYER_NAME | SANITIZED_PLAYER_NAME, _, Some(idx)) => {
+                        Variant::String(self.players[idx].pseudonym.clone())
+                    }
+                    (STEAM_ID, Variant::U64(xuid), Some(idx)) => {
+                        Variant::U64(self.pseudonym_xuid(idx, *xuid))
+                    }
+                    (CLAN_TAG | CLAN_TEAM_NAME, _, _) => Variant::String(String::new()),
+                    _ => continue,
+                };
+                if span.bits.start < position {
+                    return Err(AnonymizeError::Bitreader(BitReadError::MalformedMessage));
+                }
+
+                let mut encoded = Bitwriter::new();
+                match (span.decoder, &value) {
+                    (Decoder::StringDecoder, Variant::String(value)) => encoded.write_string(value),
+                    (Decoder::Unsigned64Decoder, Variant::U64(value)) => {
+                        encoded.write_varint_u_64(*value)
+                    }
+                    (Decoder::Fixed64Decoder, Variant::U64(value)) => {
+                        encoded.write_bytes(&value.to_ne_bytes())
+                    }
+                    _ => continue,
+                };
+
+                copy_bits(
+                    &mut bitreader,
+                    Some(&mut writer),
+                    span.bits.start - position,
+                )?;
+                copy_bits(&mut bitreader, None, span.bits.len())?;
+                writer.write_bytes(&encoded.into_bytes());
+                position = span.bits.end;
+            }
+        }
+
+        let remaining = (data.len() * 8)
+            .checked_sub(position)
+            .ok_or(BitReadError::MalformedMessage)?;
+        copy_bits(&mut bitreader, Some(&mut writer), remaining)?;
+
+        Ok(writer.into_bytes())
+    }
+
+    fn rewrite_string_tables(
+        &mut self,
+        tables: &mut crate::csgo_proto::CDemoStringTables,
+    ) -> Result<bool, AnonymizeError> {
+        let mut changed = false;
+        for table in tables.tables.iter_mut() {
+            if table.table_name() != "userinfo" {
+                continue;
+            }
+
+            for item in table.items.iter_mut() {
+                if let Some(data) = item.data.as_mut() {
+                    changed |= self.rewrite_player_info(data)?;
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Rewrites the encoded `CMsgPlayerInfo` stored in the userinfo string table
+    fn rewrite_player_info(&mut self, data: &mut Vec<u8>) -> Result<bool, AnonymizeError> {
+        if data.is_empty() {
+            return Ok(false);
+        }
+
+        let mut raw: crate::csgo_proto::CMsgPlayerInfo = prost::Message::decode(data.as_slice())?;
+
+        let idx = self.player(raw.name.as_deref(), raw.xuid.or(raw.steamid));
+        if raw.name.is_some() {
+            raw.name = Some(self.players[idx].pseudonym.clone());
+        }
+        if let Some(xuid) = raw.xuid {
+            raw.xuid = Some(self.pseudonym_xuid(idx, xuid));
+        }
+        if let Some(steamid) = raw.steamid {
+            raw.steamid = Some(self.pseudonym_xuid(idx, steamid));
+        }
+
+        *data = prost::Message::encode_to_vec(&raw);
+        Ok(true)
+    }
+}
+
+/// Copies the next bits of the reader to the writer, or skips them without a writer
+fn copy_bits(
+    bitreader: &mut Bitreader,
+    mut writer: Option<&mut Bitwriter>,
+    mut n: usize,
+) -> Result<(), BitReadError> {
+    while n > 0 {
+        let chunk = n.min(32) as u32;
+        let bits = bitreader.read_nbits(chunk)?;
+        if let Some(writer) = writer.as_mut() {
+            writer.write_nbits(bits, chunk);
+        }
+        n -= chunk as usize;
+    }
+    Ok(())
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_pseudonyms() {
+        let mut anonymizer = Anonymizer::new();
+
+        let first = anonymizer.player(Some("alice"), None);
+        let second = anonymizer.player(Some("bob"), Some(STEAMID64_BASE + 123));
+        assert_ne!(first, second);
+
+        assert_eq!(
+            first,
+            anonymizer.player(Some("alice"), Some(STEAMID64_BASE + 5))
+        );
+        assert_eq!(first, anonymizer.player(None, Some(STEAMID64_BASE + 5)));
+        assert_eq!(
+            second,
+            anonymizer.player(Some("bobby"), Some(STEAMID64_BASE + 123))
+        );
+
+        assert_eq!(
+            &[
+                PlayerMapping {
+                    name: Some("alice".to_owned()),
+                    xuid: Some(STEAMID64_BASE + 5),
+                    pseudonym: "Player 1".to_owned(),
+                    pseudonym_xuid: STEAMID64_BASE + 1,
+                },
+                PlayerMapping {
+                    name: Some("bob".to_owned()),
+                    xuid: Some(STEAMID64_BASE + 123),
+                    pseudonym: "Player 2".to_owned(),
+                    pseudonym_xuid: STEAMID64_BASE + 2,
+                },
+            ],
+            anonymizer.mappings()
+        );
+    }
+
+    #[test]
+    fn rewrite_entity_data() {
+        let mut anonymizer = Anonymizer::new();
+
+        let mut writer = Bitwriter::new();
+        writer.write_nbits(0b101, 3);
+        writer.write_string("alice");
+        writer.write_nbits(0b11011, 5);
+        writer.write_varint_u_64(STEAMID64_BASE + 5);
+        writer.write_string("clan");
+        writer.write_nbits(0b10, 2);
+        let data = writer.into_bytes();
+
+        let span = |prop_name: &str, decoder, value, bits| PropSpan {
+            entity_id: 1,
+            prop_name: prop_name.into(),
+            decoder,
+            value,
+            bits,
+        };
+        let spans = [
+            span(
+                PLAYER_NAME,
+                Decoder::StringDecoder,
+                Variant::String("alice".to_owned()),
+                3..51,
+            ),
+            span(
+                STEAM_ID,
+                Decoder::Unsigned64Decoder,
+                Variant::U64(STEAMID64_BASE + 5),
+                56..128,
+            ),
+            span(
+                CLAN_TAG,
+                Decoder::StringDecoder,
+                Variant::String("clan".to_owned()),
+                128..168,
+            ),
+        ];
+
+        let rewritten = anonymizer.rewrite_entity_data(&data, &spans).unwrap();
+
+        let mut bitreader = Bitreader::new(&rewritten);
+        assert_eq!(0b101, bitreader.read_nbits(3).unwrap());
+        assert_eq!("Player 1", bitreader.read_string().unwrap());
+        assert_eq!(0b11011, bitreader.read_nbits(5).unwrap());
+        assert_eq!(STEAMID64_BASE + 1, bitreader.read_varint_u_64().unwrap());
+        assert_eq!("", bitreader.read_string().unwrap());
+        assert_eq!(0b10, bitreader.read_nbits(2).unwrap());
+
+        assert_eq!(Some(STEAMID64_BASE + 5), anonymizer.mappings()[0].xuid);
+    }
+
+    #[test]
+    fn rewrite_packet() {
+        let mut anonymizer = Anonymizer::new();
+
+        let player_data = crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData {
+            allplayerdata: vec![
+                crate::csgo_proto::ccs_usr_msg_end_of_match_all_players_data::PlayerData {
+                    name: Some("alice".to_owned()),
+                    xuid: Some(STEAMID64_BASE + 5),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let chat = crate::csgo_proto::CUserMessageSayText2 {
+            param1: Some("alice".to_owned()),
+            param2: Some("my secret".to_owned()),
+            ..Default::default()
+        };
+        let tick = crate::csgo_proto::CnetMsgTick {
+            tick: Some(10),
+            ..Default::default()
+        };
+
+        let mut writer = Bitwriter::new();
+        for (msg_type, bytes) in [
+            (375, prost::Message::encode_to_vec(&player_data)),
+            (118, prost::Message::encode_to_vec(&chat)),
+            (4, prost::Message::encode_to_vec(&tick)),
+        ] {
+            writer.write_u_bit_var(msg_type);
+            writer.write_varint(bytes.len() as u32);
+            writer.write_bytes(&bytes);
+        }
+        let packet = writer.into_bytes();
+
+        let rewritten = anonymizer.rewrite_packet(&packet).unwrap().unwrap();
+
+        let mut bitreader = Bitreader::new(&rewritten);
+        let mut messages = Vec::new();
+        while bitreader.bits_remaining().unwrap_or(0) > 8 {
+            let msg_type = bitreader.read_u_bit_var().unwrap();
+            let size = bitreader.read_varint().unwrap();
+            messages.push((msg_type, bitreader.read_n_bytes(size as usize).unwrap()));
+        }
+        assert_eq!(3, messages.len());
+
+        let player_data: crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData =
+            prost::Message::decode(messages[0].1.as_slice()).unwrap();
+        assert_eq!("Player 1", player_data.allplayerdata[0].name());
+        assert_eq!(STEAMID64_BASE + 1, player_data.allplayerdata[0].xuid());
+
+        let chat: crate::csgo_proto::CUserMessageSayText2 =
+            prost::Message::decode(messages[1].1.as_slice()).unwrap();
+        assert_eq!("Player 1", chat.param1());
+        assert_eq!("", chat.param2());
+
+        assert_eq!((4, prost::Message::encode_to_vec(&tick)), messages[2]);
+    }
+}
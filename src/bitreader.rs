@@ -12,8 +12,22 @@ pub struct Bitreader<'a> {
 pub enum BitReadError {
     FailedByteRead(String),
     MalformedMessage,
+    /// Tried to read more bits than are left in the data
+    NotEnoughBits,
 }
 
+impl std::fmt::Display for BitReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FailedByteRead(reason) => write!(f, "failed to read bytes: {}", reason),
+            Self::MalformedMessage => write!(f, "malformed message"),
+            Self::NotEnoughBits => write!(f, "not enough bits"),
+        }
+    }
+}
+
+impl std::error::Error for BitReadError {}
+
 impl<'a> Bitreader<'a> {
     pub fn new(bytes: &'a [u8]) -> Bitreader<'a> {
         let b = Bitreader {
@@ -51,6 +65,9 @@ impl<'a> Bitreader<'a> {
     pub fn read_nbits(&mut self, n: u32) -> Result<u32, BitReadError> {
         if self.bits_left < n {
             self.refill();
+            if self.bits_left < n {
+                return Err(BitReadError::NotEnoughBits);
+            }
         }
         let b = self.peek(n);
         self.consume(n);
@@ -100,6 +117,9 @@ impl<'a> Bitreader<'a> {
         let mut b: u32;
         let mut s = 0;
         loop {
+            if count > 9 {
+                return Err(BitReadError::MalformedMessage);
+            }
             b = self.read_nbits(8)?;
             if b < 0x80 {
                 if count > 9 || count == 9 && b > 1 {
@@ -121,6 +141,10 @@ impl<'a> Bitreader<'a> {
         Ok(self.read_nbits(1)? != 0)
     }
     pub fn read_n_bytes(&mut self, n: usize) -> Result<Vec<u8>, BitReadError> {
+        // The length usually comes from the data itself, so it is checked before allocating
+        if n > self.bits_remaining().unwrap_or(0) / 8 {
+            return Err(BitReadError::NotEnoughBits);
+        }
         let mut bytes = vec![0_u8; n];
         match self.reader.read_bytes(&mut bytes) {
             true => {
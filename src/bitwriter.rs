@@ -0,0 +1,140 @@
+/// The counterpart to the [`Bitreader`](crate::bitreader::Bitreader), used for creating bit
+/// packed data that the Bitreader can read again
+pub struct Bitwriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl Bitwriter {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            bit_len: 0,
+        }
+    }
+
+    pub fn write_nbits(&mut self, value: u32, n: u32) {
+        for i in 0..n {
+            let bit = (value >> i) & 1;
+            let shift = self.bit_len % 8;
+            if shift == 0 {
+                self.bytes.push(0);
+            }
+            if bit == 1 {
+                *self
+                    .bytes
+                    .last_mut()
+                    .expect("We just pushed a byte if needed") |= 1 << shift;
+            }
+            self.bit_len += 1;
+        }
+    }
+
+    pub fn write_boolean(&mut self, value: bool) {
+        self.write_nbits(value as u32, 1);
+    }
+
+    pub fn write_u_bit_var(&mut self, value: u32) {
+        match value >> 4 {
+            0 => self.write_nbits(value, 6),
+            upper if upper < (1 << 4) => {
+                self.write_nbits((value & 0b1111) | 0b010000, 6);
+                self.write_nbits(upper, 4);
+            }
+            upper if upper < (1 << 8) => {
+                self.write_nbits((value & 0b1111) | 0b100000, 6);
+                self.write_nbits(upper, 8);
+            }
+            upper => {
+                self.write_nbits((value & 0b1111) | 0b110000, 6);
+                self.write_nbits(upper, 28);
+            }
+        };
+    }
+
+    pub fn write_varint(&mut self, mut value: u32) {
+        loop {
+            let b = value & 127;
+            value >>= 7;
+            if value == 0 {
+                self.write_nbits(b, 8);
+                return;
+            }
+            self.write_nbits(b | 0x80, 8);
+        }
+    }
+
+    pub fn write_varint_u_64(&mut self, mut value: u64) {
+        loop {
+            let b = (value & 127) as u32;
+            value >>= 7;
+            if value == 0 {
+                self.write_nbits(b, 8);
+                return;
+            }
+            self.write_nbits(b | 0x80, 8);
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        // Byte aligned data can just be copied over
+        if self.bit_len == self.bytes.len() * 8 {
+            self.bytes.extend_from_slice(bytes);
+            self.bit_len += bytes.len() * 8;
+            return;
+        }
+
+        for b in bytes {
+            self.write_nbits(*b as u32, 8);
+        }
+    }
+
+    /// Writes the string followed by a null byte
+    pub fn write_string(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+        self.write_nbits(0, 8);
+    }
+
+    /// Returns the written data, padded with 0 bits to the next full byte
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut writer = Bitwriter::new();
+        writer.write_boolean(true);
+        writer.write_nbits(0b101, 3);
+        writer.write_u_bit_var(7);
+        writer.write_u_bit_var(200);
+        writer.write_u_bit_var(3000);
+        writer.write_u_bit_var(1 << 20);
+        writer.write_varint(300);
+        writer.write_varint_u_64(76561197960265728);
+        writer.write_bytes(&[0xde, 0xad]);
+        writer.write_string("test");
+        let bytes = writer.into_bytes();
+
+        let mut reader = crate::bitreader::Bitreader::new(&bytes);
+        assert!(reader.read_boolean().unwrap());
+        assert_eq!(0b101, reader.read_nbits(3).unwrap());
+        assert_eq!(7, reader.read_u_bit_var().unwrap());
+        assert_eq!(200, reader.read_u_bit_var().unwrap());
+        assert_eq!(3000, reader.read_u_bit_var().unwrap());
+        assert_eq!(1 << 20, reader.read_u_bit_var().unwrap());
+        assert_eq!(300, reader.read_varint().unwrap());
+        assert_eq!(76561197960265728, reader.read_varint_u_64().unwrap());
+        assert_eq!(vec![0xde, 0xad], reader.read_n_bytes(2).unwrap());
+        assert_eq!(
+            b"test\0".to_vec(),
+            (0..5)
+                .map(|_| reader.read_nbits(8).unwrap() as u8)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Reading GOTV+ broadcasts, which deliver a demo as a series of fragments instead of a single
+//! file.
+//!
+//! A broadcast consists of a `start` fragment, containing the signon data, followed by a `full`
+//! fragment with a snapshot of the game and then `delta` fragments for all the following ticks.
+//! Every fragment contains frames, encoded in the same way as the frames following the header of
+//! a normal demo, so the frames can be passed to the same parsers.
+
+use crate::{Frame, FrameParseError, FrameReader};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FragmentKind {
+    Start,
+    Full,
+    Delta,
+}
+
+impl FragmentKind {
+    /// The name of the fragment, as used in the URL of a broadcast (`/<fragment>/<name>`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Full => "full",
+            Self::Delta => "delta",
+        }
+    }
+}
+
+/// A source of broadcast fragments
+pub trait FragmentSource {
+    /// Returns the data of the fragment, or `None` if the fragment does not exist (yet)
+    fn fragment(&mut self, number: u32, kind: FragmentKind) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+/// Any closure can be used as a source, which makes it easy to plug in a HTTP client or some
+/// in-memory stand-in
+impl<F> FragmentSource for F
+where
+    F: FnMut(u32, FragmentKind) -> std::io::Result<Option<Vec<u8>>>,
+{
+    fn fragment(&mut self, number: u32, kind: FragmentKind) -> std::io::Result<Option<Vec<u8>>> {
+        self(number, kind)
+    }
+}
+
+/// Loads fragments saved in a directory, using the same layout as the URLs of a broadcast, so the
+/// fragment `5/delta` is loaded from `<root>/5/delta`
+pub struct DirectorySource {
+    root: std::path::PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new<P>(root: P) -> Self
+    where
+        P: Into<std::path::PathBuf>,
+    {
+        Self { root: root.into() }
+    }
+}
+
+impl FragmentSource for DirectorySource {
+    fn fragment(&mut self, number: u32, kind: FragmentKind) -> std::io::Result<Option<Vec<u8>>> {
+        let path = self.root.join(number.to_string()).join(kind.name());
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BroadcastError {
+    Io(std::io::Error),
+    /// The `start` or `full` fragment is missing, without which the broadcast can not be parsed
+    MissingFragment {
+        number: u32,
+        kind: FragmentKind,
+    },
+    Frame(FrameParseError),
+}
+
+/// Turns the fragments of a broadcast into a stream of frames
+pub struct Broadcast<S> {
+    source: S,
+    signup_fragment: u32,
+    next: (u32, FragmentKind),
+    current: Option<FrameReader<std::io::Cursor<Vec<u8>>>>,
+}
+
+impl<S> Broadcast<S>
+where
+    S: FragmentSource,
+{
+    /// Creates a new broadcast, the fragment numbers are the `signup_fragment` and `fragment`
+    /// fields returned by the `/sync` endpoint of a broadcast
+    pub fn new(source: S, signup_fragment: u32, fragment: u32) -> Self {
+        Self {
+            source,
+            signup_fragment,
+            next: (fragment, FragmentKind::Start),
+            current: None,
+        }
+    }
+
+    /// The number of the next fragment that will be loaded
+    pub fn next_fragment(&self) -> u32 {
+        match self.next {
+            (_, FragmentKind::Start) => self.signup_fragment,
+            (number, _) => number,
+        }
+    }
+
+    /// Returns the next frame of the broadcast.
+    ///
+    /// Returns `Ok(None)` once the next delta fragment is not available, for live broadcasts
+    /// calling this again later will continue with that fragment once it is available
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'static>>, BroadcastError> {
+        loop {
+            if let Some(reader) = self.current.as_mut() {
+                if let Some(frame) = reader.next_frame().map_err(BroadcastError::Frame)? {
+                    return Ok(Some(frame));
+                }
+                self.current = None;
+            }
+
+            let (number, kind) = (self.next_fragment(), self.next.1);
+            let data = match self
+                .source
+                .fragment(number, kind)
+                .map_err(BroadcastError::Io)?
+            {
+                Some(d) => d,
+                None if kind == FragmentKind::Delta => return Ok(None),
+                None => return Err(BroadcastError::MissingFragment { number, kind }),
+            };
+
+            self.next = match self.next {
+                (number, FragmentKind::Start) => (number, FragmentKind::Full),
+                (number, FragmentKind::Full) => (number, FragmentKind::Delta),
+                (number, FragmentKind::Delta) => (number + 1, FragmentKind::Delta),
+            };
+            self.current = Some(FrameReader::new(std::io::Cursor::new(data)));
+        }
+    }
+}
+
+impl<S> Iterator for Broadcast<S>
+where
+    S: FragmentSource,
+{
+    type Item = Result<Frame<'static>, BroadcastError>;
+
+    /// See [`Broadcast::next_frame`], the iterator ends once the next delta fragment is not
+    /// available
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragments() -> std::collections::HashMap<(u32, FragmentKind), Vec<u8>> {
+        [
+            // A FileHeader in the start fragment
+            ((1, FragmentKind::Start), vec![1, 0, 1, 0xaa]),
+            // A FullPacket in the full fragment
+            ((5, FragmentKind::Full), vec![13, 10, 0]),
+            // Packets in the delta fragments
+            ((5, FragmentKind::Delta), vec![7, 11, 0, 7, 12, 0]),
+            ((6, FragmentKind::Delta), vec![7, 13, 0]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn frames() {
+        let fragments = fragments();
+        let source = |number: u32, kind: FragmentKind| -> std::io::Result<_> {
+            Ok(fragments.get(&(number, kind)).cloned())
+        };
+
+        let mut broadcast = Broadcast::new(source, 1, 5);
+        assert_eq!(1, broadcast.next_fragment());
+
+        let frames: Vec<_> = broadcast
+            .by_ref()
+            .map(|f| f.map(|f| (f.cmd, f.tick)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (crate::DemoCommand::FileHeader, 0),
+                (crate::DemoCommand::FullPacket, 10),
+                (crate::DemoCommand::Packet, 11),
+                (crate::DemoCommand::Packet, 12),
+                (crate::DemoCommand::Packet, 13),
+            ],
+            frames
+        );
+        assert_eq!(7, broadcast.next_fragment());
+    }
+
+    #[test]
+    fn live() {
+        let mut fragments = fragments();
+        fragments.remove(&(6, FragmentKind::Delta));
+        let fragments = std::cell::RefCell::new(fragments);
+        let source = |number: u32, kind: FragmentKind| -> std::io::Result<_> {
+            Ok(fragments.borrow().get(&(number, kind)).cloned())
+        };
+
+        let mut broadcast = Broadcast::new(source, 1, 5);
+        assert_eq!(4, broadcast.by_ref().count());
+        assert!(broadcast.next_frame().unwrap().is_none());
+
+        // Once the next fragment is available, we continue with it
+        fragments
+            .borrow_mut()
+            .insert((6, FragmentKind::Delta), vec![7, 13, 0]);
+        assert_eq!(13, broadcast.next_frame().unwrap().unwrap().tick);
+    }
+
+    #[test]
+    fn missing_full() {
+        let mut fragments = fragments();
+        fragments.remove(&(5, FragmentKind::Full));
+        let source = |number: u32, kind: FragmentKind| -> std::io::Result<_> {
+            Ok(fragments.get(&(number, kind)).cloned())
+        };
+
+        let mut broadcast = Broadcast::new(source, 1, 5);
+        assert!(broadcast.next_frame().unwrap().is_some());
+        assert!(matches!(
+            broadcast.next_frame(),
+            Err(BroadcastError::MissingFragment {
+                number: 5,
+                kind: FragmentKind::Full
+            })
+        ));
+    }
+
+    #[test]
+    fn directory() {
+        let root = std::env::temp_dir().join(format!("csdemo-broadcast-{}", std::process::id()));
+        for ((number, kind), data) in fragments() {
+            let dir = root.join(number.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(kind.name()), data).unwrap();
+        }
+
+        let broadcast = Broadcast::new(DirectorySource::new(&root), 1, 5);
+        let count = broadcast.count();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(5, count);
+    }
+}
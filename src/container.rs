@@ -1,21 +1,56 @@
 #[derive(Debug)]
 pub enum ParseContainerError {
     MissingHeader,
-    InvalidMagic(core::str::Utf8Error),
+    InvalidMagic([u8; 8]),
     MismatchedLength {
         buffer_len: usize,
         expected_len: usize,
     },
     Other(&'static str),
+    Io(std::io::Error),
 }
 
+/// The magic every cs2 demo starts with
+pub const MAGIC: &[u8; 8] = b"PBDEMS2\0";
+
+/// The size of the header at the start of every demo
+pub(crate) const HEADER_SIZE: usize = 16;
+
 /// A Container models the outer layer of a CS2 demo, which starts with a specific magic string and
 /// some other values. Then it just stores the raw bytes afterwards, that contain the actual demo
 /// data
 #[derive(Debug)]
 pub struct Container<'b> {
     pub magic: &'b str,
+    /// The offset of the FileInfo frame from the start of the demo
+    pub file_info_offset: u32,
+    /// The offset of the SpawnGroups frame from the start of the demo
+    pub spawngroups_offset: u32,
     pub inner: &'b [u8],
+    /// Only set for containers parsed using [`Container::parse_lenient`], whose data did not match
+    /// the length stored in the header
+    pub truncation: Option<Truncation>,
+}
+
+/// Describes how the data of a demo differs from what its header announced, which happens for
+/// demos of crashed servers or demos that are still being recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+    /// The length of the demo data according to the header. The FileInfo frame is usually missing
+    /// in that case, so its length can not be known and this only goes up to where it starts
+    pub expected_len: usize,
+    /// The length of the demo data actually present
+    pub buffer_len: usize,
+    /// The number of bytes at the end of the data, that did not form a complete frame and were
+    /// therefore dropped
+    pub incomplete_bytes: usize,
+}
+
+impl Truncation {
+    /// The number of bytes missing, compared to the length derived from the header
+    pub fn missing_bytes(&self) -> usize {
+        self.expected_len.saturating_sub(self.buffer_len)
+    }
 }
 
 impl<'b> Container<'b> {
@@ -24,24 +59,283 @@ impl<'b> Container<'b> {
     where
         'ib: 'b,
     {
-        if input.len() < 16 {
-            return Err(ParseContainerError::MissingHeader);
-        }
+        let (magic, header) = Self::parse_header(input)?;
 
-        let magic = core::str::from_utf8(&input[..8]).map_err(ParseContainerError::InvalidMagic)?;
-        let raw_len: [u8; 4] = input[8..12]
-            .try_into()
-            .expect("We know that the input buffer is at least 16 bytes large");
-        let len = u32::from_le_bytes(raw_len);
-
-        let inner = &input[16..];
-        if inner.len() != len as usize + 2 {
+        let inner = &input[HEADER_SIZE..];
+        if header.file_info_end(inner) != Some(inner.len()) {
             return Err(ParseContainerError::MismatchedLength {
                 buffer_len: inner.len(),
-                expected_len: len as usize + 2,
+                expected_len: header.expected_len(inner),
             });
         }
 
-        Ok(Self { magic, inner })
+        Ok(Self {
+            magic,
+            file_info_offset: header.file_info_offset,
+            spawngroups_offset: header.spawngroups_offset,
+            inner,
+            truncation: None,
+        })
+    }
+
+    /// Like [`Container::parse`], but also accepts demos whose data does not match the length
+    /// stored in the header.
+    ///
+    /// In that case the data is cut off after the last complete frame and the
+    /// [`truncation`](Self::truncation) describes how much data was missing or dropped.
+    pub fn parse_lenient<'ib>(input: &'ib [u8]) -> Result<Self, ParseContainerError>
+    where
+        'ib: 'b,
+    {
+        let (magic, header) = Self::parse_header(input)?;
+
+        let inner = &input[HEADER_SIZE..];
+        if header.file_info_end(inner) == Some(inner.len()) {
+            return Ok(Self {
+                magic,
+                file_info_offset: header.file_info_offset,
+                spawngroups_offset: header.spawngroups_offset,
+                inner,
+                truncation: None,
+            });
+        }
+
+        let mut frames = crate::FallibleFrameIterator::parse(inner);
+        let mut complete_len = 0;
+        while let Some(Ok(_)) = frames.next() {
+            complete_len = frames.offset();
+        }
+
+        Ok(Self {
+            magic,
+            file_info_offset: header.file_info_offset,
+            spawngroups_offset: header.spawngroups_offset,
+            inner: &inner[..complete_len],
+            truncation: Some(Truncation {
+                expected_len: header.expected_len(inner),
+                buffer_len: inner.len(),
+                incomplete_bytes: inner.len() - complete_len,
+            }),
+        })
+    }
+
+    fn parse_header(input: &[u8]) -> Result<(&str, Header), ParseContainerError> {
+        let raw: &[u8; HEADER_SIZE] = input
+            .get(..HEADER_SIZE)
+            .ok_or(ParseContainerError::MissingHeader)?
+            .try_into()
+            .expect("We just got exactly HEADER_SIZE bytes");
+        let header = Header::parse(raw)?;
+
+        let magic = core::str::from_utf8(&input[..MAGIC.len()])
+            .expect("We already checked that the magic matches the expected one");
+
+        Ok((magic, header))
+    }
+
+    /// Returns the FileInfo frame, by directly jumping to the offset stored in the header instead
+    /// of going through all the frames of the demo.
+    ///
+    /// Returns `None` if the offset does not point at a valid FileInfo frame, like for truncated
+    /// demos
+    pub fn file_info_frame(&self) -> Option<crate::Frame<'b>> {
+        let offset = (self.file_info_offset as usize).checked_sub(HEADER_SIZE)?;
+        let (_, frame) = crate::Frame::parse(self.inner.get(offset..)?).ok()?;
+
+        (frame.cmd == crate::DemoCommand::FileInfo).then_some(frame)
+    }
+}
+
+/// The fields stored in the header of a demo
+struct Header {
+    file_info_offset: u32,
+    spawngroups_offset: u32,
+}
+
+impl Header {
+    fn parse(raw: &[u8; HEADER_SIZE]) -> Result<Self, ParseContainerError> {
+        let magic: [u8; 8] = raw[..8].try_into().expect("The header is 16 bytes large");
+        if &magic != MAGIC {
+            return Err(ParseContainerError::InvalidMagic(magic));
+        }
+
+        let file_info_offset =
+            u32::from_le_bytes(raw[8..12].try_into().expect("The header is 16 bytes large"));
+        let spawngroups_offset = u32::from_le_bytes(
+            raw[12..16]
+                .try_into()
+                .expect("The header is 16 bytes large"),
+        );
+
+        Ok(Self {
+            file_info_offset,
+            spawngroups_offset,
+        })
+    }
+
+    /// Where the FileInfo frame ends in the data following the header, which is where a complete
+    /// demo ends, as the FileInfo frame is always its last frame.
+    ///
+    /// Returns `None` if there is no complete FileInfo frame at the offset
+    fn file_info_end(&self, inner: &[u8]) -> Option<usize> {
+        let offset = (self.file_info_offset as usize).checked_sub(HEADER_SIZE)?;
+        let (rest, frame) = crate::Frame::parse(inner.get(offset..)?).ok()?;
+
+        (frame.cmd == crate::DemoCommand::FileInfo).then_some(inner.len() - rest.len())
+    }
+
+    /// The expected length of the data following the header, which only goes up to the start of
+    /// the FileInfo frame if the frame is missing
+    fn expected_len(&self, inner: &[u8]) -> usize {
+        self.file_info_end(inner)
+            .unwrap_or((self.file_info_offset as usize).saturating_sub(HEADER_SIZE))
+    }
+}
+
+/// The streaming counterpart to [`Container`], which only reads the header upfront and then hands
+/// out the frames one at a time through a [`FrameReader`](crate::FrameReader).
+pub struct ContainerReader<R> {
+    pub magic: String,
+    /// The offset of the FileInfo frame from the start of the demo
+    pub file_info_offset: u32,
+    /// The offset of the SpawnGroups frame from the start of the demo
+    pub spawngroups_offset: u32,
+    frames: crate::FrameReader<R>,
+}
+
+impl<R> ContainerReader<R>
+where
+    R: std::io::Read,
+{
+    /// Reads the header of a cs2 demo container from the given reader
+    pub fn new(mut reader: R) -> Result<Self, ParseContainerError> {
+        let mut raw = [0; HEADER_SIZE];
+        reader.read_exact(&mut raw).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ParseContainerError::MissingHeader,
+            _ => ParseContainerError::Io(e),
+        })?;
+        let header = Header::parse(&raw)?;
+
+        Ok(Self {
+            magic: String::from_utf8_lossy(MAGIC).into_owned(),
+            file_info_offset: header.file_info_offset,
+            spawngroups_offset: header.spawngroups_offset,
+            frames: crate::FrameReader::new(reader),
+        })
+    }
+
+    pub fn frames(self) -> crate::FrameReader<R> {
+        self.frames
+    }
+}
+
+impl<R> ContainerReader<R>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    /// Follows the frames of a demo that is still being recorded, see
+    /// [`FrameFollower`](crate::FrameFollower)
+    pub fn follow(self) -> std::io::Result<crate::FrameFollower<R>> {
+        // No frames have been read yet, so the FrameReader did not buffer anything that would be
+        // lost here
+        crate::FrameFollower::new(self.frames.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn demo(file_info_offset: u32, frames: &[u8]) -> Vec<u8> {
+        let mut result = MAGIC.to_vec();
+        result.extend_from_slice(&file_info_offset.to_le_bytes());
+        result.extend_from_slice(&[0; 4]);
+        result.extend_from_slice(frames);
+        result
+    }
+
+    // Three small frames, a FileHeader with 2 bytes, a Packet with 1 byte and an empty FileInfo
+    const FRAMES: &[u8] = &[1, 0, 2, 0xaa, 0xbb, 7, 5, 1, 0xcc, 2, 9, 0];
+    const FILE_INFO_OFFSET: u32 = HEADER_SIZE as u32 + 9;
+
+    #[test]
+    fn lenient_complete() {
+        let input = demo(FILE_INFO_OFFSET, FRAMES);
+
+        let container = Container::parse_lenient(&input).unwrap();
+
+        assert_eq!(FRAMES, container.inner);
+        assert_eq!(None, container.truncation);
+        assert!(Container::parse(&input).is_ok());
+    }
+
+    #[test]
+    fn lenient_truncated() {
+        let input = demo(FILE_INFO_OFFSET, &FRAMES[..8]);
+
+        assert!(Container::parse(&input).is_err());
+
+        let container = Container::parse_lenient(&input).unwrap();
+
+        assert_eq!(&FRAMES[..5], container.inner);
+        assert_eq!(
+            Some(Truncation {
+                expected_len: 9,
+                buffer_len: 8,
+                incomplete_bytes: 3,
+            }),
+            container.truncation
+        );
+        assert_eq!(1, container.truncation.unwrap().missing_bytes());
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut frames = FRAMES.to_vec();
+        frames.extend_from_slice(&[7, 5, 1, 0xcc]);
+        let input = demo(FILE_INFO_OFFSET, &frames);
+
+        assert!(matches!(
+            Container::parse(&input),
+            Err(ParseContainerError::MismatchedLength {
+                buffer_len: 16,
+                expected_len: 12,
+            })
+        ));
+    }
+
+    #[test]
+    fn lenient_still_recording() {
+        // Demos that are still being recorded, have not yet written their length
+        let input = demo(0, FRAMES);
+
+        let container = Container::parse_lenient(&input).unwrap();
+
+        assert_eq!(FRAMES, container.inner);
+        assert_eq!(0, container.truncation.unwrap().missing_bytes());
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut input = demo(FILE_INFO_OFFSET, FRAMES);
+        input[0] = b'X';
+
+        assert!(matches!(
+            Container::parse(&input),
+            Err(ParseContainerError::InvalidMagic(_))
+        ));
+    }
+
+    #[test]
+    fn jump_to_file_info() {
+        // A FileHeader followed by an empty FileInfo frame
+        let frames = &[1, 0, 2, 0xaa, 0xbb, 2, 9, 0];
+        let input = demo(HEADER_SIZE as u32 + 5, frames);
+
+        let container = Container::parse_lenient(&input).unwrap();
+
+        let frame = container.file_info_frame().unwrap();
+        assert_eq!(crate::DemoCommand::FileInfo, frame.cmd);
+        assert_eq!(9, frame.tick);
     }
 }
@@ -0,0 +1,179 @@
+//! Cutting a range of ticks out of a demo, to create a smaller demo that can still be played on
+//! its own
+
+use crate::{
+    Container, DemoCommand, DemoWriter, FallibleFrameIterator, FrameDecompressError, FrameError,
+    FrameIndex,
+};
+
+#[derive(Debug)]
+pub enum CutError {
+    Frame(FrameError),
+    DecompressFrame(FrameDecompressError),
+    DecodeProtobuf(prost::DecodeError),
+    MissingFileInfo,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for CutError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Writes a new demo containing only the ticks from `start` to `end` (inclusive) of the given
+/// demo.
+///
+/// All the signon frames are copied as they are needed to play the demo at all. Because the
+/// state at `start` can only be restored from a FullPacket, the output actually starts at the
+/// closest FullPacket before `start`, or at the beginning of the demo if there is none.
+/// Lastly the FileInfo of the demo is adjusted to match the ticks in the output.
+pub fn cut<W>(
+    container: &Container<'_>,
+    start: i32,
+    end: i32,
+    mut writer: DemoWriter<W>,
+) -> Result<W, CutError>
+where
+    W: std::io::Write + std::io::Seek,
+{
+    let index = FrameIndex::build(container.inner).map_err(CutError::Frame)?;
+
+    let range_start = index
+        .full_packet_before(start)
+        .map(|e| e.offset)
+        .unwrap_or(0);
+    let range_end = index
+        .offset_after_tick(end)
+        .unwrap_or(container.inner.len());
+
+    let mut buffer = Vec::new();
+    let mut in_signon = true;
+    let mut file_info = None;
+    // Keep the Stop frame in the same place relative to the FileInfo as in the original demo
+    let mut stop = None;
+
+    let mut first_tick = None;
+    let mut last_tick = 0;
+    let mut n_frames = 0;
+
+    for frame in FallibleFrameIterator::parse(container.inner) {
+        let (position, frame) = frame.map_err(CutError::Frame)?;
+
+        match frame.cmd {
+            DemoCommand::FileInfo => {
+                let data = frame
+                    .decompress_with_buf(&mut buffer)
+                    .map_err(CutError::DecompressFrame)?;
+                let raw: crate::csgo_proto::CDemoFileInfo =
+                    prost::Message::decode(data).map_err(CutError::DecodeProtobuf)?;
+                file_info = Some(raw);
+                continue;
+            }
+            DemoCommand::Stop => {
+                stop = Some(file_info.is_none());
+                continue;
+            }
+            DemoCommand::Packet | DemoCommand::FullPacket => {
+                in_signon = false;
+            }
+            _ => {}
+        };
+
+        if in_signon {
+            writer.write_frame(&frame)?;
+            continue;
+        }
+
+        if position.offset < range_start || position.offset >= range_end {
+            continue;
+        }
+
+        writer.write_frame(&frame)?;
+
+        first_tick.get_or_insert(frame.tick);
+        last_tick = frame.tick;
+        n_frames += 1;
+    }
+
+    let mut file_info = file_info.ok_or(CutError::MissingFileInfo)?;
+    let n_ticks = last_tick - first_tick.unwrap_or(last_tick);
+    if file_info.playback_ticks() > 0 {
+        let tick_interval = file_info.playback_time() / file_info.playback_ticks() as f32;
+        file_info.playback_time = Some(n_ticks as f32 * tick_interval);
+    }
+    file_info.playback_ticks = Some(n_ticks);
+    file_info.playback_frames = Some(n_frames);
+
+    if stop == Some(true) {
+        writer.write_data(DemoCommand::Stop, last_tick, &[])?;
+    }
+    writer.write_data(
+        DemoCommand::FileInfo,
+        last_tick,
+        &prost::Message::encode_to_vec(&file_info),
+    )?;
+    if stop == Some(false) {
+        writer.write_data(DemoCommand::Stop, last_tick, &[])?;
+    }
+
+    writer.finish().map_err(CutError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cut_range() {
+        let mut writer = DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+        for (cmd, tick) in [
+            (DemoCommand::FileHeader, -1),
+            (DemoCommand::SignonPacket, -1),
+            (DemoCommand::Packet, 1),
+            (DemoCommand::FullPacket, 5),
+            (DemoCommand::Packet, 6),
+            (DemoCommand::Packet, 10),
+            (DemoCommand::Stop, 10),
+            (DemoCommand::FileInfo, 10),
+        ] {
+            writer.write_data(cmd, tick, &[]).unwrap();
+        }
+        let input = writer.finish().unwrap().into_inner();
+        let container = Container::parse(&input).unwrap();
+
+        let writer = DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+        let output = cut(&container, 6, 6, writer).unwrap().into_inner();
+
+        let container = Container::parse(&output).unwrap();
+        let file_info = container.file_info_frame().unwrap();
+        assert_eq!(6, file_info.tick);
+
+        let frames: Vec<_> = crate::FrameIterator::parse(container.inner)
+            .map(|f| (f.cmd, f.tick))
+            .collect();
+        assert_eq!(
+            vec![
+                (DemoCommand::FileHeader, -1),
+                (DemoCommand::SignonPacket, -1),
+                (DemoCommand::FullPacket, 5),
+                (DemoCommand::Packet, 6),
+                (DemoCommand::Stop, 6),
+                (DemoCommand::FileInfo, 6),
+            ],
+            frames
+        );
+
+        let mut buffer = Vec::new();
+        let info: crate::csgo_proto::CDemoFileInfo = prost::Message::decode(
+            crate::FrameIterator::parse(container.inner)
+                .find(|f| f.cmd == DemoCommand::FileInfo)
+                .unwrap()
+                .decompress_with_buf(&mut buffer)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1, info.playback_ticks());
+        assert_eq!(2, info.playback_frames());
+    }
+}
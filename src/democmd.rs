@@ -53,3 +53,31 @@ impl TryFrom<i32> for DemoCommand {
         }
     }
 }
+
+impl From<DemoCommand> for i32 {
+    fn from(value: DemoCommand) -> Self {
+        match value {
+            DemoCommand::Error => -1,
+            DemoCommand::Stop => 0,
+            DemoCommand::FileHeader => 1,
+            DemoCommand::FileInfo => 2,
+            DemoCommand::SyncTick => 3,
+            DemoCommand::SendTables => 4,
+            DemoCommand::ClassInfo => 5,
+            DemoCommand::StringTables => 6,
+            DemoCommand::Packet => 7,
+            DemoCommand::SignonPacket => 8,
+            DemoCommand::ConsoleCmd => 9,
+            DemoCommand::CustomData => 10,
+            DemoCommand::CustomDataCallbacks => 11,
+            DemoCommand::UserCmd => 12,
+            DemoCommand::FullPacket => 13,
+            DemoCommand::SaveGame => 14,
+            DemoCommand::SpawnGroups => 15,
+            DemoCommand::AnimationData => 16,
+            DemoCommand::AnimationHeader => 17,
+            DemoCommand::Max => 18,
+            DemoCommand::IsCompressed => 64,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{DemoCommand, Frame, FrameParseError};
+
+/// Reads the frames of a demo that is still being recorded, like a `tail -f` for demos.
+///
+/// Instead of failing when reaching a partially written frame at the end, it remembers where the
+/// last complete frame ended and waits for more data to be written. Once the `Stop` frame was
+/// read, the demo is complete and it no longer waits for more data.
+///
+/// The reader is expected to be positioned right after the container header, see
+/// [`ContainerReader::follow`](crate::ContainerReader::follow) for a convenient way to get there.
+pub struct FrameFollower<R> {
+    reader: std::io::BufReader<R>,
+    /// The offset right after the last complete frame
+    offset: u64,
+    poll_interval: std::time::Duration,
+    idle_timeout: Option<std::time::Duration>,
+    finished: bool,
+}
+
+impl<R> FrameFollower<R>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    pub fn new(reader: R) -> std::io::Result<Self> {
+        let mut reader = std::io::BufReader::new(reader);
+        let offset = std::io::Seek::stream_position(&mut reader)?;
+
+        Ok(Self {
+            reader,
+            offset,
+            poll_interval: std::time::Duration::from_millis(100),
+            idle_timeout: None,
+            finished: false,
+        })
+    }
+
+    /// How long to wait before checking for new data again, defaults to 100ms
+    pub fn poll_interval(mut self, interval: std::time::Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// How long to wait for new data, before giving up. By default it waits forever, until the
+    /// demo is complete
+    pub fn idle_timeout(mut self, timeout: Option<std::time::Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// The offset right after the last complete frame that was read
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether or not the `Stop` frame was read, after which no more frames are expected
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Attempts to read the next frame, without waiting, returns `Ok(None)` if no complete frame
+    /// is available yet
+    pub fn try_next_frame(&mut self) -> Result<Option<Frame<'static>>, FrameParseError> {
+        match crate::frame::read_frame(&mut self.reader) {
+            Ok(Some(frame)) => {
+                self.offset = std::io::Seek::stream_position(&mut self.reader)
+                    .map_err(FrameParseError::Io)?;
+                if frame.cmd == DemoCommand::Stop {
+                    self.finished = true;
+                }
+                Ok(Some(frame))
+            }
+            Ok(None) | Err(FrameParseError::NotEnoughBytes) => {
+                // Go back to the start of the partial frame, to read it again once it is complete
+                std::io::Seek::seek(&mut self.reader, std::io::SeekFrom::Start(self.offset))
+                    .map_err(FrameParseError::Io)?;
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the next frame, waiting for it to be written if needed.
+    ///
+    /// Returns `Ok(None)` once the demo is complete or the idle timeout has been reached
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'static>>, FrameParseError> {
+        let started = std::time::Instant::now();
+
+        loop {
+            if let Some(frame) = self.try_next_frame()? {
+                return Ok(Some(frame));
+            }
+
+            if self.finished {
+                return Ok(None);
+            }
+            if let Some(timeout) = self.idle_timeout {
+                if started.elapsed() >= timeout {
+                    return Ok(None);
+                }
+            }
+
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+impl<R> Iterator for FrameFollower<R>
+where
+    R: std::io::Read + std::io::Seek,
+{
+    type Item = Result<Frame<'static>, FrameParseError>;
+
+    /// See [`FrameFollower::next_frame`]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A buffer that can still be written to, while it is being read
+    #[derive(Clone)]
+    struct Growing {
+        data: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+        position: u64,
+    }
+
+    impl std::io::Read for Growing {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let data = self.data.borrow();
+            let start = (self.position as usize).min(data.len());
+            let n = buf.len().min(data.len() - start);
+            buf[..n].copy_from_slice(&data[start..start + n]);
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl std::io::Seek for Growing {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.position = match pos {
+                std::io::SeekFrom::Start(p) => p,
+                std::io::SeekFrom::Current(p) => (self.position as i64 + p) as u64,
+                std::io::SeekFrom::End(p) => (self.data.borrow().len() as i64 + p) as u64,
+            };
+            Ok(self.position)
+        }
+    }
+
+    #[test]
+    fn partial_frames() {
+        let data = std::rc::Rc::new(std::cell::RefCell::new(vec![1, 0, 2, 0xaa]));
+        let mut follower = FrameFollower::new(Growing {
+            data: data.clone(),
+            position: 0,
+        })
+        .unwrap();
+
+        assert!(follower.try_next_frame().unwrap().is_none());
+        assert_eq!(0, follower.offset());
+
+        data.borrow_mut().extend_from_slice(&[0xbb, 7, 5]);
+        let frame = follower.try_next_frame().unwrap().unwrap();
+        assert_eq!(DemoCommand::FileHeader, frame.cmd);
+        assert_eq!(&[0xaa, 0xbb], frame.inner.as_ref());
+        assert_eq!(5, follower.offset());
+        assert!(follower.try_next_frame().unwrap().is_none());
+
+        data.borrow_mut().extend_from_slice(&[1, 0xcc, 0, 6, 0]);
+        assert_eq!(5, follower.try_next_frame().unwrap().unwrap().tick);
+        assert!(!follower.is_finished());
+        assert_eq!(
+            DemoCommand::Stop,
+            follower.try_next_frame().unwrap().unwrap().cmd
+        );
+        assert!(follower.is_finished());
+        assert!(follower.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let data = std::rc::Rc::new(std::cell::RefCell::new(vec![7, 5, 1, 0xcc, 7]));
+        let follower = FrameFollower::new(Growing { data, position: 0 })
+            .unwrap()
+            .poll_interval(std::time::Duration::from_millis(1))
+            .idle_timeout(Some(std::time::Duration::from_millis(10)));
+
+        assert_eq!(1, follower.count());
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Debug)]
 pub struct Frame<'b> {
     pub cmd: crate::DemoCommand,
     pub tick: i32,
@@ -10,14 +11,65 @@ pub enum FrameParseError {
     ParseVarint(()),
     NotEnoughBytes,
     ParseDemoCommand(i32),
+    Io(std::io::Error),
 }
 
 #[derive(Debug)]
 pub enum FrameDecompressError {
     GettingDecompressedLength(snap::Error),
     Decompressing(snap::Error),
+    /// The decompressed length stored in the frame is larger than the compressed data could
+    /// possibly expand to
+    InvalidDecompressedLength(usize),
+}
+
+impl std::fmt::Display for FrameParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseVarint(()) => write!(f, "invalid varint"),
+            Self::NotEnoughBytes => write!(f, "not enough bytes"),
+            Self::ParseDemoCommand(cmd) => write!(f, "unknown demo command {}", cmd),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FrameParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
+impl std::fmt::Display for FrameDecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GettingDecompressedLength(e) => {
+                write!(f, "failed to get the decompressed length: {}", e)
+            }
+            Self::Decompressing(e) => write!(f, "{}", e),
+            Self::InvalidDecompressedLength(len) => {
+                write!(f, "invalid decompressed length {}", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameDecompressError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::GettingDecompressedLength(e) | Self::Decompressing(e) => Some(e),
+            Self::InvalidDecompressedLength(_) => None,
+        }
+    }
+}
+
+/// Snappy can at most expand the data by a factor of about 21, anything larger than this is
+/// corrupted and would only lead to huge allocations
+const MAX_EXPANSION: usize = 32;
+
 impl<'b> Frame<'b> {
     pub fn parse<'ib>(input: &'ib [u8]) -> Result<(&'ib [u8], Self), FrameParseError>
     where
@@ -48,6 +100,20 @@ impl<'b> Frame<'b> {
         ))
     }
 
+    /// The number of bytes the frame takes up in the demo, assuming the usual encoding of its
+    /// varints
+    pub fn encoded_len(&self) -> usize {
+        let mut raw_cmd = i32::from(self.cmd) as u32;
+        if self.compressed {
+            raw_cmd |= 64;
+        }
+
+        crate::varint::varint_len(raw_cmd)
+            + crate::varint::varint_len(self.tick as u32)
+            + crate::varint::varint_len(self.inner.len() as u32)
+            + self.inner.len()
+    }
+
     pub fn data(&self) -> Option<&[u8]> {
         if self.compressed {
             return None;
@@ -69,6 +135,11 @@ impl<'b> Frame<'b> {
 
         let uncompressed_len = snap::raw::decompress_len(&self.inner)
             .map_err(|e| FrameDecompressError::GettingDecompressedLength(e))?;
+        if uncompressed_len > self.inner.len().saturating_mul(MAX_EXPANSION) {
+            return Err(FrameDecompressError::InvalidDecompressedLength(
+                uncompressed_len,
+            ));
+        }
         buf.resize(uncompressed_len, 0);
 
         snap::raw::Decoder::new()
@@ -83,6 +154,14 @@ impl<'b> Frame<'b> {
             return Ok(());
         }
 
+        let uncompressed_len = snap::raw::decompress_len(&self.inner)
+            .map_err(FrameDecompressError::GettingDecompressedLength)?;
+        if uncompressed_len > self.inner.len().saturating_mul(MAX_EXPANSION) {
+            return Err(FrameDecompressError::InvalidDecompressedLength(
+                uncompressed_len,
+            ));
+        }
+
         let decompressed = snap::raw::Decoder::new()
             .decompress_vec(self.inner.as_ref())
             .map_err(FrameDecompressError::Decompressing)?;
@@ -120,11 +199,391 @@ impl<'b> Iterator for FrameIterator<'b> {
                 Some(frame)
             }
             Err(_e) => {
-                // TODO
-                // How do we handle errors?
+                // Errors are only reported by the FallibleFrameIterator, so we just stop here
                 self.remaining = &[];
                 None
             }
         }
     }
 }
+
+/// The location of a frame in the demo data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramePosition {
+    /// The byte offset of the frame, relative to the start of the data being iterated over
+    pub offset: usize,
+    /// The index of the frame, counting every frame (and error) before it
+    pub index: usize,
+}
+
+#[derive(Debug)]
+pub struct FrameError {
+    pub position: FramePosition,
+    pub error: FrameParseError,
+}
+
+impl From<FrameError> for FrameParseError {
+    fn from(value: FrameError) -> Self {
+        value.error
+    }
+}
+
+impl FrameError {
+    /// Whether the error was caused by the data ending in the middle of a frame, which is the case
+    /// for demos that were truncated or are still being written
+    pub fn is_truncated(&self) -> bool {
+        matches!(self.error, FrameParseError::NotEnoughBytes)
+    }
+}
+
+/// Like the [`FrameIterator`], but reports errors instead of just stopping and tracks the
+/// [`FramePosition`] of every frame.
+///
+/// By default iteration ends after the first error, but with [`resync`](Self::resync) enabled it
+/// will try to find the next valid frame after the broken one and continue from there.
+pub struct FallibleFrameIterator<'b> {
+    input: &'b [u8],
+    offset: usize,
+    index: usize,
+    resync: bool,
+    done: bool,
+}
+
+impl<'b> FallibleFrameIterator<'b> {
+    pub fn parse<'ib>(input: &'ib [u8]) -> Self
+    where
+        'ib: 'b,
+    {
+        Self {
+            input,
+            offset: 0,
+            index: 0,
+            resync: false,
+            done: false,
+        }
+    }
+
+    /// Configures whether the iterator should try to continue after a broken frame
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.resync = resync;
+        self
+    }
+
+    /// The offset at which the next frame would be parsed
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn find_next_frame(&self, start: usize) -> Option<usize> {
+        // A single successful parse is not very meaningful with random data, so we require
+        // the following frame to also be valid (or the data to end right after the frame)
+        (start..self.input.len()).find(|offset| match Frame::parse(&self.input[*offset..]) {
+            Ok((rem, _)) => rem.is_empty() || Frame::parse(rem).is_ok(),
+            Err(_) => false,
+        })
+    }
+}
+
+impl<'b> Iterator for FallibleFrameIterator<'b> {
+    type Item = Result<(FramePosition, Frame<'b>), FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.input.len() {
+            return None;
+        }
+
+        let position = FramePosition {
+            offset: self.offset,
+            index: self.index,
+        };
+        self.index += 1;
+
+        match Frame::parse(&self.input[self.offset..]) {
+            Ok((rem, frame)) => {
+                self.offset = self.input.len() - rem.len();
+                Some(Ok((position, frame)))
+            }
+            Err(error) => {
+                match self.resync {
+                    true => match self.find_next_frame(self.offset + 1) {
+                        Some(offset) => self.offset = offset,
+                        None => self.done = true,
+                    },
+                    false => self.done = true,
+                };
+
+                Some(Err(FrameError { position, error }))
+            }
+        }
+    }
+}
+
+/// Reads the frames of a demo one at a time from any [`std::io::Read`], so only a single frame
+/// needs to be kept in memory at a time, instead of the entire demo.
+///
+/// The reader is expected to be positioned right after the container header, see
+/// [`ContainerReader`](crate::ContainerReader) for a convenient way to get there.
+///
+/// Same as the [`FallibleFrameIterator`], iterating reports the errors of the reader and of
+/// broken frames together with their [`FramePosition`] and ends after the first one.
+pub struct FrameReader<R> {
+    reader: std::io::BufReader<R>,
+    offset: usize,
+    index: usize,
+    done: bool,
+}
+
+impl<R> FrameReader<R>
+where
+    R: std::io::Read,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: std::io::BufReader::new(reader),
+            offset: 0,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Reads the next frame, returns `Ok(None)` once the reader has been exhausted cleanly
+    pub fn next_frame(&mut self) -> Result<Option<Frame<'static>>, FrameParseError> {
+        let mut counting = CountingReader {
+            inner: &mut self.reader,
+            count: 0,
+        };
+        let result = read_frame(&mut counting);
+        self.offset += counting.count;
+        result
+    }
+
+    /// The number of bytes read so far, which is the offset of the next frame if there was no
+    /// error
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
+/// Reads a single frame from the reader, returns `Ok(None)` if the reader was already exhausted
+pub(crate) fn read_frame<R>(reader: &mut R) -> Result<Option<Frame<'static>>, FrameParseError>
+where
+    R: std::io::Read,
+{
+    use std::io::Read;
+
+    let raw_cmd = match crate::varint::read_varint(reader) {
+        Ok(Some(c)) => c,
+        Ok(None) => return Ok(None),
+        Err(e) => return Err(map_read_err(e)),
+    };
+    let tick = crate::varint::read_varint(reader)
+        .map_err(map_read_err)?
+        .ok_or(FrameParseError::NotEnoughBytes)?;
+    let size = crate::varint::read_varint(reader)
+        .map_err(map_read_err)?
+        .ok_or(FrameParseError::NotEnoughBytes)?;
+
+    // Using take + read_to_end avoids allocating a huge buffer upfront, if the size is garbage
+    let mut data = Vec::new();
+    reader
+        .take(size as u64)
+        .read_to_end(&mut data)
+        .map_err(FrameParseError::Io)?;
+    if data.len() < size as usize {
+        return Err(FrameParseError::NotEnoughBytes);
+    }
+
+    let demo_cmd = crate::DemoCommand::try_from((raw_cmd & !64) as i32)
+        .map_err(FrameParseError::ParseDemoCommand)?;
+
+    Ok(Some(Frame {
+        tick: tick as i32,
+        cmd: demo_cmd,
+        compressed: (raw_cmd & 64) == 64,
+        inner: std::borrow::Cow::Owned(data),
+    }))
+}
+
+/// Counts the bytes read through it, for the positions of the frames of a [`FrameReader`]
+struct CountingReader<'r, R> {
+    inner: &'r mut R,
+    count: usize,
+}
+
+impl<R> std::io::Read for CountingReader<'_, R>
+where
+    R: std::io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+fn map_read_err(err: std::io::Error) -> FrameParseError {
+    match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => FrameParseError::NotEnoughBytes,
+        _ => FrameParseError::Io(err),
+    }
+}
+
+impl<R> Iterator for FrameReader<R>
+where
+    R: std::io::Read,
+{
+    type Item = Result<Frame<'static>, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let position = FramePosition {
+            offset: self.offset,
+            index: self.index,
+        };
+        match self.next_frame() {
+            Ok(Some(frame)) => {
+                self.index += 1;
+                Some(Ok(frame))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(FrameError { position, error }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two small frames, a FileHeader with 2 bytes and a Packet with 1 byte
+    const FRAMES: &[u8] = &[1, 0, 2, 0xaa, 0xbb, 7, 5, 1, 0xcc];
+
+    #[test]
+    fn encoded_len() {
+        let len: usize = FrameIterator::parse(FRAMES)
+            .map(|frame| frame.encoded_len())
+            .sum();
+
+        assert_eq!(FRAMES.len(), len);
+    }
+
+    #[test]
+    fn reader_errors() {
+        // The Packet frame is cut off after its size
+        let mut reader = FrameReader::new(&FRAMES[..FRAMES.len() - 1]);
+
+        let frame = reader.next().unwrap().unwrap();
+        assert_eq!(crate::DemoCommand::FileHeader, frame.cmd);
+
+        let err = reader.next().unwrap().unwrap_err();
+        assert!(err.is_truncated());
+        assert_eq!(
+            FramePosition {
+                offset: 5,
+                index: 1
+            },
+            err.position
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn fallible_positions() {
+        let frames: Vec<_> = FallibleFrameIterator::parse(FRAMES)
+            .map(|f| f.unwrap())
+            .map(|(pos, frame)| (pos, frame.cmd, frame.tick))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (
+                    FramePosition {
+                        offset: 0,
+                        index: 0
+                    },
+                    crate::DemoCommand::FileHeader,
+                    0
+                ),
+                (
+                    FramePosition {
+                        offset: 5,
+                        index: 1
+                    },
+                    crate::DemoCommand::Packet,
+                    5
+                ),
+            ],
+            frames
+        );
+    }
+
+    #[test]
+    fn fallible_truncated() {
+        let mut iter = FallibleFrameIterator::parse(&FRAMES[..FRAMES.len() - 1]);
+
+        assert!(iter.next().unwrap().is_ok());
+
+        let err = iter.next().unwrap().unwrap_err();
+        assert_eq!(
+            FramePosition {
+                offset: 5,
+                index: 1
+            },
+            err.position
+        );
+        assert!(err.is_truncated());
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn fallible_resync() {
+        let mut input = FRAMES[..5].to_vec();
+        // 63 is not a valid DemoCommand
+        input.push(63);
+        input.extend_from_slice(&FRAMES[5..]);
+
+        let without_resync: Vec<_> = FallibleFrameIterator::parse(&input).collect();
+        assert_eq!(2, without_resync.len());
+        assert!(without_resync[1].is_err());
+
+        let with_resync: Vec<_> = FallibleFrameIterator::parse(&input).resync(true).collect();
+        assert_eq!(3, with_resync.len());
+        assert!(with_resync[1].is_err());
+
+        let (pos, frame) = with_resync[2].as_ref().unwrap();
+        assert_eq!(6, pos.offset);
+        assert_eq!(crate::DemoCommand::Packet, frame.cmd);
+    }
+
+    #[test]
+    fn decompress_invalid_length() {
+        // Claims to decompress to 2^28 bytes, from just a few bytes of data
+        let frame = Frame {
+            cmd: crate::DemoCommand::Packet,
+            tick: 0,
+            compressed: true,
+            inner: std::borrow::Cow::Borrowed(&[0x80, 0x80, 0x80, 0x80, 0x01, 0x00]),
+        };
+
+        let mut buf = Vec::new();
+        assert!(matches!(
+            frame.decompress_with_buf(&mut buf),
+            Err(FrameDecompressError::InvalidDecompressedLength(0x10000000))
+        ));
+        assert!(buf.is_empty());
+    }
+}
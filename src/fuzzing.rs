@@ -0,0 +1,57 @@
+//! Entry points for the fuzz targets in `fuzz/`, which need to reach some of the internals of the
+//! parser. This is only built with the `fuzzing` feature, which the fuzz crate enables, and is not
+//! part of the public API.
+
+use crate::parser::FirstPassError;
+
+/// Runs a sequence of reads on a [`Bitreader`](crate::bitreader::Bitreader), the first byte of
+/// the input selects the reads and the rest is the data being read
+pub fn bitreader(input: &[u8]) {
+    let Some((&ops, data)) = input.split_first() else {
+        return;
+    };
+
+    let mut bitreader = crate::bitreader::Bitreader::new(data);
+    for round in 0.. {
+        let op = ops.rotate_left(round % 8) % 8;
+        let result = match op {
+            0 => bitreader.read_nbits(1 + (round % 32)).map(|_| ()),
+            1 => bitreader.read_u_bit_var().map(|_| ()),
+            2 => bitreader.read_varint32().map(|_| ()),
+            3 => bitreader.read_varint_u_64().map(|_| ()),
+            4 => bitreader.read_ubit_var_fp().map(|_| ()),
+            5 => bitreader.read_bit_coord().map(|_| ()),
+            6 => bitreader
+                .read_varint()
+                .and_then(|n| bitreader.read_n_bytes(n as usize))
+                .map(|_| ()),
+            _ => bitreader.read_boolean().map(|_| ()),
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+/// Parses the input as the field paths of an entity update
+pub fn parse_paths(input: &[u8]) -> Result<usize, FirstPassError> {
+    let mut bitreader = crate::bitreader::Bitreader::new(input);
+    let mut paths = crate::parser::Paths::new();
+
+    crate::parser::fieldpath::parse_paths(&mut bitreader, &mut paths)
+}
+
+/// Parses the input as a `CsvcMsgFlattenedSerializer` and builds the serializers from it
+pub fn get_serializers(input: &[u8]) -> Result<usize, FirstPassError> {
+    let msg: crate::csgo_proto::CsvcMsgFlattenedSerializer = prost::Message::decode(input)?;
+
+    let mut qf_mapper = crate::parser::decoder::QfMapper {
+        idx: 0,
+        map: std::collections::HashMap::new(),
+    };
+    let mut prop_controller = crate::parser::propcontroller::PropController::new();
+
+    let serializers =
+        crate::parser::sendtables::get_serializers(&msg, &mut qf_mapper, &mut prop_controller)?;
+    Ok(serializers.len())
+}
@@ -298,6 +298,16 @@ pub enum ParseGameEventError {
     MismatchedKeysFields,
 }
 
+impl std::fmt::Display for ParseGameEventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MismatchedKeysFields => write!(f, "the keys do not match the fields"),
+        }
+    }
+}
+
+impl std::error::Error for ParseGameEventError {}
+
 pub static EVENT_PARSERS: phf::Map<&'static str, GameEventParser> = phf::phf_map! {
     "hltv_versioninfo" => GameEventParser::new(HltvVersionInfo::parse),
 
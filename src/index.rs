@@ -0,0 +1,306 @@
+use crate::{DemoCommand, FallibleFrameIterator, FrameError};
+
+/// The magic at the start of a serialized [`FrameIndex`], the last byte is the version
+const INDEX_MAGIC: &[u8; 8] = b"CSDIDX\0\x02";
+
+/// An index over the frames of a demo, mapping ticks to the byte offsets of their frames and
+/// storing the positions of all the "special" frames needed to start parsing in the middle of a
+/// demo.
+///
+/// All offsets are relative to the data the index was built from, which is usually the
+/// [`Container::inner`](crate::Container::inner) of a demo.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrameIndex {
+    /// The first frame of every tick, in the order they appear in the demo
+    pub ticks: Vec<IndexEntry>,
+    pub full_packets: Vec<IndexEntry>,
+    pub send_tables: Vec<IndexEntry>,
+    pub class_info: Vec<IndexEntry>,
+    pub string_tables: Vec<IndexEntry>,
+    /// The first Packet or FullPacket, which ends the signon frames at the start of the demo
+    pub signon_end: Option<IndexEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub tick: i32,
+    pub offset: usize,
+    /// The index of the frame, counted from the first frame of the data
+    pub frame: usize,
+}
+
+impl FrameIndex {
+    /// Builds the index in a single pass over the frames, without decompressing or decoding any
+    /// of them
+    pub fn build(input: &[u8]) -> Result<Self, FrameError> {
+        let mut index = Self::default();
+
+        for frame in FallibleFrameIterator::parse(input) {
+            let (position, frame) = frame?;
+            let entry = IndexEntry {
+                tick: frame.tick,
+                offset: position.offset,
+                frame: position.index,
+            };
+
+            if index.ticks.last().map(|e| e.tick) != Some(frame.tick) {
+                index.ticks.push(entry);
+            }
+
+            if index.signon_end.is_none()
+                && matches!(frame.cmd, DemoCommand::Packet | DemoCommand::FullPacket)
+            {
+                index.signon_end = Some(entry);
+            }
+
+            match frame.cmd {
+                DemoCommand::FullPacket => index.full_packets.push(entry),
+                DemoCommand::SendTables => index.send_tables.push(entry),
+                DemoCommand::ClassInfo => index.class_info.push(entry),
+                DemoCommand::StringTables => index.string_tables.push(entry),
+                _ => {}
+            };
+        }
+
+        Ok(index)
+    }
+
+    /// Returns the offset of the first frame of the given tick, or of the closest tick before
+    /// it, if there is no frame for exactly that tick
+    pub fn offset_for_tick(&self, tick: i32) -> Option<usize> {
+        last_before(&self.ticks, tick).map(|e| e.offset)
+    }
+
+    /// Returns the offset of the first frame belonging to a tick after the given one
+    pub fn offset_after_tick(&self, tick: i32) -> Option<usize> {
+        let idx = self.ticks.partition_point(|e| e.tick <= tick);
+        self.ticks.get(idx).map(|e| e.offset)
+    }
+
+    /// Returns the last FullPacket at or before the given tick
+    pub fn full_packet_before(&self, tick: i32) -> Option<IndexEntry> {
+        last_before(&self.full_packets, tick)
+    }
+
+    /// Serializes the index into a compact binary representation, which can be stored next to
+    /// the demo and loaded again using [`FrameIndex::read`]
+    pub fn write<W>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        writer.write_all(INDEX_MAGIC)?;
+
+        for entries in self.lists() {
+            write_entries(&mut writer, entries)?;
+        }
+        write_entries(&mut writer, self.signon_end.as_slice())?;
+
+        Ok(())
+    }
+
+    /// Loads an index previously serialized using [`FrameIndex::write`]
+    pub fn read<R>(mut reader: R) -> std::io::Result<Self>
+    where
+        R: std::io::Read,
+    {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Not a serialized FrameIndex",
+            ));
+        }
+
+        let mut index = Self::default();
+        for entries in index.lists_mut() {
+            *entries = read_entries(&mut reader)?;
+        }
+        index.signon_end = read_entries(&mut reader)?.first().copied();
+
+        Ok(index)
+    }
+
+    fn lists(&self) -> [&Vec<IndexEntry>; 5] {
+        [
+            &self.ticks,
+            &self.full_packets,
+            &self.send_tables,
+            &self.class_info,
+            &self.string_tables,
+        ]
+    }
+
+    fn lists_mut(&mut self) -> [&mut Vec<IndexEntry>; 5] {
+        [
+            &mut self.ticks,
+            &mut self.full_packets,
+            &mut self.send_tables,
+            &mut self.class_info,
+            &mut self.string_tables,
+        ]
+    }
+}
+
+fn write_entries<W>(writer: &mut W, entries: &[IndexEntry]) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    crate::varint::write_varint(writer, to_u32(entries.len())?)?;
+
+    // Ticks, offsets and frames are (mostly) increasing, so storing the differences keeps the
+    // varints small
+    let mut prev = IndexEntry {
+        tick: 0,
+        offset: 0,
+        frame: 0,
+    };
+    for entry in entries {
+        let increasing = |value: usize, prev: usize| {
+            value.checked_sub(prev).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Offsets in the index need to be increasing",
+                )
+            })
+        };
+        let offset_delta = increasing(entry.offset, prev.offset)?;
+        let frame_delta = increasing(entry.frame, prev.frame)?;
+
+        crate::varint::write_varint(writer, entry.tick.wrapping_sub(prev.tick) as u32)?;
+        crate::varint::write_varint(writer, to_u32(offset_delta)?)?;
+        crate::varint::write_varint(writer, to_u32(frame_delta)?)?;
+
+        prev = *entry;
+    }
+
+    Ok(())
+}
+
+fn read_entries<R>(reader: &mut R) -> std::io::Result<Vec<IndexEntry>>
+where
+    R: std::io::Read,
+{
+    let count = read_varint(reader)?;
+
+    let mut entries = Vec::new();
+    let mut prev = IndexEntry {
+        tick: 0,
+        offset: 0,
+        frame: 0,
+    };
+    for _ in 0..count {
+        let tick_delta = read_varint(reader)?;
+        let offset_delta = read_varint(reader)?;
+        let frame_delta = read_varint(reader)?;
+
+        let entry = IndexEntry {
+            tick: prev.tick.wrapping_add(tick_delta as i32),
+            offset: prev.offset + offset_delta as usize,
+            frame: prev.frame + frame_delta as usize,
+        };
+        entries.push(entry);
+        prev = entry;
+    }
+
+    Ok(entries)
+}
+
+fn last_before(entries: &[IndexEntry], tick: i32) -> Option<IndexEntry> {
+    let idx = entries.partition_point(|e| e.tick <= tick);
+    idx.checked_sub(1).map(|i| entries[i])
+}
+
+fn to_u32(value: usize) -> std::io::Result<u32> {
+    u32::try_from(value).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Value is too large for the index",
+        )
+    })
+}
+
+fn read_varint<R>(reader: &mut R) -> std::io::Result<u32>
+where
+    R: std::io::Read,
+{
+    crate::varint::read_varint(reader)?.ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A FileHeader and SendTables for the signon tick (-1), followed by a Packet, FullPacket and
+    // Packet for the ticks 5, 5 and 9
+    const FRAMES: &[u8] = &[
+        1, 0xff, 0xff, 0xff, 0xff, 0x0f, 1, 0xaa, //
+        4, 0xff, 0xff, 0xff, 0xff, 0x0f, 0, //
+        7, 5, 1, 0xcc, //
+        13, 5, 0, //
+        7, 9, 0,
+    ];
+
+    #[test]
+    fn build() {
+        let index = FrameIndex::build(FRAMES).unwrap();
+
+        assert_eq!(
+            FrameIndex {
+                ticks: vec![
+                    IndexEntry {
+                        tick: -1,
+                        offset: 0,
+                        frame: 0
+                    },
+                    IndexEntry {
+                        tick: 5,
+                        offset: 15,
+                        frame: 2
+                    },
+                    IndexEntry {
+                        tick: 9,
+                        offset: 22,
+                        frame: 4
+                    },
+                ],
+                full_packets: vec![IndexEntry {
+                    tick: 5,
+                    offset: 19,
+                    frame: 3
+                }],
+                send_tables: vec![IndexEntry {
+                    tick: -1,
+                    offset: 8,
+                    frame: 1
+                }],
+                class_info: vec![],
+                string_tables: vec![],
+                signon_end: Some(IndexEntry {
+                    tick: 5,
+                    offset: 15,
+                    frame: 2
+                }),
+            },
+            index
+        );
+
+        assert_eq!(None, index.offset_for_tick(-2));
+        assert_eq!(Some(15), index.offset_for_tick(7));
+        assert_eq!(Some(22), index.offset_for_tick(100));
+        assert_eq!(Some(22), index.offset_after_tick(5));
+        assert_eq!(None, index.offset_after_tick(9));
+        assert_eq!(None, index.full_packet_before(4));
+        assert_eq!(Some(19), index.full_packet_before(9).map(|e| e.offset));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let index = FrameIndex::build(FRAMES).unwrap();
+
+        let mut buf = Vec::new();
+        index.write(&mut buf).unwrap();
+
+        assert_eq!(index, FrameIndex::read(buf.as_slice()).unwrap());
+    }
+}
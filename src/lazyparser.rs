@@ -1,4 +1,8 @@
-use crate::{Container, FrameIterator};
+use crate::{
+    parser::{Checkpoint, CheckpointError, Engine, EntityFilter, ParseError, ParseOptions},
+    Container, FallibleFrameIterator, Frame, FrameError, FrameIndex, FrameIterator,
+    FrameParseError,
+};
 
 mod events;
 pub use events::LazyEventIterator;
@@ -6,13 +10,76 @@ pub use events::LazyEventIterator;
 mod entities;
 pub use entities::LazyEntityIterator;
 
+mod stream;
+pub use stream::{LazyStreamIterator, StreamItem};
+
+mod owned;
+pub use owned::OwnedLazyParser;
+
+#[derive(Debug)]
+pub enum SeekError {
+    BuildIndex(FrameError),
+    /// The index does not point at valid frames, likely because it was built for a different demo
+    InvalidIndex,
+    MissingSendTables,
+    MissingClassInfo,
+    Decode(ParseError),
+}
+
+/// The frames of the given data, for the lazy iterators to report broken frames. A truncated
+/// frame at the end is not an error, same as for the [`FrameIterator`], so that incomplete demos
+/// can still be parsed
+fn frames(inner: &[u8]) -> impl Iterator<Item = Result<Frame<'_>, FrameParseError>> {
+    FallibleFrameIterator::parse(inner).filter_map(|frame| match frame {
+        Ok((_, frame)) => Some(Ok(frame)),
+        Err(e) if e.is_truncated() => None,
+        Err(e) => Some(Err(e.error)),
+    })
+}
+
+/// The offset of the next frame of the [`Checkpoint`], which needs to point at the start of a
+/// frame or the end of the data
+fn checkpoint_offset(inner: &[u8], checkpoint: &Checkpoint) -> Result<usize, CheckpointError> {
+    let offset = checkpoint.offset();
+    let valid =
+        inner
+            .get(offset..)
+            .is_some_and(|rest| match FallibleFrameIterator::parse(rest).next() {
+                None | Some(Ok(_)) => true,
+                Some(Err(e)) => e.is_truncated(),
+            });
+    if !valid {
+        return Err(CheckpointError::InvalidFrame(checkpoint.frame()));
+    }
+    Ok(offset)
+}
+
 pub struct LazyParser<'b> {
     container: Container<'b>,
+    index: Option<FrameIndex>,
+    options: ParseOptions,
 }
 
 impl<'b> LazyParser<'b> {
     pub fn new(container: Container<'b>) -> Self {
-        Self { container }
+        Self {
+            container,
+            index: None,
+            options: ParseOptions::default(),
+        }
+    }
+
+    /// Configures how the iterators handle anomalies of the demo, see [`ParseOptions`]
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Uses the given index for seeking, instead of building a new one on every call to
+    /// [`LazyParser::seek`]
+    pub fn with_index(mut self, index: FrameIndex) -> Self {
+        self.index = Some(index);
+        self
     }
 
     pub fn file_header(&self) -> Option<crate::csgo_proto::CDemoFileHeader> {
@@ -31,109 +98,45 @@ impl<'b> LazyParser<'b> {
     pub fn file_info(&self) -> Option<crate::csgo_proto::CDemoFileInfo> {
         let mut buffer = Vec::new();
 
-        for frame in FrameIterator::parse(self.container.inner) {
-            if let crate::DemoCommand::FileInfo = frame.cmd {
-                let data = frame.decompress_with_buf(&mut buffer).ok()?;
-                let raw: crate::csgo_proto::CDemoFileInfo = prost::Message::decode(data).ok()?;
-                return Some(raw);
-            }
-        }
-        None
+        // Usually we can jump directly to the FileInfo, but if the header is broken we still want
+        // to find it by going through all the frames
+        let frame = match self.container.file_info_frame() {
+            Some(f) => f,
+            None => FrameIterator::parse(self.container.inner)
+                .find(|f| f.cmd == crate::DemoCommand::FileInfo)?,
+        };
+
+        let data = frame.decompress_with_buf(&mut buffer).ok()?;
+        let raw: crate::csgo_proto::CDemoFileInfo = prost::Message::decode(data).ok()?;
+        Some(raw)
     }
 
     pub fn player_info(&self) -> std::collections::HashMap<crate::UserId, crate::parser::Player> {
-        let mut result = std::collections::HashMap::new();
-
-        let mut buffer = Vec::new();
+        let mut players = PlayerCollector(std::collections::HashMap::new());
+        let mut engine = crate::parser::Engine::new(crate::parser::EntityFilter::disabled());
 
         for frame in FrameIterator::parse(self.container.inner) {
-            let packet = match frame.cmd {
-                crate::DemoCommand::Packet | crate::DemoCommand::SignonPacket => {
-                    let data = match frame.decompress_with_buf(&mut buffer) {
-                        Ok(d) => d,
-                        Err(_) => continue,
-                    };
-                    let raw: crate::csgo_proto::CDemoPacket = match prost::Message::decode(data) {
-                        Ok(d) => d,
-                        Err(_) => continue,
-                    };
-
-                    raw
-                }
-                crate::DemoCommand::FullPacket => {
-                    let data = match frame.decompress_with_buf(&mut buffer) {
-                        Ok(d) => d,
-                        Err(_) => continue,
-                    };
-                    let raw: crate::csgo_proto::CDemoFullPacket = match prost::Message::decode(data)
-                    {
-                        Ok(d) => d,
-                        Err(_) => continue,
-                    };
-
-                    match raw.packet {
-                        Some(p) => p,
-                        None => continue,
-                    }
-                }
-                _ => continue,
-            };
-
-            let mut bitreader = crate::bitreader::Bitreader::new(packet.data());
-
-            while bitreader.bits_remaining().unwrap_or(0) > 8 {
-                let msg_type = match bitreader.read_u_bit_var() {
-                    Ok(t) => t,
-                    Err(e) => break,
-                };
-                let size = match bitreader.read_varint() {
-                    Ok(s) => s,
-                    Err(e) => break,
-                };
-                let msg_bytes = match bitreader.read_n_bytes(size as usize) {
-                    Ok(s) => s,
-                    Err(e) => break,
-                };
-
-                assert_eq!(msg_bytes.len(), size as usize);
-
-                let net_msg_type =
-                    match crate::netmessagetypes::NetmessageType::try_from(msg_type as i32) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            dbg!(e);
-                            continue;
-                        }
-                    };
-
-                match net_msg_type {
-                    crate::netmessagetypes::NetmessageType::CS_UM_EndOfMatchAllPlayersData => {
-                        let raw: crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData =
-                            match prost::Message::decode(msg_bytes.as_slice()) {
-                                Ok(d) => d,
-                                Err(e) => continue,
-                            };
-
-                        for data in raw.allplayerdata {
-                            result.insert(
-                                crate::UserId(data.slot()),
-                                crate::parser::Player {
-                                    name: data.name.unwrap(),
-                                    xuid: data.xuid.unwrap(),
-                                    team: data.teamnumber.unwrap(),
-                                    color: data.playercolor.unwrap(),
-                                },
-                            );
-                        }
-                    }
-                    _unknown => {
-                        // dbg!(unknown);
-                    }
-                };
+            // Same as before, we want as many players as possible, even if some frames are broken
+            let _ = engine.handle_frame(&frame, &mut players);
+        }
+
+        players.0
+    }
+
+    /// Every player that was on the server at some point of the demo, which decodes the demo
+    /// with the [`ParseOptions`] of the parser
+    pub fn roster(&self) -> Result<crate::parser::Roster, ParseError> {
+        let mut engine = crate::parser::Engine::new(crate::parser::EntityFilter::disabled())
+            .with_options(self.options);
+
+        for frame in frames(self.container.inner) {
+            match frame {
+                Ok(frame) => engine.handle_frame(&frame, &mut EngineOnly)?,
+                Err(e) => return Err(engine.frame_error(e)),
             }
         }
 
-        result
+        Ok(engine.roster().clone())
     }
 
     pub fn events(&self) -> LazyEventIterator<'b> {
@@ -143,4 +146,107 @@ impl<'b> LazyParser<'b> {
     pub fn entities(&self) -> LazyEntityIterator<'b> {
         LazyEntityIterator::new(self)
     }
+
+    /// Returns a single iterator over both the events and the entities, in the order they
+    /// appear in the demo, which only needs to decode the demo once
+    pub fn stream(&self, filter: crate::parser::EntityFilter) -> LazyStreamIterator<'b> {
+        LazyStreamIterator::new(self, filter)
+    }
+
+    /// Returns an entity iterator starting at the given tick, which first yields the complete
+    /// state of all entities at that tick and then continues with the updates afterwards.
+    ///
+    /// Instead of decoding every tick before the target, this only replays the signon frames,
+    /// then starts at the closest FullPacket before the tick and plays the remaining updates
+    /// forward. Afterwards the [`string_tables`](LazyEntityIterator::string_tables) are the same
+    /// as when going through the demo up to that tick, as long as no table is created after the
+    /// signon. Such a table is unknown when the FullPacket is replayed, which is reported as a
+    /// [`Warning::InvalidStringTable`](crate::parser::Warning::InvalidStringTable) instead of
+    /// guessing how its updates are encoded
+    pub fn seek(&self, tick: i32) -> Result<LazyEntityIterator<'b>, SeekError> {
+        let built;
+        let index = match self.index.as_ref() {
+            Some(index) => index,
+            None => {
+                built = FrameIndex::build(self.container.inner).map_err(SeekError::BuildIndex)?;
+                &built
+            }
+        };
+
+        LazyEntityIterator::seek(self.container.inner, index, tick, self.options)
+    }
+
+    /// Returns an event iterator that continues right after the [`Checkpoint`], which was
+    /// created by any of the iterators of the same demo
+    pub fn resume_events(
+        &self,
+        checkpoint: &Checkpoint,
+    ) -> Result<LazyEventIterator<'b>, CheckpointError> {
+        let (engine, offset) = self.resume(checkpoint, EntityFilter::disabled())?;
+        Ok(LazyEventIterator::from_frames(std::iter::empty())
+            .with_engine(engine)
+            .with_clock(checkpoint)
+            .with_frames(frames(&self.container.inner[offset..])))
+    }
+
+    /// Returns an entity iterator that continues right after the [`Checkpoint`], which needs to
+    /// be created by an iterator with entities enabled
+    pub fn resume_entities(
+        &self,
+        checkpoint: &Checkpoint,
+    ) -> Result<LazyEntityIterator<'b>, CheckpointError> {
+        let (engine, offset) = self.resume(checkpoint, EntityFilter::all())?;
+        Ok(LazyEntityIterator::from_frames(std::iter::empty())
+            .with_engine(engine)
+            .with_frames(frames(&self.container.inner[offset..])))
+    }
+
+    /// Returns a stream that continues right after the [`Checkpoint`], see
+    /// [`LazyParser::stream`]
+    pub fn resume_stream(
+        &self,
+        checkpoint: &Checkpoint,
+        filter: EntityFilter,
+    ) -> Result<LazyStreamIterator<'b>, CheckpointError> {
+        let (engine, offset) = self.resume(checkpoint, filter)?;
+        Ok(
+            LazyStreamIterator::from_frames(std::iter::empty(), EntityFilter::disabled())
+                .with_engine(engine)
+                .with_frames(frames(&self.container.inner[offset..])),
+        )
+    }
+
+    /// Restores the engine of the checkpoint and checks the offset of its frame
+    fn resume(
+        &self,
+        checkpoint: &Checkpoint,
+        filter: EntityFilter,
+    ) -> Result<(Engine, usize), CheckpointError> {
+        let offset = checkpoint_offset(self.container.inner, checkpoint)?;
+        let engine = Engine::restore(checkpoint, filter)?.with_options(self.options);
+        Ok((engine, offset))
+    }
+}
+
+/// Ignores everything, for when only the state of the engine itself is needed
+struct EngineOnly;
+
+impl crate::parser::DemoHandler for EngineOnly {}
+
+/// Only collects the players
+struct PlayerCollector(std::collections::HashMap<crate::UserId, crate::parser::Player>);
+
+impl crate::parser::DemoHandler for PlayerCollector {
+    fn on_user_message(
+        &mut self,
+        _tick: u32,
+        msg_type: crate::NetmessageType,
+        data: &[u8],
+    ) -> Result<(), crate::parser::FirstPassError> {
+        if msg_type == crate::NetmessageType::CS_UM_EndOfMatchAllPlayersData {
+            // A broken message only means less players, not that the other messages are broken
+            let _ = crate::parser::update_players(data, &mut self.0);
+        }
+        Ok(())
+    }
 }
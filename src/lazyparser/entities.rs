@@ -1,341 +1,266 @@
 use crate::{
-    parser::{
-        decoder, entities, propcontroller, sendtables, update_entity, Class, FirstPassError, Paths,
-    },
-    DemoCommand, FrameIterator,
+    parser::{entities, DemoHandler, Engine, FirstPassError, ParseError},
+    Frame, FrameIterator, FrameParseError,
 };
 
 use std::collections::VecDeque;
 
 pub struct LazyEntityIterator<'b> {
-    buffer: Vec<u8>,
-    frames: FrameIterator<'b>,
-
-    current_tick: u32,
-    pending_entities: VecDeque<(u32, entities::EntityState)>,
-
-    paths: Paths,
-    baselines: std::collections::HashMap<u32, Vec<u8>>,
-    serializers: std::collections::HashMap<String, sendtables::Serializer>,
-    qf_mapper: decoder::QfMapper,
-    prop_controller: propcontroller::PropController,
-    entity_ctx: entities::EntityContext,
+    frames: Box<dyn Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b>,
+
+    engine: Engine,
+    pending_entities: EntityQueue,
 }
 
 impl<'b> LazyEntityIterator<'b> {
     pub(crate) fn new(parser: &super::LazyParser<'b>) -> Self {
+        let mut iter = Self::from_frames(std::iter::empty()).with_options(parser.options);
+        iter.frames = Box::new(super::frames(parser.container.inner));
+        iter
+    }
+
+    /// Creates a new iterator over the entities in the given frames, which allows for using any
+    /// source of frames, like a [`FrameIterator`](crate::FrameIterator)
+    pub fn from_frames<FI>(frames: FI) -> Self
+    where
+        FI: IntoIterator<Item = Frame<'b>>,
+        FI::IntoIter: Send + 'b,
+    {
+        Self {
+            frames: Box::new(frames.into_iter().map(Ok)),
+
+            engine: Engine::new(entities::EntityFilter::all()),
+            pending_entities: EntityQueue(VecDeque::with_capacity(64)),
+        }
+    }
+
+    /// Like [`from_frames`](Self::from_frames), but for a source of frames that can fail, like a
+    /// [`FrameReader`](crate::FrameReader), whose errors are returned by the iterator
+    pub fn from_fallible_frames<FI, E>(frames: FI) -> Self
+    where
+        FI: IntoIterator<Item = Result<Frame<'b>, E>>,
+        FI::IntoIter: Send + 'b,
+        E: Into<FrameParseError>,
+    {
         Self {
-            buffer: Vec::new(),
-            frames: FrameIterator::parse(parser.container.inner),
-
-            current_tick: 0,
-            pending_entities: VecDeque::with_capacity(64),
-
-            paths: Paths::new(),
-            baselines: std::collections::HashMap::new(),
-            serializers: std::collections::HashMap::new(),
-            qf_mapper: decoder::QfMapper {
-                idx: 0,
-                map: std::collections::HashMap::new(),
-            },
-            prop_controller: propcontroller::PropController::new(),
-            entity_ctx: entities::EntityContext {
-                entities: std::collections::HashMap::new(),
-                cls_to_class: std::collections::HashMap::new(),
-                filter: entities::EntityFilter::all(),
-            },
+            frames: Box::new(frames.into_iter().map(|frame| frame.map_err(Into::into))),
+
+            engine: Engine::new(entities::EntityFilter::all()),
+            pending_entities: EntityQueue(VecDeque::with_capacity(64)),
+        }
+    }
+
+    /// Configures how anomalies of the demo are handled, see
+    /// [`ParseOptions`](crate::parser::ParseOptions)
+    pub fn with_options(mut self, options: crate::parser::ParseOptions) -> Self {
+        self.engine = self.engine.with_options(options);
+        self
+    }
+
+    /// Captures the state of the parser, to later continue after the last frame that was parsed.
+    ///
+    /// Returns `None` while there are still entities of that frame left to be returned
+    pub fn checkpoint(&self) -> Option<crate::parser::Checkpoint> {
+        if !self.pending_entities.0.is_empty() {
+            return None;
         }
+        Some(self.engine.checkpoint())
+    }
+
+    pub(super) fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Replaces the frames of the iterator, for sources of frames that can fail
+    pub(super) fn with_frames<FI>(mut self, frames: FI) -> Self
+    where
+        FI: Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b,
+    {
+        self.frames = Box::new(frames);
+        self
+    }
+
+    /// The warnings recorded while parsing the entities so far
+    pub fn diagnostics(&self) -> &crate::parser::Diagnostics {
+        self.engine.diagnostics()
+    }
+
+    /// The string tables as of the last parsed frame
+    pub fn string_tables(&self) -> &crate::StringTables {
+        self.engine.string_tables()
+    }
+
+    /// The players of the demo up to the last parsed frame
+    pub fn roster(&self) -> &crate::parser::Roster {
+        self.engine.roster()
+    }
+
+    /// The complete state of the entity as of the last parsed frame, not only the props of its
+    /// last update
+    pub fn entity(&self, entity_id: i32) -> Option<entities::EntityState> {
+        self.engine.entity(entity_id)
     }
 }
 
-impl<'b> LazyEntityIterator<'b> {
-    fn inner_parse_packet(
-        raw: &crate::csgo_proto::CDemoPacket,
-        entity_ctx: &mut entities::EntityContext,
-        paths: &mut Paths,
-        qf_mapper: &mut decoder::QfMapper,
-        baselines: &mut std::collections::HashMap<u32, Vec<u8>>,
-        prop_controller: &propcontroller::PropController,
-        entity_states: &mut VecDeque<(u32, entities::EntityState)>,
-        current_tick: &mut u32,
+/// Queues up the entity states, until they are returned by the iterator
+struct EntityQueue(VecDeque<(u32, entities::EntityState)>);
+
+impl DemoHandler for EntityQueue {
+    fn on_entity(&mut self, tick: u32, state: entities::EntityState) -> Result<(), FirstPassError> {
+        self.0.push_back((tick, state));
+        Ok(())
+    }
+
+    fn on_entity_left_pvs(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
     ) -> Result<(), FirstPassError> {
-        let mut bitreader = crate::bitreader::Bitreader::new(raw.data());
-
-        let mut msg_bytes = Vec::new();
-
-        while bitreader.bits_remaining().unwrap_or(0) > 8 {
-            let msg_type = bitreader.read_u_bit_var()?;
-            let size = bitreader.read_varint()?;
-            msg_bytes.clear();
-            msg_bytes.resize(size as usize, 0);
-            bitreader.read_n_bytes_mut(size as usize, &mut msg_bytes)?;
-
-            assert_eq!(msg_bytes.len(), size as usize);
-
-            let net_msg_type =
-                match crate::netmessagetypes::NetmessageType::try_from(msg_type as i32) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        dbg!(e);
-                        continue;
-                    }
-                };
-
-            match net_msg_type {
-                crate::netmessagetypes::NetmessageType::net_Tick => {
-                    let raw: crate::csgo_proto::CnetMsgTick =
-                        prost::Message::decode(msg_bytes.as_slice())?;
-
-                    assert!(
-                        *current_tick <= raw.tick(),
-                        "Current Tick {} <= Tick Packet {}",
-                        *current_tick,
-                        raw.tick()
-                    );
-                    if raw.tick() > *current_tick {
-                        *current_tick = raw.tick();
-                    }
+        self.on_entity(tick, state)
+    }
+
+    fn on_entity_deleted(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
+}
+
+impl<'b> LazyEntityIterator<'b> {
+    /// Creates an iterator whose first entries are the state of every entity at the given tick,
+    /// followed by the normal updates of all the ticks afterwards
+    pub(super) fn seek(
+        inner: &'b [u8],
+        index: &crate::FrameIndex,
+        tick: i32,
+        options: crate::parser::ParseOptions,
+    ) -> Result<Self, super::SeekError> {
+        let (mut iter, replay_end) = Self::replay(inner, index, tick, options)?;
+        iter.frames = Box::new(super::frames(&inner[replay_end..]));
+        Ok(iter)
+    }
+
+    /// Creates an iterator without any frames, whose pending entities are the state at the given
+    /// tick, and the offset in `inner` at which the remaining frames start
+    pub(super) fn replay(
+        inner: &[u8],
+        index: &crate::FrameIndex,
+        tick: i32,
+        options: crate::parser::ParseOptions,
+    ) -> Result<(Self, usize), super::SeekError> {
+        let mut iter = Self::from_frames(std::iter::empty()).with_options(options);
+
+        let parse_frame = |offset: usize| {
+            let (rest, frame) = inner
+                .get(offset..)
+                .and_then(|data| Frame::parse(data).ok())
+                .ok_or(super::SeekError::InvalidIndex)?;
+            Ok((inner.len() - rest.len(), frame))
+        };
+
+        // Without a FullPacket before the tick, we have no snapshot to start from and need to
+        // replay everything from the start of the demo
+        let replay_start = match index.full_packet_before(tick) {
+            Some(full_packet) => {
+                if index.send_tables.is_empty() {
+                    return Err(super::SeekError::MissingSendTables);
                 }
-                // TODO
-                // How to handle these things?
-                crate::netmessagetypes::NetmessageType::svc_ClearAllStringTables => {}
-                crate::netmessagetypes::NetmessageType::svc_CreateStringTable => {}
-                crate::netmessagetypes::NetmessageType::svc_UpdateStringTable => {}
-                crate::netmessagetypes::NetmessageType::svc_PacketEntities => {
-                    let raw: crate::csgo_proto::CsvcMsgPacketEntities =
-                        prost::Message::decode(msg_bytes.as_slice())?;
-
-                    if entity_ctx.filter.enabled {
-                        let mut bitreader = crate::bitreader::Bitreader::new(raw.entity_data());
-                        let mut entity_id: i32 = -1;
-                        for _ in 0..raw.updated_entries() {
-                            entity_id += 1 + (bitreader.read_u_bit_var()? as i32);
-
-                            match bitreader.read_nbits(2)? {
-                                0b01 | 0b11 => {
-                                    entity_ctx.entities.remove(&entity_id);
-                                }
-                                0b10 => {
-                                    let cls =
-                                        entity_ctx.create_entity(entity_id, &mut bitreader)?;
-
-                                    if let Some(baseline_bytes) = baselines.get(&cls) {
-                                        let mut br =
-                                            crate::bitreader::Bitreader::new(baseline_bytes);
-
-                                        // TODO
-                                        // How should we handle is this?
-                                        let _state = update_entity(
-                                            entity_id,
-                                            &mut br,
-                                            entity_ctx,
-                                            paths,
-                                            qf_mapper,
-                                            prop_controller,
-                                        )?;
-                                    }
-
-                                    let state = update_entity(
-                                        entity_id,
-                                        &mut bitreader,
-                                        entity_ctx,
-                                        paths,
-                                        qf_mapper,
-                                        prop_controller,
-                                    )?;
-                                    if let Some(state) = state {
-                                        entity_states.push_back((*current_tick, state));
-                                    }
-                                }
-                                0b00 => {
-                                    if raw.has_pvs_vis_bits() > 0
-                                        && bitreader.read_nbits(2)? & 0x01 == 1
-                                    {
-                                        continue;
-                                    }
-
-                                    let state = update_entity(
-                                        entity_id,
-                                        &mut bitreader,
-                                        entity_ctx,
-                                        paths,
-                                        qf_mapper,
-                                        prop_controller,
-                                    )?;
-                                    if let Some(state) = state {
-                                        entity_states.push_back((*current_tick, state));
-                                    }
-                                }
-                                unknown => {
-                                    panic!("{:?}", unknown);
-                                }
-                            };
-                        }
-                    }
+                if index.class_info.is_empty() {
+                    return Err(super::SeekError::MissingClassInfo);
                 }
-                _ => {
-                    // dbg!(unknown);
+
+                // The signon frames contain the send tables, the classes and the
+                // svc_CreateStringTable messages. A FullPacket only contains the entries of the
+                // string tables, but later updates can only be decoded using the properties of
+                // the tables from when they were created. The packets after the signon are not
+                // needed, as the FullPacket re-creates all the entities
+                let signon_end = index
+                    .signon_end
+                    .map_or(full_packet.offset, |entry| entry.offset)
+                    .min(full_packet.offset);
+                let signon = inner
+                    .get(..signon_end)
+                    .ok_or(super::SeekError::InvalidIndex)?;
+                for frame in FrameIterator::parse(signon) {
+                    iter.handle_frame(frame).map_err(super::SeekError::Decode)?;
                 }
-            };
+
+                let (end, frame) = parse_frame(full_packet.offset)?;
+                iter.handle_frame(frame).map_err(super::SeekError::Decode)?;
+                iter.engine.set_position(full_packet.frame + 1, end);
+                end
+            }
+            None => 0,
+        };
+
+        let replay_end = index
+            .offset_after_tick(tick)
+            .unwrap_or(inner.len())
+            .max(replay_start);
+        for frame in FrameIterator::parse(&inner[replay_start..replay_end]) {
+            iter.handle_frame(frame).map_err(super::SeekError::Decode)?;
         }
 
-        Ok(())
+        iter.collapse_pending();
+
+        Ok((iter, replay_end))
+    }
+
+    fn handle_frame(&mut self, frame: Frame<'_>) -> Result<(), ParseError> {
+        self.engine.handle_frame(&frame, &mut self.pending_entities)
+    }
+
+    /// Collapses all the pending entity states into a single state per entity, containing the
+    /// current value of every prop, as if they were all created in the current tick.
+    ///
+    /// This is used after seeking, where only the state at the target tick is of interest and not
+    /// every update that lead to it
+    fn collapse_pending(&mut self) {
+        let mut order = Vec::new();
+        let mut last_op = std::collections::HashMap::new();
+        for (_, state) in self.pending_entities.0.drain(..) {
+            if last_op.insert(state.id, state.op).is_none() {
+                order.push(state.id);
+            }
+        }
+
+        for id in order {
+            // Entities that are not visible at the moment are left out, and the ones that were
+            // deleted no longer have a state
+            if last_op.get(&id) == Some(&entities::EntityOp::LeftPVS) {
+                continue;
+            }
+            if let Some(mut state) = self.engine.entity(id) {
+                state.op = entities::EntityOp::Created;
+                self.pending_entities
+                    .0
+                    .push_back((self.engine.current_tick(), state));
+            }
+        }
     }
 }
 
 impl<'b> Iterator for LazyEntityIterator<'b> {
-    type Item = Result<(u32, crate::parser::entities::EntityState), ()>;
+    type Item = Result<(u32, crate::parser::entities::EntityState), ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(tmp) = self.pending_entities.pop_front() {
+        if let Some(tmp) = self.pending_entities.0.pop_front() {
             return Some(Ok(tmp));
         }
 
-        while let Some(frame) = self.frames.next() {
-            match frame.cmd {
-                DemoCommand::SignonPacket | DemoCommand::Packet => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let raw: crate::csgo_proto::CDemoPacket = match prost::Message::decode(data) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    if let Err(e) = Self::inner_parse_packet(
-                        &raw,
-                        &mut self.entity_ctx,
-                        &mut self.paths,
-                        &mut self.qf_mapper,
-                        &mut self.baselines,
-                        &mut self.prop_controller,
-                        &mut self.pending_entities,
-                        &mut self.current_tick,
-                    ) {
-                        return Some(Err(()));
-                    }
-                }
-                DemoCommand::FullPacket => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let raw: crate::csgo_proto::CDemoFullPacket = match prost::Message::decode(data)
-                    {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    if let Some(packet) = raw.packet {
-                        if let Err(e) = Self::inner_parse_packet(
-                            &packet,
-                            &mut self.entity_ctx,
-                            &mut self.paths,
-                            &mut self.qf_mapper,
-                            &mut self.baselines,
-                            &mut self.prop_controller,
-                            &mut self.pending_entities,
-                            &mut self.current_tick,
-                        ) {
-                            return Some(Err(()));
-                        }
-                    }
-                }
-
-                // Handling all the "meta" related stuff
-                DemoCommand::StringTables => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let raw: crate::csgo_proto::CDemoStringTables =
-                        match prost::Message::decode(data) {
-                            Ok(d) => d,
-                            Err(e) => return Some(Err(())),
-                        };
-
-                    for table in raw.tables.into_iter() {
-                        if table.table_name() == "instancebaseline" {
-                            for item in table.items.into_iter() {
-                                let k = item.str().parse::<u32>().unwrap_or(u32::MAX);
-                                self.baselines.insert(k, item.data.unwrap_or(Vec::new()));
-                            }
-                        }
-                    }
-                }
-                DemoCommand::SendTables => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let tables: crate::csgo_proto::CDemoSendTables =
-                        match prost::Message::decode(data) {
-                            Ok(d) => d,
-                            Err(e) => return Some(Err(())),
-                        };
-
-                    let mut bitreader = crate::bitreader::Bitreader::new(tables.data());
-
-                    let n_bytes = match bitreader.read_varint() {
-                        Ok(b) => b,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let bytes = match bitreader.read_n_bytes(n_bytes as usize) {
-                        Ok(b) => b,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    let serializer_msg: crate::csgo_proto::CsvcMsgFlattenedSerializer =
-                        match prost::Message::decode(bytes.as_slice()) {
-                            Ok(d) => d,
-                            Err(e) => return Some(Err(())),
-                        };
-
-                    // std::fs::write("send_table.b", bytes.as_slice());
-
-                    assert!(self.serializers.is_empty());
-                    self.serializers = match sendtables::get_serializers(
-                        &serializer_msg,
-                        &mut self.qf_mapper,
-                        &mut self.prop_controller,
-                    ) {
-                        Ok(s) => s,
-                        Err(e) => return Some(Err(())),
-                    };
-                }
-                DemoCommand::ClassInfo => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-                    let raw: crate::csgo_proto::CDemoClassInfo = match prost::Message::decode(data)
-                    {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    self.entity_ctx.cls_to_class.clear();
-
-                    for class_t in raw.classes {
-                        let cls_id = class_t.class_id();
-                        let network_name = class_t.network_name();
-
-                        if let Some(ser) = self.serializers.remove(network_name) {
-                            self.entity_ctx.cls_to_class.insert(
-                                cls_id as u32,
-                                Class {
-                                    name: network_name.into(),
-                                    serializer: ser,
-                                },
-                            );
-                        }
-                    }
-                }
-                _ => continue,
+        for frame in self.frames.by_ref() {
+            let result = match frame {
+                Ok(frame) => self.engine.handle_frame(&frame, &mut self.pending_entities),
+                Err(e) => Err(self.engine.frame_error(e)),
             };
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
 
-            if let Some(tmp) = self.pending_entities.pop_front() {
+            if let Some(tmp) = self.pending_entities.0.pop_front() {
                 return Some(Ok(tmp));
             }
         }
@@ -1,209 +1,189 @@
-use crate::{parser::GameEventMapping, DemoEvent, FrameIterator};
+use crate::{
+    packet::{EventClock, TimedEvent},
+    parser::{DemoHandler, Engine, EntityFilter, FirstPassError, ParseError},
+    DemoEvent, Frame, FrameParseError,
+};
 
 use std::collections::VecDeque;
 
 pub struct LazyEventIterator<'b> {
-    pub(super) buffer: Vec<u8>,
-    pub(super) frames: FrameIterator<'b>,
+    frames: Box<dyn Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b>,
 
-    pub(super) pending_events: VecDeque<crate::DemoEvent>,
-    pub(super) event_mapper: GameEventMapping,
+    engine: Engine,
+    pending_events: EventQueue,
 }
 
 impl<'b> LazyEventIterator<'b> {
     pub(crate) fn new(parser: &super::LazyParser<'b>) -> Self {
         Self {
-            buffer: Vec::new(),
-            frames: FrameIterator::parse(parser.container.inner),
+            frames: Box::new(super::frames(parser.container.inner)),
 
-            pending_events: VecDeque::with_capacity(64),
-            event_mapper: crate::parser::GameEventMapping {
-                mapping: std::collections::HashMap::new(),
-            },
+            engine: Engine::new(EntityFilter::disabled()).with_options(parser.options),
+            pending_events: EventQueue::new(),
         }
     }
+
+    /// Creates a new iterator over the events in the given frames, which allows for using any
+    /// source of frames, like a [`FrameIterator`](crate::FrameIterator)
+    pub fn from_frames<FI>(frames: FI) -> Self
+    where
+        FI: IntoIterator<Item = Frame<'b>>,
+        FI::IntoIter: Send + 'b,
+    {
+        Self {
+            frames: Box::new(frames.into_iter().map(Ok)),
+
+            engine: Engine::new(EntityFilter::disabled()),
+            pending_events: EventQueue::new(),
+        }
+    }
+
+    /// Like [`from_frames`](Self::from_frames), but for a source of frames that can fail, like a
+    /// [`FrameReader`](crate::FrameReader), whose errors are returned by the iterator
+    pub fn from_fallible_frames<FI, E>(frames: FI) -> Self
+    where
+        FI: IntoIterator<Item = Result<Frame<'b>, E>>,
+        FI::IntoIter: Send + 'b,
+        E: Into<FrameParseError>,
+    {
+        Self {
+            frames: Box::new(frames.into_iter().map(|frame| frame.map_err(Into::into))),
+
+            engine: Engine::new(EntityFilter::disabled()),
+            pending_events: EventQueue::new(),
+        }
+    }
+
+    /// Configures how anomalies of the demo are handled, see
+    /// [`ParseOptions`](crate::parser::ParseOptions)
+    pub fn with_options(mut self, options: crate::parser::ParseOptions) -> Self {
+        self.engine = self.engine.with_options(options);
+        self
+    }
+
+    /// Captures the state of the parser, to later continue after the last frame that was parsed.
+    ///
+    /// Returns `None` while there are still events of that frame left to be returned
+    pub fn checkpoint(&self) -> Option<crate::parser::Checkpoint> {
+        if !self.pending_events.events.is_empty() {
+            return None;
+        }
+
+        let mut checkpoint = self.engine.checkpoint();
+        checkpoint.seq = self.pending_events.clock.seq();
+        Some(checkpoint)
+    }
+
+    pub(super) fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Continues the [`seq`](TimedEvent::seq) of the events after the checkpoint
+    pub(super) fn with_clock(mut self, checkpoint: &crate::parser::Checkpoint) -> Self {
+        self.pending_events.clock = EventClock::resume(checkpoint.tick(), checkpoint.seq);
+        self
+    }
+
+    /// Replaces the frames of the iterator, for sources of frames that can fail
+    pub(super) fn with_frames<FI>(mut self, frames: FI) -> Self
+    where
+        FI: Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b,
+    {
+        self.frames = Box::new(frames);
+        self
+    }
+
+    /// The warnings recorded while parsing the events so far
+    pub fn diagnostics(&self) -> &crate::parser::Diagnostics {
+        self.engine.diagnostics()
+    }
+
+    /// The string tables as of the last parsed frame
+    pub fn string_tables(&self) -> &crate::StringTables {
+        self.engine.string_tables()
+    }
+
+    /// The players of the demo up to the last parsed frame
+    pub fn roster(&self) -> &crate::parser::Roster {
+        self.engine.roster()
+    }
 }
 
-impl<'b> LazyEventIterator<'b> {
-    fn inner_parse_packet(
-        raw: &crate::csgo_proto::CDemoPacket,
-        events: &mut VecDeque<DemoEvent>,
-        event_mapper: &mut GameEventMapping,
-    ) -> Result<(), ()> {
-        let mut bitreader = crate::bitreader::Bitreader::new(raw.data());
-
-        while bitreader.bits_remaining().unwrap_or(0) > 8 {
-            let msg_type = bitreader.read_u_bit_var().map_err(|e| ())?;
-            let size = bitreader.read_varint().map_err(|e| ())?;
-            let msg_bytes = bitreader.read_n_bytes(size as usize).map_err(|e| ())?;
-
-            assert_eq!(msg_bytes.len(), size as usize);
-
-            let net_msg_type =
-                match crate::netmessagetypes::NetmessageType::try_from(msg_type as i32) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        dbg!(e);
-                        continue;
-                    }
-                };
-
-            match net_msg_type {
-                crate::netmessagetypes::NetmessageType::GE_Source1LegacyGameEventList => {
-                    let event_list: crate::csgo_proto::CsvcMsgGameEventList =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    event_mapper.mapping.clear();
-                    for event in event_list.descriptors {
-                        event_mapper
-                            .mapping
-                            .insert(event.eventid(), (event.name().to_owned(), event.keys));
-                    }
-                }
-                crate::netmessagetypes::NetmessageType::svc_ServerInfo => {
-                    let raw: crate::csgo_proto::CsvcMsgServerInfo =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    events.push_back(DemoEvent::ServerInfo(Box::new(raw)));
-                }
-                crate::netmessagetypes::NetmessageType::net_Tick => {
-                    let raw: crate::csgo_proto::CnetMsgTick =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    events.push_back(DemoEvent::Tick(Box::new(raw)));
-                }
-                crate::netmessagetypes::NetmessageType::GE_Source1LegacyGameEvent => {
-                    let raw: crate::csgo_proto::CMsgSource1LegacyGameEvent =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    match event_mapper.mapping.get(&raw.eventid()) {
-                        Some((name, keys)) => {
-                            match crate::game_event::EVENT_PARSERS.get(name) {
-                                Some(parser) => {
-                                    let parsed = parser
-                                        .parse(keys.as_slice(), raw.clone())
-                                        .map_err(|e| ())?;
-
-                                    events.push_back(DemoEvent::GameEvent(Box::new(parsed)));
-                                }
-                                None => {
-                                    println!("No parser for {:?}", name);
-                                }
-                            };
-                        }
-                        None => {
-                            println!("Unknown Event - ID: {}", raw.eventid());
-                        }
-                    };
-                }
-                crate::netmessagetypes::NetmessageType::CS_UM_ServerRankUpdate => {
-                    let raw: crate::csgo_proto::CcsUsrMsgServerRankUpdate =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    events.push_back(DemoEvent::RankUpdate(Box::new(raw)));
-                }
-                crate::netmessagetypes::NetmessageType::CS_UM_ServerRankRevealAll => {
-                    let raw: crate::csgo_proto::CcsUsrMsgServerRankRevealAll =
-                        prost::Message::decode(msg_bytes.as_slice()).map_err(|e| ())?;
-
-                    events.push_back(DemoEvent::RankReveal(Box::new(raw)));
-                }
-                crate::netmessagetypes::NetmessageType::net_SignonState
-                | crate::netmessagetypes::NetmessageType::svc_ClearAllStringTables
-                | crate::netmessagetypes::NetmessageType::svc_CreateStringTable
-                | crate::netmessagetypes::NetmessageType::svc_UpdateStringTable
-                | crate::netmessagetypes::NetmessageType::net_SetConVar
-                | crate::netmessagetypes::NetmessageType::svc_ClassInfo
-                | crate::netmessagetypes::NetmessageType::svc_VoiceInit
-                | crate::netmessagetypes::NetmessageType::svc_PacketEntities
-                | crate::netmessagetypes::NetmessageType::svc_UserCmds
-                | crate::netmessagetypes::NetmessageType::GE_SosStartSoundEvent
-                | crate::netmessagetypes::NetmessageType::GE_SosStopSoundEvent
-                | crate::netmessagetypes::NetmessageType::CS_GE_PlayerAnimationEvent
-                | crate::netmessagetypes::NetmessageType::CS_GE_RadioIconEvent
-                | crate::netmessagetypes::NetmessageType::CS_GE_FireBullets
-                | crate::netmessagetypes::NetmessageType::UM_SayText2
-                | crate::netmessagetypes::NetmessageType::CS_UM_XpUpdate
-                | crate::netmessagetypes::NetmessageType::CS_UM_WeaponSound
-                | crate::netmessagetypes::NetmessageType::CS_UM_RadioText
-                | crate::netmessagetypes::NetmessageType::TE_WorldDecal
-                | crate::netmessagetypes::NetmessageType::TE_EffectDispatch
-                | crate::netmessagetypes::NetmessageType::CS_UM_EndOfMatchAllPlayersData
-                | crate::netmessagetypes::NetmessageType::TE_PhysicsProp
-                | crate::netmessagetypes::NetmessageType::UM_TextMsg
-                | crate::netmessagetypes::NetmessageType::CS_UM_VoteFailed
-                | crate::netmessagetypes::NetmessageType::net_SpawnGroup_Load
-                | crate::netmessagetypes::NetmessageType::CS_UM_MatchEndConditions
-                | crate::netmessagetypes::NetmessageType::TE_Explosion => {}
-                _unknown => {
-                    // dbg!(unknown);
-                }
-            };
+/// Queues up the events, until they are returned by the iterator
+struct EventQueue {
+    events: VecDeque<TimedEvent>,
+    clock: EventClock,
+}
+
+impl EventQueue {
+    fn new() -> Self {
+        Self {
+            events: VecDeque::with_capacity(64),
+            clock: EventClock::default(),
         }
+    }
+
+    fn push(&mut self, tick: u32, event: DemoEvent) {
+        let event = self.clock.timed(tick, event);
+        self.events.push_back(event);
+    }
+}
 
+impl DemoHandler for EventQueue {
+    fn on_tick(&mut self, tick: crate::csgo_proto::CnetMsgTick) -> Result<(), FirstPassError> {
+        self.push(tick.tick(), DemoEvent::Tick(Box::new(tick)));
+        Ok(())
+    }
+
+    fn on_server_info(
+        &mut self,
+        info: crate::csgo_proto::CsvcMsgServerInfo,
+    ) -> Result<(), FirstPassError> {
+        self.push(self.clock.tick(), DemoEvent::ServerInfo(Box::new(info)));
+        Ok(())
+    }
+
+    fn on_game_event(
+        &mut self,
+        tick: u32,
+        event: crate::game_event::GameEvent,
+    ) -> Result<(), FirstPassError> {
+        self.push(tick, DemoEvent::GameEvent(Box::new(event)));
+        Ok(())
+    }
+
+    fn on_user_message(
+        &mut self,
+        tick: u32,
+        msg_type: crate::NetmessageType,
+        data: &[u8],
+    ) -> Result<(), FirstPassError> {
+        if let Some(event) = crate::parser::decode_user_message_event(msg_type, data)? {
+            self.push(tick, event);
+        }
         Ok(())
     }
 }
 
 impl<'b> Iterator for LazyEventIterator<'b> {
-    type Item = Result<crate::DemoEvent, ()>;
+    type Item = Result<TimedEvent, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        use crate::DemoCommand;
-
-        if let Some(event) = self.pending_events.pop_front() {
+        if let Some(event) = self.pending_events.events.pop_front() {
             return Some(Ok(event));
         }
 
-        while let Some(frame) = self.frames.next() {
-            match frame.cmd {
-                DemoCommand::SignonPacket | DemoCommand::Packet => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    let raw: crate::csgo_proto::CDemoPacket = match prost::Message::decode(data) {
-                        Ok(p) => p,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    if let Err(e) = Self::inner_parse_packet(
-                        &raw,
-                        &mut self.pending_events,
-                        &mut self.event_mapper,
-                    ) {
-                        return Some(Err(()));
-                    }
-                }
-                DemoCommand::FullPacket => {
-                    let data = match frame.decompress_with_buf(&mut self.buffer) {
-                        Ok(d) => d,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    let raw: crate::csgo_proto::CDemoFullPacket = match prost::Message::decode(data)
-                    {
-                        Ok(p) => p,
-                        Err(e) => return Some(Err(())),
-                    };
-
-                    // TODO
-
-                    if let Some(packet) = raw.packet {
-                        if let Err(e) = Self::inner_parse_packet(
-                            &packet,
-                            &mut self.pending_events,
-                            &mut self.event_mapper,
-                        ) {
-                            return Some(Err(()));
-                        }
-                    }
-                }
-                _ => {}
+        for frame in self.frames.by_ref() {
+            let result = match frame {
+                Ok(frame) => self.engine.handle_frame(&frame, &mut self.pending_events),
+                Err(e) => Err(self.engine.frame_error(e)),
             };
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
 
-            if let Some(event) = self.pending_events.pop_front() {
+            if let Some(event) = self.pending_events.events.pop_front() {
                 return Some(Ok(event));
             }
         }
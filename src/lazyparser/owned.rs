@@ -0,0 +1,287 @@
+use crate::{
+    parser::{Checkpoint, CheckpointError, EntityFilter, ParseError, ParseOptions},
+    Container, FallibleFrameIterator, Frame, FrameIndex, FrameParseError, ParseContainerError,
+    Truncation,
+};
+
+use std::sync::Arc;
+
+use super::{LazyEntityIterator, LazyEventIterator, LazyParser, LazyStreamIterator, SeekError};
+
+/// Like the [`LazyParser`], but owns the data of the demo, so it and all of its iterators are
+/// `Send + 'static` and can be stored or moved to other threads independently of the buffer the
+/// demo was read into.
+///
+/// The data is shared between the parser and its iterators, so creating them is cheap, but
+/// every frame read by an iterator is copied out of the shared data
+pub struct OwnedLazyParser {
+    data: Arc<[u8]>,
+    file_info_offset: u32,
+    spawngroups_offset: u32,
+    /// The length of [`Container::inner`], which starts right after the header
+    inner_len: usize,
+    truncation: Option<Truncation>,
+
+    index: Option<FrameIndex>,
+    options: ParseOptions,
+}
+
+impl OwnedLazyParser {
+    /// Parses the container of the demo, see [`Container::parse`]
+    pub fn new(data: impl Into<Arc<[u8]>>) -> Result<Self, ParseContainerError> {
+        let data = data.into();
+        let container = Container::parse(&data)?;
+        Ok(Self::from_container(&container, data.clone()))
+    }
+
+    /// Parses the container of the demo, while also accepting demos whose data does not match
+    /// the length in the header, see [`Container::parse_lenient`]
+    pub fn new_lenient(data: impl Into<Arc<[u8]>>) -> Result<Self, ParseContainerError> {
+        let data = data.into();
+        let container = Container::parse_lenient(&data)?;
+        Ok(Self::from_container(&container, data.clone()))
+    }
+
+    fn from_container(container: &Container<'_>, data: Arc<[u8]>) -> Self {
+        Self {
+            file_info_offset: container.file_info_offset,
+            spawngroups_offset: container.spawngroups_offset,
+            inner_len: container.inner.len(),
+            truncation: container.truncation,
+            data,
+
+            index: None,
+            options: ParseOptions::default(),
+        }
+    }
+
+    /// Configures how the iterators handle anomalies of the demo, see [`ParseOptions`]
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Uses the given index for seeking, instead of building a new one on every call to
+    /// [`OwnedLazyParser::seek`]
+    pub fn with_index(mut self, index: FrameIndex) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// The container of the demo, borrowing the shared data
+    pub fn container(&self) -> Container<'_> {
+        Container {
+            magic: core::str::from_utf8(&self.data[..crate::MAGIC.len()])
+                .expect("We already checked that the magic matches the expected one"),
+            file_info_offset: self.file_info_offset,
+            spawngroups_offset: self.spawngroups_offset,
+            inner: self.inner(),
+            truncation: self.truncation,
+        }
+    }
+
+    /// A [`LazyParser`] borrowing the shared data
+    pub fn parser(&self) -> LazyParser<'_> {
+        let parser = LazyParser::new(self.container()).with_options(self.options);
+        match self.index.clone() {
+            Some(index) => parser.with_index(index),
+            None => parser,
+        }
+    }
+
+    fn inner(&self) -> &[u8] {
+        &self.data[crate::container::HEADER_SIZE..][..self.inner_len]
+    }
+
+    fn frames(&self, offset: usize) -> SharedFrames {
+        SharedFrames {
+            data: self.data.clone(),
+            offset: crate::container::HEADER_SIZE + offset,
+            end: crate::container::HEADER_SIZE + self.inner_len,
+        }
+    }
+
+    pub fn file_header(&self) -> Option<crate::csgo_proto::CDemoFileHeader> {
+        self.parser().file_header()
+    }
+
+    pub fn file_info(&self) -> Option<crate::csgo_proto::CDemoFileInfo> {
+        self.parser().file_info()
+    }
+
+    pub fn player_info(&self) -> std::collections::HashMap<crate::UserId, crate::parser::Player> {
+        self.parser().player_info()
+    }
+
+    /// See [`LazyParser::roster`]
+    pub fn roster(&self) -> Result<crate::parser::Roster, ParseError> {
+        self.parser().roster()
+    }
+
+    pub fn events(&self) -> LazyEventIterator<'static> {
+        LazyEventIterator::from_frames(std::iter::empty())
+            .with_options(self.options)
+            .with_frames(self.frames(0))
+    }
+
+    pub fn entities(&self) -> LazyEntityIterator<'static> {
+        LazyEntityIterator::from_frames(std::iter::empty())
+            .with_options(self.options)
+            .with_frames(self.frames(0))
+    }
+
+    /// See [`LazyParser::stream`]
+    pub fn stream(&self, filter: EntityFilter) -> LazyStreamIterator<'static> {
+        LazyStreamIterator::from_frames(std::iter::empty(), filter)
+            .with_options(self.options)
+            .with_frames(self.frames(0))
+    }
+
+    /// See [`LazyParser::seek`]
+    pub fn seek(&self, tick: i32) -> Result<LazyEntityIterator<'static>, SeekError> {
+        let built;
+        let index = match self.index.as_ref() {
+            Some(index) => index,
+            None => {
+                built = FrameIndex::build(self.inner()).map_err(SeekError::BuildIndex)?;
+                &built
+            }
+        };
+
+        let (iter, replay_end) =
+            LazyEntityIterator::replay(self.inner(), index, tick, self.options)?;
+        Ok(iter.with_frames(self.frames(replay_end)))
+    }
+
+    /// See [`LazyParser::resume_events`]
+    pub fn resume_events(
+        &self,
+        checkpoint: &Checkpoint,
+    ) -> Result<LazyEventIterator<'static>, CheckpointError> {
+        let (engine, offset) = self.parser().resume(checkpoint, EntityFilter::disabled())?;
+        Ok(LazyEventIterator::from_frames(std::iter::empty())
+            .with_engine(engine)
+            .with_clock(checkpoint)
+            .with_frames(self.frames(offset)))
+    }
+
+    /// See [`LazyParser::resume_entities`]
+    pub fn resume_entities(
+        &self,
+        checkpoint: &Checkpoint,
+    ) -> Result<LazyEntityIterator<'static>, CheckpointError> {
+        let (engine, offset) = self.parser().resume(checkpoint, EntityFilter::all())?;
+        Ok(LazyEntityIterator::from_frames(std::iter::empty())
+            .with_engine(engine)
+            .with_frames(self.frames(offset)))
+    }
+
+    /// See [`LazyParser::resume_stream`]
+    pub fn resume_stream(
+        &self,
+        checkpoint: &Checkpoint,
+        filter: EntityFilter,
+    ) -> Result<LazyStreamIterator<'static>, CheckpointError> {
+        let (engine, offset) = self.parser().resume(checkpoint, filter)?;
+        Ok(
+            LazyStreamIterator::from_frames(std::iter::empty(), EntityFilter::disabled())
+                .with_engine(engine)
+                .with_frames(self.frames(offset)),
+        )
+    }
+}
+
+/// The frames in the shared data, which are copied out one at a time, with the same handling of
+/// errors as [`super::frames`]
+struct SharedFrames {
+    data: Arc<[u8]>,
+    offset: usize,
+    end: usize,
+}
+
+impl Iterator for SharedFrames {
+    type Item = Result<Frame<'static>, FrameParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frames = FallibleFrameIterator::parse(&self.data[self.offset..self.end]);
+        match frames.next()? {
+            Ok((_, frame)) => {
+                self.offset += frames.offset();
+                Some(Ok(Frame {
+                    cmd: frame.cmd,
+                    tick: frame.tick,
+                    compressed: frame.compressed,
+                    inner: std::borrow::Cow::Owned(frame.inner.into_owned()),
+                }))
+            }
+            Err(e) => {
+                self.offset = self.end;
+                if e.is_truncated() {
+                    None
+                } else {
+                    Some(Err(e.error))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send<T: Send + 'static>(_: &T) {}
+
+    /// Builds a demo from the frames, where the FileInfo frame is the last one and starts at the
+    /// given offset into the frames
+    fn demo(file_info_offset: usize, frames: &[u8]) -> Vec<u8> {
+        let mut data = crate::MAGIC.to_vec();
+        data.extend(((crate::container::HEADER_SIZE + file_info_offset) as u32).to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend(frames);
+        data
+    }
+
+    #[test]
+    fn iterators_are_send() {
+        // A single empty FileInfo frame
+        let parser = OwnedLazyParser::new(demo(0, &[2, 0, 0])).unwrap();
+
+        let events = parser.events();
+        assert_send(&events);
+        assert_send(&parser.entities());
+        assert_send(&parser.stream(EntityFilter::all()));
+        assert_send(&parser);
+
+        let handle = std::thread::spawn(move || events.count());
+        assert_eq!(0, handle.join().unwrap());
+    }
+
+    #[test]
+    fn shared_frames() {
+        // An empty Packet frame, followed by a broken frame and an empty FileInfo frame
+        let parser = OwnedLazyParser::new(demo(7, &[7, 0, 0, 0x7f, 0, 1, 0, 2, 0, 0])).unwrap();
+
+        let mut frames = parser.frames(0);
+        let frame = frames.next().unwrap().unwrap();
+        assert_eq!(crate::DemoCommand::Packet, frame.cmd);
+        assert!(matches!(
+            frames.next(),
+            Some(Err(FrameParseError::ParseDemoCommand(_)))
+        ));
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn roster_broken_frame() {
+        // Same as above, an empty Packet frame followed by a broken frame
+        let parser = OwnedLazyParser::new(demo(7, &[7, 0, 0, 0x7f, 0, 1, 0, 2, 0, 0])).unwrap();
+
+        let err = parser.roster().unwrap_err();
+        assert!(matches!(
+            err.error,
+            crate::parser::FirstPassError::Frame(FrameParseError::ParseDemoCommand(_))
+        ));
+        assert_eq!(Some(1), err.context.frame);
+    }
+}
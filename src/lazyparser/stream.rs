@@ -0,0 +1,261 @@
+use crate::{
+    parser::{entities, DemoHandler, Engine, EntityFilter, FirstPassError, ParseError},
+    DemoEvent, Frame, FrameParseError,
+};
+
+use std::collections::VecDeque;
+
+/// A single item of a [`LazyStreamIterator`]
+#[derive(Debug, PartialEq)]
+pub enum StreamItem {
+    /// A game event, user message or any of the other [`DemoEvent`]s
+    Event(DemoEvent),
+    /// Every user message as it is stored in the demo, which comes before its
+    /// [`Event`](Self::Event) if it is decoded into one
+    UserMessage {
+        msg_type: crate::NetmessageType,
+        data: Vec<u8>,
+    },
+    /// Anything that happened to an entity, see [`EntityState::op`](entities::EntityState::op)
+    Entity(entities::EntityState),
+    /// The entries of a string table that were added or changed
+    StringTableUpdate {
+        table: String,
+        entries: Vec<crate::StringTableEntry>,
+    },
+}
+
+/// Iterates over the events and entity updates of a demo together, in the exact order of the
+/// messages in the demo
+pub struct LazyStreamIterator<'b> {
+    frames: Box<dyn Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b>,
+
+    engine: Engine,
+    pending: StreamQueue,
+}
+
+impl<'b> LazyStreamIterator<'b> {
+    pub(crate) fn new(parser: &super::LazyParser<'b>, filter: EntityFilter) -> Self {
+        Self {
+            frames: Box::new(super::frames(parser.container.inner)),
+
+            engine: Engine::new(filter).with_options(parser.options),
+            pending: StreamQueue::new(),
+        }
+    }
+
+    /// Creates a new iterator over the events and entities in the given frames, which allows for
+    /// using any source of frames, like a [`FrameIterator`](crate::FrameIterator)
+    pub fn from_frames<FI>(frames: FI, filter: EntityFilter) -> Self
+    where
+        FI: IntoIterator<Item = Frame<'b>>,
+        FI::IntoIter: Send + 'b,
+    {
+        Self {
+            frames: Box::new(frames.into_iter().map(Ok)),
+
+            engine: Engine::new(filter),
+            pending: StreamQueue::new(),
+        }
+    }
+
+    /// Like [`from_frames`](Self::from_frames), but for a source of frames that can fail, like a
+    /// [`FrameReader`](crate::FrameReader), whose errors are returned by the iterator
+    pub fn from_fallible_frames<FI, E>(frames: FI, filter: EntityFilter) -> Self
+    where
+        FI: IntoIterator<Item = Result<Frame<'b>, E>>,
+        FI::IntoIter: Send + 'b,
+        E: Into<FrameParseError>,
+    {
+        Self {
+            frames: Box::new(frames.into_iter().map(|frame| frame.map_err(Into::into))),
+
+            engine: Engine::new(filter),
+            pending: StreamQueue::new(),
+        }
+    }
+
+    /// Configures how anomalies of the demo are handled, see
+    /// [`ParseOptions`](crate::parser::ParseOptions)
+    pub fn with_options(mut self, options: crate::parser::ParseOptions) -> Self {
+        self.engine = self.engine.with_options(options);
+        self
+    }
+
+    /// Captures the state of the parser, to later continue after the last frame that was parsed.
+    ///
+    /// Returns `None` while there are still items of that frame left to be returned
+    pub fn checkpoint(&self) -> Option<crate::parser::Checkpoint> {
+        if !self.pending.items.is_empty() {
+            return None;
+        }
+        Some(self.engine.checkpoint())
+    }
+
+    pub(super) fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Replaces the frames of the iterator, for sources of frames that can fail
+    pub(super) fn with_frames<FI>(mut self, frames: FI) -> Self
+    where
+        FI: Iterator<Item = Result<Frame<'b>, FrameParseError>> + Send + 'b,
+    {
+        self.frames = Box::new(frames);
+        self
+    }
+
+    /// The warnings recorded while parsing the demo so far
+    pub fn diagnostics(&self) -> &crate::parser::Diagnostics {
+        self.engine.diagnostics()
+    }
+
+    /// The string tables as of the last parsed frame
+    pub fn string_tables(&self) -> &crate::StringTables {
+        self.engine.string_tables()
+    }
+
+    /// The players of the demo up to the last parsed frame
+    pub fn roster(&self) -> &crate::parser::Roster {
+        self.engine.roster()
+    }
+
+    /// The complete state of the entity as of the last parsed frame, not only the props of its
+    /// last update
+    pub fn entity(&self, entity_id: i32) -> Option<entities::EntityState> {
+        self.engine.entity(entity_id)
+    }
+}
+
+/// Queues up the items, until they are returned by the iterator
+struct StreamQueue {
+    items: VecDeque<(u32, StreamItem)>,
+    /// The tick of the last `net_Tick`, for the items that are not passed a tick
+    tick: u32,
+}
+
+impl StreamQueue {
+    fn new() -> Self {
+        Self {
+            items: VecDeque::with_capacity(64),
+            tick: 0,
+        }
+    }
+}
+
+impl DemoHandler for StreamQueue {
+    fn on_tick(&mut self, tick: crate::csgo_proto::CnetMsgTick) -> Result<(), FirstPassError> {
+        self.tick = tick.tick();
+        self.items.push_back((
+            self.tick,
+            StreamItem::Event(DemoEvent::Tick(Box::new(tick))),
+        ));
+        Ok(())
+    }
+
+    fn on_server_info(
+        &mut self,
+        info: crate::csgo_proto::CsvcMsgServerInfo,
+    ) -> Result<(), FirstPassError> {
+        self.items.push_back((
+            self.tick,
+            StreamItem::Event(DemoEvent::ServerInfo(Box::new(info))),
+        ));
+        Ok(())
+    }
+
+    fn on_game_event(
+        &mut self,
+        tick: u32,
+        event: crate::game_event::GameEvent,
+    ) -> Result<(), FirstPassError> {
+        self.items.push_back((
+            tick,
+            StreamItem::Event(DemoEvent::GameEvent(Box::new(event))),
+        ));
+        Ok(())
+    }
+
+    fn on_entity(&mut self, tick: u32, state: entities::EntityState) -> Result<(), FirstPassError> {
+        self.items.push_back((tick, StreamItem::Entity(state)));
+        Ok(())
+    }
+
+    fn on_entity_left_pvs(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
+
+    fn on_entity_deleted(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
+
+    fn on_string_table_update(
+        &mut self,
+        tick: u32,
+        table: &crate::StringTable,
+        entries: &[crate::StringTableEntry],
+    ) -> Result<(), FirstPassError> {
+        self.items.push_back((
+            tick,
+            StreamItem::StringTableUpdate {
+                table: table.name().to_owned(),
+                entries: entries.to_vec(),
+            },
+        ));
+        Ok(())
+    }
+
+    fn on_user_message(
+        &mut self,
+        tick: u32,
+        msg_type: crate::NetmessageType,
+        data: &[u8],
+    ) -> Result<(), FirstPassError> {
+        self.items.push_back((
+            tick,
+            StreamItem::UserMessage {
+                msg_type,
+                data: data.to_vec(),
+            },
+        ));
+        if let Some(event) = crate::parser::decode_user_message_event(msg_type, data)? {
+            self.items.push_back((tick, StreamItem::Event(event)));
+        }
+        Ok(())
+    }
+}
+
+impl<'b> Iterator for LazyStreamIterator<'b> {
+    type Item = Result<(u32, StreamItem), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.pending.items.pop_front() {
+            return Some(Ok(item));
+        }
+
+        for frame in self.frames.by_ref() {
+            let result = match frame {
+                Ok(frame) => self.engine.handle_frame(&frame, &mut self.pending),
+                Err(e) => Err(self.engine.frame_error(e)),
+            };
+            if let Err(e) = result {
+                return Some(Err(e));
+            }
+
+            if let Some(item) = self.pending.items.pop_front() {
+                return Some(Ok(item));
+            }
+        }
+
+        None
+    }
+}
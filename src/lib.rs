@@ -1,27 +1,53 @@
 mod container;
-pub use container::{Container, ParseContainerError};
+pub use container::{Container, ContainerReader, ParseContainerError, Truncation, MAGIC};
 
 mod frame;
-pub use frame::{Frame, FrameDecompressError, FrameIterator, FrameParseError};
+pub use frame::{
+    FallibleFrameIterator, Frame, FrameDecompressError, FrameError, FrameIterator, FrameParseError,
+    FramePosition, FrameReader,
+};
+
+mod follow;
+pub use follow::FrameFollower;
+
+mod index;
+pub use index::{FrameIndex, IndexEntry};
+
+mod writer;
+pub use writer::DemoWriter;
 
 mod democmd;
 pub use democmd::DemoCommand;
 
 mod netmessagetypes;
+pub use netmessagetypes::NetmessageType;
 
 mod bitreader;
+mod bitwriter;
 mod varint;
 
+mod stringtable;
+pub use stringtable::{
+    StringTable, StringTableEntry, StringTableError, StringTableInfo, StringTables,
+};
+
 mod packet;
-pub use packet::DemoEvent;
+pub use packet::{DemoEvent, TimedEvent};
 pub mod game_event;
 
 mod values;
 pub use values::*;
 
+pub mod anonymizer;
+pub mod broadcast;
+pub mod cutter;
 pub mod lazyparser;
 pub mod parser;
 
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
+
 pub mod csgo_proto {
     include!(concat!(env!("OUT_DIR"), "/_.rs"));
 }
@@ -198,7 +198,7 @@ impl TryFrom<i32> for NetmessageType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(non_camel_case_types)]
 pub enum NetmessageType {
     net_NOP,
@@ -8,3 +8,78 @@ pub enum DemoEvent {
     RankUpdate(Box<csgo_proto::CcsUsrMsgServerRankUpdate>),
     RankReveal(Box<csgo_proto::CcsUsrMsgServerRankRevealAll>),
 }
+
+/// A [`DemoEvent`] together with when it happened
+#[derive(Debug, PartialEq)]
+pub struct TimedEvent {
+    /// The tick of the last `net_Tick` before the event, which matches the ticks of the
+    /// [`EntityTickList`](crate::parser::EntityTickList)
+    pub tick: u32,
+    /// The position of the event within its tick, starting at 0
+    pub seq: u32,
+    pub event: DemoEvent,
+}
+
+/// Assigns the tick and position within the tick to the events, in the order they are emitted
+#[derive(Debug, Default)]
+pub(crate) struct EventClock {
+    tick: u32,
+    seq: u32,
+}
+
+impl EventClock {
+    /// Continues after the given number of events of the tick
+    pub fn resume(tick: u32, seq: u32) -> Self {
+        Self { tick, seq }
+    }
+
+    /// The tick of the last event
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// The number of events of the current tick so far
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    pub fn timed(&mut self, tick: u32, event: DemoEvent) -> TimedEvent {
+        if tick != self.tick {
+            self.tick = tick;
+            self.seq = 0;
+        }
+
+        let seq = self.seq;
+        self.seq += 1;
+
+        TimedEvent { tick, seq, event }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_event(tick: u32) -> DemoEvent {
+        DemoEvent::Tick(Box::new(csgo_proto::CnetMsgTick {
+            tick: Some(tick),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn clock_restarts_seq_per_tick() {
+        let mut clock = EventClock::default();
+
+        let timed: Vec<_> = [0, 0, 5, 5, 5, 6]
+            .into_iter()
+            .map(|tick| {
+                let event = clock.timed(tick, tick_event(tick));
+                (event.tick, event.seq)
+            })
+            .collect();
+
+        assert_eq!(vec![(0, 0), (0, 1), (5, 0), (5, 1), (5, 2), (6, 0)], timed);
+        assert_eq!(6, clock.tick());
+    }
+}
@@ -1,19 +1,35 @@
-use crate::{packet::DemoEvent, DemoCommand, Frame, FrameDecompressError, UserId};
+use crate::{
+    packet::{DemoEvent, EventClock, TimedEvent},
+    Frame, FrameDecompressError, UserId,
+};
 
-mod fieldpath;
+pub(crate) mod fieldpath;
 pub use fieldpath::{FieldPath, Paths};
 
 pub(crate) mod decoder;
+mod diagnostics;
+pub use diagnostics::{Diagnostics, Warning, WarningStats};
+mod options;
+pub use options::{ParseMode, ParseOptions};
+mod checkpoint;
+pub use checkpoint::{Checkpoint, CheckpointError};
+mod parallel;
+pub use parallel::{parse_parallel, parse_parallel_with_options};
+mod roster;
+pub use roster::{Presence, Roster, RosterPlayer};
+mod engine;
+pub use engine::{DemoHandler, Engine, StringTableMessage};
 pub mod entities;
 pub(crate) mod propcontroller;
 pub(crate) mod sendtables;
 pub(crate) mod variant;
 
-pub use entities::EntityFilter;
+pub use entities::{EntityFilter, EntityOp};
 pub use variant::Variant;
 
 #[derive(Debug)]
 pub enum FirstPassError {
+    Frame(crate::FrameParseError),
     DecompressFrame(FrameDecompressError),
     NoDataFrame,
     DecodeProtobuf(prost::DecodeError),
@@ -21,6 +37,110 @@ pub enum FirstPassError {
     MissingFileInfo,
     Bitreader(crate::bitreader::BitReadError),
     ParseGameEventError(crate::game_event::ParseGameEventError),
+    /// The send tables do not contain a field that the entity data refers to
+    InvalidSendTables(&'static str),
+    /// A field path of an entity update points to a field that does not exist
+    InvalidFieldPath(FieldPath),
+    /// An entity update refers to an entity that was never created
+    UnknownEntity(i32),
+    /// An entity refers to a class that is not part of the ClassInfo
+    UnknownClass(u32),
+    UnknownQuantizedFloat(u8),
+    InvalidEntityOperation(u32),
+    /// An anomaly of the demo, which is only an error in the [`Strict`](ParseMode::Strict) mode
+    Anomaly(Warning),
+    /// The demo contains more than one set of SendTables
+    DuplicateSendTables,
+    /// The player info is missing the given field
+    InvalidPlayerInfo(&'static str),
+}
+
+impl std::fmt::Display for FirstPassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Frame(e) => write!(f, "failed to parse frame: {}", e),
+            Self::DecompressFrame(e) => write!(f, "failed to decompress frame: {}", e),
+            Self::NoDataFrame => write!(f, "missing data frame"),
+            Self::DecodeProtobuf(e) => write!(f, "failed to decode protobuf: {}", e),
+            Self::MissingFileHeader => write!(f, "missing FileHeader"),
+            Self::MissingFileInfo => write!(f, "missing FileInfo"),
+            Self::Bitreader(e) => write!(f, "failed to read bits: {}", e),
+            Self::ParseGameEventError(e) => write!(f, "failed to parse game event: {}", e),
+            Self::InvalidSendTables(reason) => write!(f, "invalid send tables: {}", reason),
+            Self::InvalidFieldPath(path) => write!(
+                f,
+                "invalid field path: {:?}",
+                &path.path[..path.last.saturating_add(1).min(path.path.len())]
+            ),
+            Self::UnknownEntity(id) => write!(f, "unknown entity {}", id),
+            Self::UnknownClass(cls) => write!(f, "unknown class {}", cls),
+            Self::UnknownQuantizedFloat(idx) => write!(f, "unknown quantized float {}", idx),
+            Self::InvalidEntityOperation(op) => write!(f, "invalid entity operation {:#b}", op),
+            Self::Anomaly(warning) => write!(f, "{}", warning),
+            Self::DuplicateSendTables => write!(f, "duplicate SendTables"),
+            Self::InvalidPlayerInfo(field) => write!(f, "player info is missing {}", field),
+        }
+    }
+}
+
+impl std::error::Error for FirstPassError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Frame(e) => Some(e),
+            Self::DecompressFrame(e) => Some(e),
+            Self::DecodeProtobuf(e) => Some(e),
+            Self::Bitreader(e) => Some(e),
+            Self::ParseGameEventError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A [`FirstPassError`] together with where in the demo it happened
+#[derive(Debug)]
+pub struct ParseError {
+    pub error: FirstPassError,
+    pub context: ErrorContext,
+}
+
+/// Where in a demo an error happened, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorContext {
+    /// The tick of the last `net_Tick` message before the error
+    pub tick: u32,
+    /// The index of the frame, counted from the first frame passed to the parser
+    pub frame: Option<usize>,
+    /// The net message that was being decoded
+    pub message: Option<crate::NetmessageType>,
+    /// The entity that was being decoded
+    pub entity_id: Option<i32>,
+    /// The class of the entity that was being decoded
+    pub class: Option<std::sync::Arc<str>>,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (tick {}", self.error, self.context.tick)?;
+        if let Some(frame) = self.context.frame {
+            write!(f, ", frame {}", frame)?;
+        }
+        if let Some(message) = self.context.message {
+            write!(f, ", message {:?}", message)?;
+        }
+        if let Some(entity_id) = self.context.entity_id {
+            write!(f, ", entity {}", entity_id)?;
+        }
+        if let Some(class) = self.context.class.as_ref() {
+            write!(f, ", class {}", class)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
 }
 
 impl From<prost::DecodeError> for FirstPassError {
@@ -47,9 +167,15 @@ pub struct Player {
     pub color: i32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Entity {
     pub cls: u32,
+    pub serial: u32,
+    /// Entities that left the PVS still exist, until they enter it again or are deleted
+    pub in_pvs: bool,
+    /// The current value of every prop of the entity, starting with the ones from its baseline,
+    /// by the id of the prop. Only kept for entities that pass the [`EntityFilter`]
+    pub props: std::collections::BTreeMap<u32, entities::EntityProp>,
 }
 
 #[derive(Debug)]
@@ -74,6 +200,65 @@ impl EntityTickList {
         });
     }
 
+    /// The entity states of the given tick, which allows for looking up the state of the entities
+    /// at the tick of a [`TimedEvent`]
+    pub fn get(&self, tick: u32) -> Option<&EntityTickStates> {
+        let idx = self
+            .ticks
+            .binary_search_by_key(&tick, |states| states.tick)
+            .ok()?;
+        self.ticks.get(idx)
+    }
+
+    /// The complete state of every entity that exists at the end of the given tick, which applies
+    /// all the states up to it. Same as for
+    /// [`LazyParser::seek`](crate::lazyparser::LazyParser::seek), every state contains all the
+    /// props the entity got since it was created and is [`Updated`](EntityOp::Updated), ordered
+    /// by the id of the entity
+    pub fn states_at(&self, tick: u32) -> Vec<entities::EntityState> {
+        let props_of = |state: &entities::EntityState| {
+            state
+                .props
+                .iter()
+                .map(|prop| (prop.prop_info.id, prop.clone()))
+                .collect::<std::collections::BTreeMap<_, _>>()
+        };
+
+        let mut entities = std::collections::BTreeMap::new();
+        for states in self.ticks.iter().take_while(|states| states.tick <= tick) {
+            for state in states.states.iter() {
+                match state.op {
+                    EntityOp::LeftPVS => {}
+                    EntityOp::Deleted => {
+                        entities.remove(&state.id);
+                    }
+                    EntityOp::Created | EntityOp::EnteredPVS => {
+                        entities.insert(state.id, (state, props_of(state)));
+                    }
+                    EntityOp::Updated => {
+                        let (current, props) = entities
+                            .entry(state.id)
+                            .or_insert_with(|| (state, std::collections::BTreeMap::new()));
+                        *current = state;
+                        props.extend(props_of(state));
+                    }
+                }
+            }
+        }
+
+        entities
+            .into_values()
+            .map(|(state, props)| entities::EntityState {
+                id: state.id,
+                class: state.class.clone(),
+                cls: state.cls,
+                serial: state.serial,
+                op: EntityOp::Updated,
+                props: props.into_values().collect(),
+            })
+            .collect()
+    }
+
     fn add_state(&mut self, state: entities::EntityState) {
         self.ticks.last_mut().expect("We start with at least 1 tick entry and never remove any, so we can always get the last element").states.push(state);
     }
@@ -89,9 +274,43 @@ pub struct EntityTickStates {
 pub struct FirstPassOutput {
     pub header: crate::csgo_proto::CDemoFileHeader,
     pub info: crate::csgo_proto::CDemoFileInfo,
-    pub events: Vec<DemoEvent>,
+    pub events: Vec<TimedEvent>,
+    /// The players from the `CS_UM_EndOfMatchAllPlayersData` message, so only the ones that were
+    /// still there at the end of a complete demo. See [`FirstPassOutput::roster`] for all of them
     pub player_info: std::collections::HashMap<UserId, Player>,
+    /// Every player that was on the server at some point of the demo
+    pub roster: Roster,
     pub entity_states: EntityTickList,
+    pub diagnostics: Diagnostics,
+}
+
+/// The output of [`parse_partial`], which is the same as a [`FirstPassOutput`], except that the
+/// FileInfo can be missing
+#[derive(Debug)]
+pub struct PartialOutput {
+    pub header: crate::csgo_proto::CDemoFileHeader,
+    /// The FileInfo is stored at the end of the demo, so it is missing for incomplete demos
+    pub info: Option<crate::csgo_proto::CDemoFileInfo>,
+    pub events: Vec<TimedEvent>,
+    pub player_info: std::collections::HashMap<UserId, Player>,
+    pub roster: Roster,
+    pub entity_states: EntityTickList,
+    pub diagnostics: Diagnostics,
+}
+
+impl PartialOutput {
+    /// Turns it into the output of a complete demo, which fails if the FileInfo is missing
+    pub fn complete(self) -> Result<FirstPassOutput, FirstPassError> {
+        Ok(FirstPassOutput {
+            header: self.header,
+            info: self.info.ok_or(FirstPassError::MissingFileInfo)?,
+            events: self.events,
+            player_info: self.player_info,
+            roster: self.roster,
+            entity_states: self.entity_states,
+            diagnostics: self.diagnostics,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -115,392 +334,312 @@ pub fn parse<'b, FI>(frames: FI, filter: EntityFilter) -> Result<FirstPassOutput
 where
     FI: IntoIterator<Item = Frame<'b>>,
 {
-    let mut header = None;
-    let mut file_info = None;
+    parse_with_options(frames, filter, ParseOptions::default()).map_err(|e| e.error)
+}
 
-    let mut events = Vec::new();
-    let mut event_mapping = GameEventMapping {
-        mapping: std::collections::HashMap::new(),
-    };
-    let mut player_info = std::collections::HashMap::new();
-    let mut entity_ctx = entities::EntityContext {
-        entities: std::collections::HashMap::new(),
-        cls_to_class: std::collections::HashMap::new(),
+/// Like [`parse`], but with the given [`ParseOptions`] instead of the default ones and errors
+/// that tell where in the demo they happened
+pub fn parse_with_options<'b, FI>(
+    frames: FI,
+    filter: EntityFilter,
+    options: ParseOptions,
+) -> Result<FirstPassOutput, ParseError>
+where
+    FI: IntoIterator<Item = Frame<'b>>,
+{
+    parse_fallible(
+        frames.into_iter().map(Ok::<_, crate::FrameParseError>),
         filter,
-    };
-    let mut paths = Paths::new();
-    let mut qf_mapper = decoder::QfMapper {
-        idx: 0,
-        map: std::collections::HashMap::new(),
-    };
-    let mut prop_controller = propcontroller::PropController::new();
-    let mut serializers = std::collections::HashMap::new();
-
-    let mut baselines = std::collections::HashMap::new();
-
-    let mut entity_states = EntityTickList::new();
+        options,
+    )
+}
 
-    let mut current_tick = 0;
+/// Like [`parse_with_options`], but for a source of frames that can fail, like a
+/// [`FrameReader`](crate::FrameReader), whose errors are returned instead of ending the demo
+/// early
+pub fn parse_fallible<'b, FI, E>(
+    frames: FI,
+    filter: EntityFilter,
+    options: ParseOptions,
+) -> Result<FirstPassOutput, ParseError>
+where
+    FI: IntoIterator<Item = Result<Frame<'b>, E>>,
+    E: Into<crate::FrameParseError>,
+{
+    parse_frames(frames, filter, options, PartialOutput::complete)
+}
 
-    let mut buffer = Vec::new();
-    for frame in frames.into_iter() {
-        let data = frame
-            .decompress_with_buf(&mut buffer)
-            .map_err(FirstPassError::DecompressFrame)?;
+/// Like [`parse`], but does not require the demo to contain a FileInfo, which is the case for
+/// truncated demos or demos still being recorded (see
+/// [`Container::parse_lenient`](crate::Container::parse_lenient)), and instead returns everything
+/// that could be parsed
+pub fn parse_partial<'b, FI>(
+    frames: FI,
+    filter: EntityFilter,
+) -> Result<PartialOutput, FirstPassError>
+where
+    FI: IntoIterator<Item = Frame<'b>>,
+{
+    parse_partial_with_options(frames, filter, ParseOptions::default()).map_err(|e| e.error)
+}
 
-        match frame.cmd {
-            DemoCommand::FileHeader => {
-                let raw: crate::csgo_proto::CDemoFileHeader = prost::Message::decode(data)?;
-                header = Some(raw);
-            }
-            DemoCommand::FileInfo => {
-                let raw: crate::csgo_proto::CDemoFileInfo = prost::Message::decode(data)?;
-                file_info = Some(raw);
-            }
-            DemoCommand::SignonPacket | DemoCommand::Packet => {
-                let raw: crate::csgo_proto::CDemoPacket = prost::Message::decode(data)?;
-
-                inner_parse_packet(
-                    &raw,
-                    &mut events,
-                    &mut event_mapping,
-                    &mut player_info,
-                    &mut entity_ctx,
-                    &mut paths,
-                    &mut qf_mapper,
-                    &mut baselines,
-                    &prop_controller,
-                    &mut entity_states,
-                    &mut current_tick,
-                )?;
-            }
-            DemoCommand::FullPacket => {
-                let packet = parse_fullpacket(data)?;
-
-                if let Some(packet) = packet {
-                    inner_parse_packet(
-                        &packet,
-                        &mut events,
-                        &mut event_mapping,
-                        &mut player_info,
-                        &mut entity_ctx,
-                        &mut paths,
-                        &mut qf_mapper,
-                        &mut baselines,
-                        &prop_controller,
-                        &mut entity_states,
-                        &mut current_tick,
-                    )?;
-                }
-            }
-            // TODO
-            DemoCommand::AnimationData => {}
-            DemoCommand::AnimationHeader => {}
-            DemoCommand::StringTables => {
-                let raw: crate::csgo_proto::CDemoStringTables = prost::Message::decode(data)?;
-
-                for table in raw.tables.iter() {
-                    if table.table_name() == "instancebaseline" {
-                        for item in table.items.iter() {
-                            let k = item.str().parse::<u32>().unwrap_or(u32::MAX);
-                            baselines.insert(k, item.data().to_vec());
-                        }
-                    }
-                }
-            }
-            DemoCommand::SendTables => {
-                let tables: crate::csgo_proto::CDemoSendTables = prost::Message::decode(data)?;
+/// Like [`parse_partial`], but with the given [`ParseOptions`], see [`parse_with_options`]
+pub fn parse_partial_with_options<'b, FI>(
+    frames: FI,
+    filter: EntityFilter,
+    options: ParseOptions,
+) -> Result<PartialOutput, ParseError>
+where
+    FI: IntoIterator<Item = Frame<'b>>,
+{
+    parse_frames(
+        frames.into_iter().map(Ok::<_, crate::FrameParseError>),
+        filter,
+        options,
+        Ok,
+    )
+}
 
-                let mut bitreader = crate::bitreader::Bitreader::new(tables.data());
+/// Parses all the frames and turns the [`PartialOutput`] into the final output using `finish`
+fn parse_frames<'b, FI, E, T>(
+    frames: FI,
+    filter: EntityFilter,
+    options: ParseOptions,
+    finish: fn(PartialOutput) -> Result<T, FirstPassError>,
+) -> Result<T, ParseError>
+where
+    FI: IntoIterator<Item = Result<Frame<'b>, E>>,
+    E: Into<crate::FrameParseError>,
+{
+    let mut handler = EagerHandler::new(0);
 
-                let n_bytes = bitreader.read_varint()?;
-                let bytes = bitreader.read_n_bytes(n_bytes as usize)?;
+    let mut engine = Engine::new(filter).with_options(options);
+    for frame in frames {
+        match frame {
+            Ok(frame) => engine.handle_frame(&frame, &mut handler)?,
+            Err(e) => return Err(engine.frame_error(e.into())),
+        }
+    }
 
-                let serializer_msg: crate::csgo_proto::CsvcMsgFlattenedSerializer =
-                    prost::Message::decode(bytes.as_slice())?;
+    handler
+        .into_output(engine.roster().clone(), engine.diagnostics().clone())
+        .and_then(finish)
+        .map_err(|error| ParseError {
+            error,
+            // The header and the FileInfo are only known to be missing at the end of the demo
+            context: ErrorContext {
+                tick: engine.current_tick(),
+                ..Default::default()
+            },
+        })
+}
 
-                // std::fs::write("send_table.b", bytes.as_slice());
+/// Collects everything into a [`PartialOutput`]
+struct EagerHandler {
+    header: Option<crate::csgo_proto::CDemoFileHeader>,
+    file_info: Option<crate::csgo_proto::CDemoFileInfo>,
+    events: Vec<TimedEvent>,
+    clock: EventClock,
+    player_info: std::collections::HashMap<UserId, Player>,
+    entity_states: EntityTickList,
+    current_tick: u32,
+}
 
-                assert!(serializers.is_empty());
-                serializers = sendtables::get_serializers(
-                    &serializer_msg,
-                    &mut qf_mapper,
-                    &mut prop_controller,
-                )?;
-            }
-            DemoCommand::ClassInfo => {
-                let raw: crate::csgo_proto::CDemoClassInfo = prost::Message::decode(data)?;
-
-                entity_ctx.cls_to_class.clear();
-
-                for class_t in raw.classes {
-                    let cls_id = class_t.class_id();
-                    let network_name = class_t.network_name();
-
-                    if let Some(ser) = serializers.remove(network_name) {
-                        entity_ctx.cls_to_class.insert(
-                            cls_id as u32,
-                            Class {
-                                name: network_name.into(),
-                                serializer: ser,
-                            },
-                        );
-                    }
-                }
-            }
-            _ => {
-                // dbg!(other);
-            }
+impl EagerHandler {
+    /// Starts collecting at the given tick
+    fn new(tick: u32) -> Self {
+        Self {
+            header: None,
+            file_info: None,
+            events: Vec::new(),
+            clock: EventClock::resume(tick, 0),
+            player_info: std::collections::HashMap::new(),
+            entity_states: EntityTickList {
+                ticks: vec![EntityTickStates {
+                    tick,
+                    states: Vec::new(),
+                }],
+            },
+            current_tick: tick,
         }
     }
 
-    let header = header.ok_or(FirstPassError::MissingFileHeader)?;
-    let info = file_info.ok_or(FirstPassError::MissingFileInfo)?;
-
-    Ok(FirstPassOutput {
-        header,
-        info,
-        events,
-        player_info,
-        entity_states,
-    })
+    fn into_output(
+        self,
+        roster: Roster,
+        diagnostics: Diagnostics,
+    ) -> Result<PartialOutput, FirstPassError> {
+        let header = self.header.ok_or(FirstPassError::MissingFileHeader)?;
+
+        Ok(PartialOutput {
+            header,
+            info: self.file_info,
+            events: self.events,
+            player_info: self.player_info,
+            roster,
+            entity_states: self.entity_states,
+            diagnostics,
+        })
+    }
 }
 
-fn parse_fullpacket(data: &[u8]) -> Result<Option<crate::csgo_proto::CDemoPacket>, FirstPassError> {
-    let raw: crate::csgo_proto::CDemoFullPacket = prost::Message::decode(data)?;
+impl DemoHandler for EagerHandler {
+    fn on_file_header(
+        &mut self,
+        header: crate::csgo_proto::CDemoFileHeader,
+    ) -> Result<(), FirstPassError> {
+        self.header = Some(header);
+        Ok(())
+    }
 
-    // TODO
-    // Handle string table stuff
-    for _item in raw.string_table.iter().flat_map(|st| st.tables.iter()) {
-        // dbg!(&item.table_name);
+    fn on_file_info(
+        &mut self,
+        info: crate::csgo_proto::CDemoFileInfo,
+    ) -> Result<(), FirstPassError> {
+        self.file_info = Some(info);
+        Ok(())
     }
 
-    Ok(raw.packet)
-}
+    fn on_tick(&mut self, tick: crate::csgo_proto::CnetMsgTick) -> Result<(), FirstPassError> {
+        if tick.tick() > self.current_tick {
+            self.current_tick = tick.tick();
+            self.entity_states.new_tick(self.current_tick);
+        }
 
-fn inner_parse_packet(
-    raw: &crate::csgo_proto::CDemoPacket,
-    events: &mut Vec<DemoEvent>,
-    event_mapper: &mut GameEventMapping,
-    player_info: &mut std::collections::HashMap<UserId, Player>,
-    entity_ctx: &mut entities::EntityContext,
-    paths: &mut Paths,
-    qf_mapper: &mut decoder::QfMapper,
-    baselines: &mut std::collections::HashMap<u32, Vec<u8>>,
-    prop_controller: &propcontroller::PropController,
-    entity_states: &mut EntityTickList,
-    current_tick: &mut u32,
-) -> Result<(), FirstPassError> {
-    let mut bitreader = crate::bitreader::Bitreader::new(raw.data());
+        let tick_n = tick.tick();
+        self.events
+            .push(self.clock.timed(tick_n, DemoEvent::Tick(Box::new(tick))));
+        Ok(())
+    }
 
-    while bitreader.bits_remaining().unwrap_or(0) > 8 {
-        let msg_type = bitreader.read_u_bit_var()?;
-        let size = bitreader.read_varint()?;
-        let msg_bytes = bitreader.read_n_bytes(size as usize)?;
+    fn on_server_info(
+        &mut self,
+        info: crate::csgo_proto::CsvcMsgServerInfo,
+    ) -> Result<(), FirstPassError> {
+        let tick = self.clock.tick();
+        self.events.push(
+            self.clock
+                .timed(tick, DemoEvent::ServerInfo(Box::new(info))),
+        );
+        Ok(())
+    }
 
-        assert_eq!(msg_bytes.len(), size as usize);
+    fn on_game_event(
+        &mut self,
+        tick: u32,
+        event: crate::game_event::GameEvent,
+    ) -> Result<(), FirstPassError> {
+        self.events.push(
+            self.clock
+                .timed(tick, DemoEvent::GameEvent(Box::new(event))),
+        );
+        Ok(())
+    }
 
-        let net_msg_type = match crate::netmessagetypes::NetmessageType::try_from(msg_type as i32) {
-            Ok(v) => v,
-            Err(e) => {
-                dbg!(e);
-                continue;
-            }
-        };
+    fn on_entity(
+        &mut self,
+        _tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.entity_states.add_state(state);
+        Ok(())
+    }
 
-        match net_msg_type {
-            crate::netmessagetypes::NetmessageType::svc_ClearAllStringTables => {}
-            crate::netmessagetypes::NetmessageType::svc_CreateStringTable => {}
-            crate::netmessagetypes::NetmessageType::svc_UpdateStringTable => {}
-            crate::netmessagetypes::NetmessageType::GE_Source1LegacyGameEventList => {
-                let event_list: crate::csgo_proto::CsvcMsgGameEventList =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-
-                event_mapper.mapping.clear();
-                for event in event_list.descriptors {
-                    event_mapper
-                        .mapping
-                        .insert(event.eventid(), (event.name().to_owned(), event.keys));
-                }
-            }
-            crate::netmessagetypes::NetmessageType::svc_ServerInfo => {
-                let raw: crate::csgo_proto::CsvcMsgServerInfo =
-                    prost::Message::decode(msg_bytes.as_slice())?;
+    fn on_entity_left_pvs(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
 
-                events.push(DemoEvent::ServerInfo(Box::new(raw)));
-            }
-            crate::netmessagetypes::NetmessageType::net_SignonState => {
-                let _raw: crate::csgo_proto::CnetMsgSignonState =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-                // dbg!(raw);
-            }
-            crate::netmessagetypes::NetmessageType::net_Tick => {
-                let raw: crate::csgo_proto::CnetMsgTick =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-
-                assert!(
-                    *current_tick <= raw.tick(),
-                    "Current Tick {} <= Tick Packet {}",
-                    *current_tick,
-                    raw.tick()
-                );
-                if raw.tick() > *current_tick {
-                    *current_tick = raw.tick();
-                    entity_states.new_tick(*current_tick);
-                }
+    fn on_entity_deleted(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
 
-                events.push(DemoEvent::Tick(Box::new(raw)));
-            }
-            crate::netmessagetypes::NetmessageType::net_SetConVar => {}
-            crate::netmessagetypes::NetmessageType::svc_ClassInfo => {}
-            crate::netmessagetypes::NetmessageType::svc_VoiceInit => {}
-            crate::netmessagetypes::NetmessageType::svc_PacketEntities => {
-                let raw: crate::csgo_proto::CsvcMsgPacketEntities =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-
-                if entity_ctx.filter.enabled {
-                    let mut bitreader = crate::bitreader::Bitreader::new(raw.entity_data());
-                    let mut entity_id: i32 = -1;
-                    for _ in 0..raw.updated_entries() {
-                        entity_id += 1 + (bitreader.read_u_bit_var()? as i32);
-
-                        match bitreader.read_nbits(2)? {
-                            0b01 | 0b11 => {
-                                entity_ctx.entities.remove(&entity_id);
-                            }
-                            0b10 => {
-                                let cls = entity_ctx.create_entity(entity_id, &mut bitreader)?;
-
-                                if let Some(baseline_bytes) = baselines.get(&cls) {
-                                    let mut br = crate::bitreader::Bitreader::new(baseline_bytes);
-
-                                    // TODO
-                                    // How should we handle is this?
-                                    let _state = update_entity(
-                                        entity_id,
-                                        &mut br,
-                                        entity_ctx,
-                                        paths,
-                                        qf_mapper,
-                                        prop_controller,
-                                    )?;
-                                }
-
-                                let state = update_entity(
-                                    entity_id,
-                                    &mut bitreader,
-                                    entity_ctx,
-                                    paths,
-                                    qf_mapper,
-                                    prop_controller,
-                                )?;
-                                if let Some(state) = state {
-                                    entity_states.add_state(state);
-                                }
-                            }
-                            0b00 => {
-                                if raw.has_pvs_vis_bits() > 0
-                                    && bitreader.read_nbits(2)? & 0x01 == 1
-                                {
-                                    continue;
-                                }
-
-                                let state = update_entity(
-                                    entity_id,
-                                    &mut bitreader,
-                                    entity_ctx,
-                                    paths,
-                                    qf_mapper,
-                                    prop_controller,
-                                )?;
-                                if let Some(state) = state {
-                                    entity_states.add_state(state);
-                                }
-                            }
-                            unknown => {
-                                panic!("{:?}", unknown);
-                            }
-                        };
-                    }
-                }
-            }
-            crate::netmessagetypes::NetmessageType::svc_UserCmds => {}
-            crate::netmessagetypes::NetmessageType::GE_SosStartSoundEvent => {}
-            crate::netmessagetypes::NetmessageType::GE_SosStopSoundEvent => {}
-            crate::netmessagetypes::NetmessageType::CS_GE_PlayerAnimationEvent => {}
-            crate::netmessagetypes::NetmessageType::CS_GE_RadioIconEvent => {}
-            crate::netmessagetypes::NetmessageType::CS_GE_FireBullets => {}
-            crate::netmessagetypes::NetmessageType::GE_Source1LegacyGameEvent => {
-                let raw: crate::csgo_proto::CMsgSource1LegacyGameEvent =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-
-                match event_mapper.mapping.get(&raw.eventid()) {
-                    Some((name, keys)) => {
-                        match crate::game_event::EVENT_PARSERS.get(name) {
-                            Some(parser) => {
-                                let parsed = parser.parse(keys.as_slice(), raw.clone())?;
-
-                                events.push(DemoEvent::GameEvent(Box::new(parsed)));
-                            }
-                            None => {
-                                println!("No parser for {:?}", name);
-                            }
-                        };
-                    }
-                    None => {
-                        println!("Unknown Event - ID: {}", raw.eventid());
-                    }
-                };
-            }
-            crate::netmessagetypes::NetmessageType::UM_SayText2 => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_XpUpdate => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_ServerRankUpdate => {
-                let raw: crate::csgo_proto::CcsUsrMsgServerRankUpdate =
-                    prost::Message::decode(msg_bytes.as_slice())?;
+    fn on_user_message(
+        &mut self,
+        tick: u32,
+        msg_type: crate::NetmessageType,
+        data: &[u8],
+    ) -> Result<(), FirstPassError> {
+        if let Some(event) = decode_user_message_event(msg_type, data)? {
+            self.events.push(self.clock.timed(tick, event));
+        }
+        if msg_type == crate::NetmessageType::CS_UM_EndOfMatchAllPlayersData {
+            update_players(data, &mut self.player_info)?;
+        }
+        Ok(())
+    }
+}
 
-                events.push(DemoEvent::RankUpdate(Box::new(raw)));
-            }
-            crate::netmessagetypes::NetmessageType::CS_UM_ServerRankRevealAll => {
-                let raw: crate::csgo_proto::CcsUsrMsgServerRankRevealAll =
-                    prost::Message::decode(msg_bytes.as_slice())?;
+/// Decodes the user messages that are part of the [`DemoEvent`]s
+pub(crate) fn decode_user_message_event(
+    msg_type: crate::NetmessageType,
+    data: &[u8],
+) -> Result<Option<DemoEvent>, FirstPassError> {
+    match msg_type {
+        crate::NetmessageType::CS_UM_ServerRankUpdate => {
+            let raw: crate::csgo_proto::CcsUsrMsgServerRankUpdate = prost::Message::decode(data)?;
 
-                events.push(DemoEvent::RankReveal(Box::new(raw)));
-            }
-            crate::netmessagetypes::NetmessageType::CS_UM_WeaponSound => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_RadioText => {}
-            crate::netmessagetypes::NetmessageType::TE_WorldDecal => {}
-            crate::netmessagetypes::NetmessageType::TE_EffectDispatch => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_EndOfMatchAllPlayersData => {
-                let raw: crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData =
-                    prost::Message::decode(msg_bytes.as_slice())?;
-
-                for data in raw.allplayerdata {
-                    player_info.insert(
-                        UserId(data.slot()),
-                        Player {
-                            name: data.name.unwrap(),
-                            xuid: data.xuid.unwrap(),
-                            team: data.teamnumber.unwrap(),
-                            color: data.playercolor.unwrap(),
-                        },
-                    );
-                }
-            }
-            crate::netmessagetypes::NetmessageType::TE_PhysicsProp => {}
-            crate::netmessagetypes::NetmessageType::UM_TextMsg => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_VoteFailed => {}
-            crate::netmessagetypes::NetmessageType::net_SpawnGroup_Load => {}
-            crate::netmessagetypes::NetmessageType::CS_UM_MatchEndConditions => {}
-            crate::netmessagetypes::NetmessageType::TE_Explosion => {}
-            _unknown => {
-                // dbg!(unknown);
-            }
-        };
+            Ok(Some(DemoEvent::RankUpdate(Box::new(raw))))
+        }
+        crate::NetmessageType::CS_UM_ServerRankRevealAll => {
+            let raw: crate::csgo_proto::CcsUsrMsgServerRankRevealAll =
+                prost::Message::decode(data)?;
+
+            Ok(Some(DemoEvent::RankReveal(Box::new(raw))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Updates the players from the data of a `CS_UM_EndOfMatchAllPlayersData` message
+pub(crate) fn update_players(
+    data: &[u8],
+    player_info: &mut std::collections::HashMap<UserId, Player>,
+) -> Result<(), FirstPassError> {
+    let raw: crate::csgo_proto::CcsUsrMsgEndOfMatchAllPlayersData = prost::Message::decode(data)?;
+
+    for data in raw.allplayerdata {
+        player_info.insert(
+            UserId(data.slot()),
+            Player {
+                name: data.name.ok_or(FirstPassError::InvalidPlayerInfo("name"))?,
+                xuid: data.xuid.ok_or(FirstPassError::InvalidPlayerInfo("xuid"))?,
+                team: data
+                    .teamnumber
+                    .ok_or(FirstPassError::InvalidPlayerInfo("teamnumber"))?,
+                color: data
+                    .playercolor
+                    .ok_or(FirstPassError::InvalidPlayerInfo("playercolor"))?,
+            },
+        );
     }
 
     Ok(())
 }
 
+/// Updates the entity baselines from the `instancebaseline` string table, if it is part of the
+/// given string tables
+pub(crate) fn update_baselines(
+    tables: &crate::csgo_proto::CDemoStringTables,
+    baselines: &mut std::collections::HashMap<u32, Vec<u8>>,
+) {
+    for table in tables.tables.iter() {
+        if table.table_name() == "instancebaseline" {
+            for item in table.items.iter() {
+                let k = item.str().parse::<u32>().unwrap_or(u32::MAX);
+                baselines.insert(k, item.data().to_vec());
+            }
+        }
+    }
+}
+
 pub(crate) fn update_entity(
     entity_id: i32,
     bitreader: &mut crate::bitreader::Bitreader,
@@ -508,6 +647,7 @@ pub(crate) fn update_entity(
     paths: &mut Paths,
     qf_mapper: &mut decoder::QfMapper,
     prop_controller: &propcontroller::PropController,
+    diagnostics: &mut Diagnostics,
 ) -> Result<Option<entities::EntityState>, FirstPassError> {
     let n_updates = fieldpath::parse_paths(bitreader, paths)?;
     let (n_updated_values, entity_state) = match entity_ctx.decode_entity_update(
@@ -517,6 +657,7 @@ pub(crate) fn update_entity(
         paths,
         qf_mapper,
         prop_controller,
+        diagnostics,
     )? {
         Some(s) => s,
         None => return Ok(None),
@@ -538,3 +679,99 @@ static HUFFMAN_LOOKUP_TABLE: std::sync::LazyLock<Vec<(u8, u8)>> = std::sync::Laz
     }
     huf2
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(id: i32, op: EntityOp, props: &[(u32, i32)]) -> entities::EntityState {
+        entities::EntityState {
+            id,
+            class: "CTest".into(),
+            cls: 1,
+            serial: 0,
+            op,
+            props: props
+                .iter()
+                .map(|(prop_id, value)| entities::EntityProp {
+                    prop_info: propcontroller::PropInfo {
+                        id: *prop_id,
+                        prop_name: format!("m_prop{}", prop_id).into(),
+                    },
+                    value: Variant::I32(*value),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn states_at() {
+        let mut list = EntityTickList::new();
+        list.add_state(state(1, EntityOp::Created, &[(1, 10), (2, 20)]));
+        list.add_state(state(2, EntityOp::Created, &[(1, 5)]));
+        list.new_tick(3);
+        list.add_state(state(1, EntityOp::Updated, &[(2, 21), (3, 30)]));
+        list.add_state(state(2, EntityOp::LeftPVS, &[]));
+        list.new_tick(5);
+        list.add_state(state(1, EntityOp::Deleted, &[]));
+
+        assert_eq!(
+            vec![
+                state(1, EntityOp::Updated, &[(1, 10), (2, 20)]),
+                state(2, EntityOp::Updated, &[(1, 5)]),
+            ],
+            list.states_at(2)
+        );
+        assert_eq!(
+            vec![
+                state(1, EntityOp::Updated, &[(1, 10), (2, 21), (3, 30)]),
+                state(2, EntityOp::Updated, &[(1, 5)]),
+            ],
+            list.states_at(4)
+        );
+        assert_eq!(
+            vec![state(2, EntityOp::Updated, &[(1, 5)])],
+            list.states_at(5)
+        );
+    }
+
+    #[test]
+    fn missing_file_info_context() {
+        let header = Frame {
+            cmd: crate::DemoCommand::FileHeader,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(
+                &crate::csgo_proto::CDemoFileHeader::default(),
+            )),
+        };
+        let tick = prost::Message::encode_to_vec(&crate::csgo_proto::CnetMsgTick {
+            tick: Some(10),
+            ..Default::default()
+        });
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        // The id of net_Tick
+        writer.write_u_bit_var(4);
+        writer.write_varint(tick.len() as u32);
+        writer.write_bytes(&tick);
+        let packet = Frame {
+            cmd: crate::DemoCommand::Packet,
+            tick: 10,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(
+                &crate::csgo_proto::CDemoPacket {
+                    data: Some(writer.into_bytes()),
+                },
+            )),
+        };
+
+        let error = parse_with_options(
+            [header, packet],
+            EntityFilter::disabled(),
+            ParseOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error.error, FirstPassError::MissingFileInfo));
+        assert_eq!(10, error.context.tick);
+    }
+}
@@ -0,0 +1,634 @@
+//! Snapshots of the state of an [`Engine`](super::Engine), which allow for resuming the parsing
+//! of a demo later on, without decoding everything before it again
+
+/// The version of the encoding, which is bumped whenever the stored state changes
+const VERSION: u32 = 7;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Decode(prost::DecodeError),
+    /// The checkpoint was created by a different version of the parser
+    UnsupportedVersion(u32),
+    /// The offset of the frame of the checkpoint does not point at a frame of the demo, likely
+    /// because the checkpoint was created for a different demo
+    InvalidFrame(usize),
+    /// A prop of the entity with the given id has a malformed value
+    InvalidEntity(i32),
+    /// The state stored in the checkpoint could not be restored
+    Restore(super::FirstPassError),
+}
+
+impl From<prost::DecodeError> for CheckpointError {
+    fn from(value: prost::DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+impl From<super::FirstPassError> for CheckpointError {
+    fn from(value: super::FirstPassError) -> Self {
+        Self::Restore(value)
+    }
+}
+
+/// The state of an [`Engine`](super::Engine) in between two frames.
+///
+/// Instead of the decoded serializers and classes, this stores the messages they were built from
+/// and decodes them again when restoring the checkpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub(crate) frame: usize,
+    pub(crate) offset: usize,
+    pub(crate) tick: u32,
+    /// The number of events of the current tick that were returned before the checkpoint, so the
+    /// [`seq`](crate::TimedEvent::seq) of the events continues after resuming
+    pub(crate) seq: u32,
+    /// Whether a SendTables frame was handled, as another one is rejected
+    pub(crate) seen_send_tables: bool,
+    pub(crate) send_tables: Option<Vec<u8>>,
+    pub(crate) class_info: Option<Vec<u8>>,
+    pub(crate) game_events: Option<Vec<u8>>,
+    pub(crate) baselines: Vec<(u32, Vec<u8>)>,
+    /// Every entity that currently exists, by its id
+    pub(crate) entities: Vec<(i32, super::Entity)>,
+    pub(crate) string_tables: crate::StringTables,
+    pub(crate) roster: super::Roster,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawCheckpoint {
+    #[prost(uint32, tag = "1")]
+    version: u32,
+    #[prost(uint64, tag = "2")]
+    frame: u64,
+    #[prost(uint32, tag = "3")]
+    tick: u32,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    send_tables: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "5")]
+    class_info: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "6")]
+    game_events: Option<Vec<u8>>,
+    #[prost(message, repeated, tag = "7")]
+    baselines: Vec<RawBaseline>,
+    #[prost(message, repeated, tag = "8")]
+    entities: Vec<RawEntity>,
+    #[prost(uint32, tag = "9")]
+    seq: u32,
+    #[prost(message, repeated, tag = "10")]
+    string_tables: Vec<RawStringTable>,
+    #[prost(message, repeated, tag = "11")]
+    roster: Vec<RawRosterPlayer>,
+    #[prost(uint64, tag = "12")]
+    offset: u64,
+    #[prost(bool, tag = "13")]
+    seen_send_tables: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawBaseline {
+    #[prost(uint32, tag = "1")]
+    cls: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawEntity {
+    #[prost(int32, tag = "1")]
+    id: i32,
+    #[prost(uint32, tag = "2")]
+    cls: u32,
+    #[prost(message, repeated, tag = "3")]
+    props: Vec<RawProp>,
+    #[prost(uint32, tag = "4")]
+    serial: u32,
+    #[prost(bool, tag = "5")]
+    in_pvs: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawProp {
+    #[prost(uint32, tag = "1")]
+    id: u32,
+    #[prost(string, tag = "2")]
+    name: String,
+    #[prost(
+        oneof = "RawVariant",
+        tags = "3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16"
+    )]
+    value: Option<RawVariant>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum RawVariant {
+    #[prost(bool, tag = "3")]
+    Bool(bool),
+    #[prost(uint32, tag = "4")]
+    U32(u32),
+    #[prost(int32, tag = "5")]
+    I32(i32),
+    #[prost(int32, tag = "6")]
+    I16(i32),
+    #[prost(float, tag = "7")]
+    F32(f32),
+    #[prost(uint64, tag = "8")]
+    U64(u64),
+    #[prost(uint32, tag = "9")]
+    U8(u32),
+    #[prost(string, tag = "10")]
+    String(String),
+    #[prost(message, tag = "11")]
+    VecXy(RawList),
+    #[prost(message, tag = "12")]
+    VecXyz(RawList),
+    #[prost(message, tag = "13")]
+    StringVec(RawList),
+    #[prost(message, tag = "14")]
+    U32Vec(RawList),
+    #[prost(message, tag = "15")]
+    U64Vec(RawList),
+    #[prost(message, tag = "16")]
+    Stickers(RawList),
+}
+
+/// The values of the variants that are lists, only the field of the variant is used
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawList {
+    #[prost(float, repeated, tag = "1")]
+    floats: Vec<f32>,
+    #[prost(string, repeated, tag = "2")]
+    strings: Vec<String>,
+    #[prost(uint32, repeated, tag = "3")]
+    u32s: Vec<u32>,
+    #[prost(uint64, repeated, tag = "4")]
+    u64s: Vec<u64>,
+    #[prost(message, repeated, tag = "5")]
+    stickers: Vec<RawSticker>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawSticker {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(float, tag = "2")]
+    wear: f32,
+    #[prost(uint32, tag = "3")]
+    id: u32,
+    #[prost(float, tag = "4")]
+    x: f32,
+    #[prost(float, tag = "5")]
+    y: f32,
+}
+
+impl From<&super::entities::EntityProp> for RawProp {
+    fn from(prop: &super::entities::EntityProp) -> Self {
+        use super::Variant;
+
+        let value = match &prop.value {
+            Variant::Bool(v) => RawVariant::Bool(*v),
+            Variant::U32(v) => RawVariant::U32(*v),
+            Variant::I32(v) => RawVariant::I32(*v),
+            Variant::I16(v) => RawVariant::I16(i32::from(*v)),
+            Variant::F32(v) => RawVariant::F32(*v),
+            Variant::U64(v) => RawVariant::U64(*v),
+            Variant::U8(v) => RawVariant::U8(u32::from(*v)),
+            Variant::String(v) => RawVariant::String(v.clone()),
+            Variant::VecXY(v) => RawVariant::VecXy(RawList {
+                floats: v.to_vec(),
+                ..Default::default()
+            }),
+            Variant::VecXYZ(v) => RawVariant::VecXyz(RawList {
+                floats: v.to_vec(),
+                ..Default::default()
+            }),
+            Variant::StringVec(v) => RawVariant::StringVec(RawList {
+                strings: v.clone(),
+                ..Default::default()
+            }),
+            Variant::U32Vec(v) => RawVariant::U32Vec(RawList {
+                u32s: v.clone(),
+                ..Default::default()
+            }),
+            Variant::U64Vec(v) => RawVariant::U64Vec(RawList {
+                u64s: v.clone(),
+                ..Default::default()
+            }),
+            Variant::Stickers(v) => RawVariant::Stickers(RawList {
+                stickers: v
+                    .iter()
+                    .map(|sticker| RawSticker {
+                        name: sticker.name.clone(),
+                        wear: sticker.wear,
+                        id: sticker.id,
+                        x: sticker.x,
+                        y: sticker.y,
+                    })
+                    .collect(),
+                ..Default::default()
+            }),
+        };
+
+        Self {
+            id: prop.prop_info.id,
+            name: prop.prop_info.prop_name.to_string(),
+            value: Some(value),
+        }
+    }
+}
+
+impl RawProp {
+    fn decode(self) -> Option<super::entities::EntityProp> {
+        use super::{variant::Sticker, Variant};
+
+        let value = match self.value? {
+            RawVariant::Bool(v) => Variant::Bool(v),
+            RawVariant::U32(v) => Variant::U32(v),
+            RawVariant::I32(v) => Variant::I32(v),
+            RawVariant::I16(v) => Variant::I16(v.try_into().ok()?),
+            RawVariant::F32(v) => Variant::F32(v),
+            RawVariant::U64(v) => Variant::U64(v),
+            RawVariant::U8(v) => Variant::U8(v.try_into().ok()?),
+            RawVariant::String(v) => Variant::String(v),
+            RawVariant::VecXy(v) => Variant::VecXY(v.floats.try_into().ok()?),
+            RawVariant::VecXyz(v) => Variant::VecXYZ(v.floats.try_into().ok()?),
+            RawVariant::StringVec(v) => Variant::StringVec(v.strings),
+            RawVariant::U32Vec(v) => Variant::U32Vec(v.u32s),
+            RawVariant::U64Vec(v) => Variant::U64Vec(v.u64s),
+            RawVariant::Stickers(v) => Variant::Stickers(
+                v.stickers
+                    .into_iter()
+                    .map(|sticker| Sticker {
+                        name: sticker.name,
+                        wear: sticker.wear,
+                        id: sticker.id,
+                        x: sticker.x,
+                        y: sticker.y,
+                    })
+                    .collect(),
+            ),
+        };
+
+        Some(super::entities::EntityProp {
+            prop_info: super::propcontroller::PropInfo {
+                id: self.id,
+                prop_name: self.name.into(),
+            },
+            value,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawStringTable {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(bool, tag = "2")]
+    user_data_fixed_size: bool,
+    #[prost(int32, tag = "3")]
+    user_data_size_bits: i32,
+    #[prost(int32, tag = "4")]
+    flags: i32,
+    #[prost(bool, tag = "5")]
+    using_varint_bitcounts: bool,
+    #[prost(message, repeated, tag = "6")]
+    entries: Vec<RawStringTableEntry>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawStringTableEntry {
+    #[prost(int32, tag = "1")]
+    index: i32,
+    #[prost(string, optional, tag = "2")]
+    key: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    value: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawRosterPlayer {
+    #[prost(int32, tag = "1")]
+    slot: i32,
+    #[prost(int32, tag = "2")]
+    userid: i32,
+    #[prost(string, tag = "3")]
+    name: String,
+    #[prost(fixed64, tag = "4")]
+    xuid: u64,
+    #[prost(bool, tag = "5")]
+    is_bot: bool,
+    #[prost(bool, tag = "6")]
+    is_hltv: bool,
+    #[prost(message, repeated, tag = "7")]
+    presence: Vec<RawPresence>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RawPresence {
+    #[prost(uint32, tag = "1")]
+    start: u32,
+    #[prost(uint32, optional, tag = "2")]
+    end: Option<u32>,
+    #[prost(int32, tag = "3")]
+    slot: i32,
+}
+
+impl Checkpoint {
+    /// The index of the next frame to be parsed, counted from the first frame the engine saw
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// The byte offset of the next frame, relative to the start of the first frame the engine saw
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The tick of the last `net_Tick` before the checkpoint
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let raw = RawCheckpoint {
+            version: VERSION,
+            frame: self.frame as u64,
+            offset: self.offset as u64,
+            tick: self.tick,
+            seq: self.seq,
+            seen_send_tables: self.seen_send_tables,
+            send_tables: self.send_tables.clone(),
+            class_info: self.class_info.clone(),
+            game_events: self.game_events.clone(),
+            baselines: self
+                .baselines
+                .iter()
+                .map(|(cls, data)| RawBaseline {
+                    cls: *cls,
+                    data: data.clone(),
+                })
+                .collect(),
+            entities: self
+                .entities
+                .iter()
+                .map(|(id, entity)| RawEntity {
+                    id: *id,
+                    cls: entity.cls,
+                    props: entity.props.values().map(RawProp::from).collect(),
+                    serial: entity.serial,
+                    in_pvs: entity.in_pvs,
+                })
+                .collect(),
+            string_tables: self
+                .string_tables
+                .iter()
+                .map(|table| RawStringTable {
+                    name: table.info.name.clone(),
+                    user_data_fixed_size: table.info.user_data_fixed_size,
+                    user_data_size_bits: table.info.user_data_size_bits,
+                    flags: table.info.flags,
+                    using_varint_bitcounts: table.info.using_varint_bitcounts,
+                    entries: table
+                        .entries()
+                        .map(|entry| RawStringTableEntry {
+                            index: entry.index,
+                            key: entry.key.clone(),
+                            value: entry.value.clone(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            roster: self
+                .roster
+                .players()
+                .iter()
+                .map(|player| RawRosterPlayer {
+                    slot: player.slot,
+                    userid: player.userid,
+                    name: player.name.clone(),
+                    xuid: player.xuid,
+                    is_bot: player.is_bot,
+                    is_hltv: player.is_hltv,
+                    presence: player
+                        .presence
+                        .iter()
+                        .map(|presence| RawPresence {
+                            start: presence.start,
+                            end: presence.end,
+                            slot: presence.slot,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+
+        prost::Message::encode_to_vec(&raw)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CheckpointError> {
+        let raw: RawCheckpoint = prost::Message::decode(data)?;
+        if raw.version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(raw.version));
+        }
+
+        let mut string_tables = crate::StringTables::new();
+        for table in raw.string_tables {
+            let mut restored = crate::StringTable::new(crate::StringTableInfo {
+                name: table.name,
+                user_data_fixed_size: table.user_data_fixed_size,
+                user_data_size_bits: table.user_data_size_bits,
+                flags: table.flags,
+                using_varint_bitcounts: table.using_varint_bitcounts,
+            });
+            restored.apply(
+                table
+                    .entries
+                    .into_iter()
+                    .map(|entry| crate::StringTableEntry {
+                        index: entry.index,
+                        key: entry.key,
+                        value: entry.value,
+                    })
+                    .collect(),
+            );
+            string_tables.push(restored);
+        }
+
+        let mut entities = Vec::with_capacity(raw.entities.len());
+        for entity in raw.entities {
+            let mut props = std::collections::BTreeMap::new();
+            for prop in entity.props {
+                let prop = prop
+                    .decode()
+                    .ok_or(CheckpointError::InvalidEntity(entity.id))?;
+                props.insert(prop.prop_info.id, prop);
+            }
+
+            entities.push((
+                entity.id,
+                super::Entity {
+                    cls: entity.cls,
+                    serial: entity.serial,
+                    in_pvs: entity.in_pvs,
+                    props,
+                },
+            ));
+        }
+
+        let mut roster = super::Roster::new();
+        for player in raw.roster {
+            roster.insert(super::RosterPlayer {
+                slot: player.slot,
+                userid: player.userid,
+                name: player.name,
+                xuid: player.xuid,
+                is_bot: player.is_bot,
+                is_hltv: player.is_hltv,
+                presence: player
+                    .presence
+                    .into_iter()
+                    .map(|presence| super::Presence {
+                        slot: presence.slot,
+                        start: presence.start,
+                        end: presence.end,
+                    })
+                    .collect(),
+            });
+        }
+
+        Ok(Self {
+            frame: raw.frame as usize,
+            offset: raw.offset as usize,
+            tick: raw.tick,
+            seq: raw.seq,
+            seen_send_tables: raw.seen_send_tables,
+            send_tables: raw.send_tables,
+            class_info: raw.class_info,
+            game_events: raw.game_events,
+            baselines: raw
+                .baselines
+                .into_iter()
+                .map(|baseline| (baseline.cls, baseline.data))
+                .collect(),
+            entities,
+            string_tables,
+            roster,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut table = crate::StringTable::new(crate::StringTableInfo {
+            name: "userinfo".to_owned(),
+            user_data_fixed_size: false,
+            user_data_size_bits: 0,
+            flags: 0,
+            using_varint_bitcounts: true,
+        });
+        table.apply(vec![
+            crate::StringTableEntry {
+                index: 0,
+                key: Some("0".to_owned()),
+                value: Some(vec![1, 2]),
+            },
+            crate::StringTableEntry {
+                index: 3,
+                key: Some("3".to_owned()),
+                value: None,
+            },
+        ]);
+        let mut string_tables = crate::StringTables::new();
+        string_tables.push(table);
+
+        let entity = |cls, props: Vec<(u32, crate::parser::Variant)>| crate::parser::Entity {
+            cls,
+            serial: cls * 3,
+            in_pvs: cls != 0,
+            props: props
+                .into_iter()
+                .map(|(id, value)| {
+                    let prop = crate::parser::entities::EntityProp {
+                        prop_info: crate::parser::propcontroller::PropInfo {
+                            id,
+                            prop_name: format!("m_prop{}", id).into(),
+                        },
+                        value,
+                    };
+                    (id, prop)
+                })
+                .collect(),
+        };
+        let props = vec![
+            (1, crate::parser::Variant::I16(-3)),
+            (2, crate::parser::Variant::VecXYZ([1.0, 2.0, 3.0])),
+            (3, crate::parser::Variant::U64Vec(vec![4, 5])),
+            (
+                4,
+                crate::parser::Variant::Stickers(vec![crate::parser::variant::Sticker {
+                    name: "sticker".to_owned(),
+                    wear: 0.5,
+                    id: 6,
+                    x: 0.1,
+                    y: 0.2,
+                }]),
+            ),
+        ];
+
+        let mut roster = crate::parser::Roster::new();
+        roster.insert(crate::parser::RosterPlayer {
+            slot: 1,
+            userid: 2,
+            name: "player".to_owned(),
+            xuid: 76561197960265728,
+            is_bot: false,
+            is_hltv: false,
+            presence: vec![
+                crate::parser::Presence {
+                    slot: 1,
+                    start: 10,
+                    end: Some(20),
+                },
+                crate::parser::Presence {
+                    slot: 3,
+                    start: 30,
+                    end: None,
+                },
+            ],
+        });
+
+        let checkpoint = Checkpoint {
+            frame: 12,
+            offset: 678,
+            tick: 345,
+            seq: 2,
+            seen_send_tables: true,
+            send_tables: None,
+            class_info: Some(vec![1, 2, 3]),
+            game_events: Some(Vec::new()),
+            baselines: vec![(1, vec![0xff]), (7, Vec::new())],
+            entities: vec![(-1, entity(0, Vec::new())), (5, entity(7, props))],
+            string_tables,
+            roster,
+        };
+
+        let restored = Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+        assert_eq!(checkpoint, restored);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let raw = RawCheckpoint {
+            version: VERSION + 1,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Checkpoint::from_bytes(&prost::Message::encode_to_vec(&raw)),
+            Err(CheckpointError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+}
@@ -223,7 +223,7 @@ impl<'b> crate::bitreader::Bitreader<'b> {
     ) -> Result<super::variant::Variant, super::FirstPassError> {
         match qf_map.map.get(&(qf_idx as u32)) {
             Some(qf) => Ok(super::variant::Variant::F32(qf.decode(self)?)),
-            None => panic!(),
+            None => Err(super::FirstPassError::UnknownQuantizedFloat(qf_idx)),
         }
     }
 
@@ -236,11 +236,9 @@ impl<'b> crate::bitreader::Bitreader<'b> {
     }
 
     pub fn decode_uint64(&mut self) -> Result<u64, super::FirstPassError> {
-        let bytes = self.read_n_bytes(8)?;
-        match bytes.try_into() {
-            Err(_) => panic!(),
-            Ok(arr) => Ok(u64::from_ne_bytes(arr)),
-        }
+        let mut bytes = [0; 8];
+        self.read_n_bytes_mut(8, &mut bytes)?;
+        Ok(u64::from_ne_bytes(bytes))
     }
 
     pub fn decode_noscale(&mut self) -> Result<f32, super::FirstPassError> {
@@ -306,7 +304,7 @@ impl<'b> crate::bitreader::Bitreader<'b> {
         Ok(v)
     }
     pub fn read_angle(&mut self, n: usize) -> Result<f32, super::FirstPassError> {
-        Ok(self.decode_noscale()? / ((1 << n) as f32))
+        Ok(self.decode_noscale()? / (1i32.wrapping_shl(n as u32) as f32))
     }
 
     pub fn decode_normal(&mut self) -> Result<f32, super::FirstPassError> {
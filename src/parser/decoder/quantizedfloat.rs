@@ -57,7 +57,7 @@ impl QuantalizedFloat {
         let high: u32 = if self.bit_count == 32 {
             0xFFFFFFFE
         } else {
-            (1 << self.bit_count) - 1
+            (1u32 << self.bit_count) - 1
         };
 
         let mut high_mul: f32;
@@ -150,7 +150,7 @@ impl QuantalizedFloat {
             qf.flags = 0;
         }
         qf.validate_flags();
-        let mut steps = 1 << qf.bit_count;
+        let mut steps: u32 = 1 << qf.bit_count;
 
         if (qf.flags & QFF_ROUNDDOWN) != 0 {
             let range = qf.high - qf.low;
@@ -166,11 +166,13 @@ impl QuantalizedFloat {
             if delta < 1.0 {
                 delta = 1.0;
             }
-            let delta_log2 = delta.log2().ceil();
-            let range_2: u32 = 1 << delta_log2 as u32;
+            // The bounds come from the demo itself, so the shifts are limited to the 31 bits that
+            // still fit into steps, instead of overflowing for absurdly large ranges
+            let delta_log2 = (delta.log2().ceil() as u32).min(31);
+            let range_2: u32 = 1 << delta_log2;
             let mut bit_count = qf.bit_count;
             loop {
-                if (1 << bit_count) > range_2 {
+                if bit_count >= 31 || (1u32 << bit_count) > range_2 {
                     break;
                 } else {
                     bit_count += 1;
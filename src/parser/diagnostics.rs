@@ -0,0 +1,170 @@
+//! Collects the anomalies found while parsing a demo, which are not severe enough to stop the
+//! parsing, instead of printing them
+
+/// Something unexpected in a demo, that the parser skipped over
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// A game event for which there is no parser
+    UnknownGameEvent(String),
+    /// A game event with an id that is not part of the game event list
+    UnknownGameEventId(i32),
+    /// A net message with an unknown id
+    UnknownMessageType(i32),
+    /// An entity prop with the given id, for which there is no PropInfo so its value is dropped
+    MissingPropInfo(u32),
+    /// The baseline of the entity class with the given id could not be decoded
+    BaselineDecode(u32),
+    /// A `net_Tick` message went back in time
+    TickOutOfOrder { current: u32, tick: u32 },
+    /// The game event with the given name has a different number of keys than its descriptor
+    MismatchedGameEventKeys(String),
+    /// The entries of the string table with the given name could not be decoded
+    InvalidStringTable(String),
+    /// The `userinfo` entry of the player in the given slot could not be decoded
+    InvalidUserInfo(i32),
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownGameEvent(name) => write!(f, "no parser for game event {:?}", name),
+            Self::UnknownGameEventId(id) => write!(f, "unknown game event id {}", id),
+            Self::UnknownMessageType(id) => write!(f, "unknown message type {}", id),
+            Self::MissingPropInfo(id) => write!(f, "missing PropInfo for prop {}", id),
+            Self::BaselineDecode(cls) => write!(f, "failed to decode baseline of class {}", cls),
+            Self::TickOutOfOrder { current, tick } => {
+                write!(f, "tick {} is before the current tick {}", tick, current)
+            }
+            Self::MismatchedGameEventKeys(name) => {
+                write!(f, "mismatched keys for game event {:?}", name)
+            }
+            Self::InvalidStringTable(name) => write!(f, "invalid string table {:?}", name),
+            Self::InvalidUserInfo(slot) => write!(f, "invalid userinfo for slot {}", slot),
+        }
+    }
+}
+
+/// How often a [`Warning`] happened and when it was first seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarningStats {
+    pub count: usize,
+    pub first_tick: u32,
+}
+
+/// All the [`Warning`]s of a demo, each recorded once with its [`WarningStats`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    tick: u32,
+    mode: super::ParseMode,
+    warnings: std::collections::HashMap<Warning, WarningStats>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the tick that newly recorded warnings are attributed to
+    pub(crate) fn set_tick(&mut self, tick: u32) {
+        self.tick = tick;
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: super::ParseMode) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.mode == super::ParseMode::Strict
+    }
+
+    /// Records an anomaly of the demo, which is an error in the strict mode and otherwise just
+    /// means that the caller should skip over whatever caused it
+    pub(crate) fn report(&mut self, warning: Warning) -> Result<(), super::FirstPassError> {
+        if self.is_strict() {
+            return Err(super::FirstPassError::Anomaly(warning));
+        }
+        self.record(warning);
+        Ok(())
+    }
+
+    /// Records something that is worth knowing about, but not an anomaly of the demo itself
+    pub(crate) fn record(&mut self, warning: Warning) {
+        self.warnings
+            .entry(warning)
+            .or_insert(WarningStats {
+                count: 0,
+                first_tick: self.tick,
+            })
+            .count += 1;
+    }
+
+    /// Forgets all the recorded warnings
+    pub(crate) fn clear(&mut self) {
+        self.warnings.clear();
+    }
+
+    /// Adds the warnings of a later part of the same demo
+    pub(crate) fn merge(&mut self, other: Diagnostics) {
+        for (warning, stats) in other.warnings {
+            self.warnings
+                .entry(warning)
+                .or_insert(WarningStats {
+                    count: 0,
+                    first_tick: stats.first_tick,
+                })
+                .count += stats.count;
+        }
+        self.tick = other.tick;
+    }
+
+    pub fn get(&self, warning: &Warning) -> Option<&WarningStats> {
+        self.warnings.get(warning)
+    }
+
+    /// All the recorded warnings, in no particular order
+    pub fn warnings(&self) -> impl Iterator<Item = (&Warning, &WarningStats)> {
+        self.warnings.iter()
+    }
+
+    /// The number of distinct warnings
+    pub fn len(&self) -> usize {
+        self.warnings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_first_tick() {
+        let mut diagnostics = Diagnostics::new();
+        assert!(diagnostics.is_empty());
+
+        diagnostics.set_tick(10);
+        diagnostics.record(Warning::UnknownMessageType(2));
+        diagnostics.set_tick(20);
+        diagnostics.record(Warning::UnknownMessageType(2));
+        diagnostics.record(Warning::MissingPropInfo(7));
+
+        assert_eq!(2, diagnostics.len());
+        assert_eq!(
+            Some(&WarningStats {
+                count: 2,
+                first_tick: 10
+            }),
+            diagnostics.get(&Warning::UnknownMessageType(2))
+        );
+        assert_eq!(
+            Some(&WarningStats {
+                count: 1,
+                first_tick: 20
+            }),
+            diagnostics.get(&Warning::MissingPropInfo(7))
+        );
+    }
+}
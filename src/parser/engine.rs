@@ -0,0 +1,1111 @@
+//! The core loop shared by all the parsers, which decodes the frames and net messages of a demo
+//! once and passes the results on to a [`DemoHandler`]
+
+use super::{
+    decoder, entities, propcontroller, sendtables, update_baselines, update_entity, Checkpoint,
+    Class, Diagnostics, ErrorContext, FirstPassError, GameEventMapping, ParseError, ParseOptions,
+    Paths, Warning,
+};
+use crate::{game_event::GameEvent, DemoCommand, Frame, NetmessageType};
+
+/// The string table related messages of a demo
+#[derive(Debug, Clone, Copy)]
+pub enum StringTableMessage<'a> {
+    /// A snapshot of all the string tables, from a `StringTables` frame or a `FullPacket`
+    Snapshot(&'a crate::csgo_proto::CDemoStringTables),
+    Create(&'a crate::csgo_proto::CsvcMsgCreateStringTable),
+    Update(&'a crate::csgo_proto::CsvcMsgUpdateStringTable),
+    ClearAll,
+}
+
+/// Receives the decoded parts of a demo from an [`Engine`].
+///
+/// Every callback has a default implementation that ignores its input, so a handler only needs
+/// to implement the parts of a demo it is interested in. Returning an error from a callback stops
+/// the parsing and returns that error from the engine, together with the
+/// [`ErrorContext`] of where it happened.
+#[allow(unused_variables)]
+pub trait DemoHandler {
+    fn on_file_header(
+        &mut self,
+        header: crate::csgo_proto::CDemoFileHeader,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    fn on_file_info(
+        &mut self,
+        info: crate::csgo_proto::CDemoFileInfo,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called for every `net_Tick` message, even if the tick did not change
+    fn on_tick(&mut self, tick: crate::csgo_proto::CnetMsgTick) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    fn on_server_info(
+        &mut self,
+        info: crate::csgo_proto::CsvcMsgServerInfo,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    fn on_game_event(&mut self, tick: u32, event: GameEvent) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called for every entity update, only if entities are enabled in the
+    /// [`EntityFilter`](entities::EntityFilter) of the engine
+    fn on_entity(&mut self, tick: u32, state: entities::EntityState) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called instead of [`on_entity`](DemoHandler::on_entity) for the first update of a newly
+    /// created entity, which is passed on to `on_entity` by default. Unlike the other updates,
+    /// the state contains all the props of the entity, including the ones from its baseline
+    fn on_entity_created(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity(tick, state)
+    }
+
+    /// Called instead of [`on_entity_created`](DemoHandler::on_entity_created) when an entity
+    /// that left the PVS is visible again, which is passed on to `on_entity_created` by default
+    fn on_entity_entered_pvs(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        self.on_entity_created(tick, state)
+    }
+
+    /// Called when an entity leaves the PVS, with a state without any props, only for entities
+    /// that pass the [`EntityFilter`](entities::EntityFilter) of the engine
+    fn on_entity_left_pvs(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called when an entity is deleted, with a state without any props, only for entities that
+    /// pass the [`EntityFilter`](entities::EntityFilter) of the engine
+    fn on_entity_deleted(
+        &mut self,
+        tick: u32,
+        state: entities::EntityState,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called for all the messages that the engine does not handle itself, like the user
+    /// messages, with the raw protobuf data of the message
+    fn on_user_message(
+        &mut self,
+        tick: u32,
+        msg_type: NetmessageType,
+        data: &[u8],
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    fn on_string_table(
+        &mut self,
+        tick: u32,
+        message: StringTableMessage<'_>,
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+
+    /// Called for every string table that was changed by a string table message, with the
+    /// changed entries as they are stored in the table now
+    fn on_string_table_update(
+        &mut self,
+        tick: u32,
+        table: &crate::StringTable,
+        entries: &[crate::StringTableEntry],
+    ) -> Result<(), FirstPassError> {
+        Ok(())
+    }
+}
+
+/// Holds all the state needed for decoding a demo, like the game event descriptors, the classes
+/// and the entities
+pub struct Engine {
+    buffer: Vec<u8>,
+    current_tick: u32,
+    frames: usize,
+    /// The byte offset of the next frame, see [`Checkpoint::offset`]
+    offset: usize,
+    context: ErrorContext,
+    diagnostics: Diagnostics,
+
+    event_mapper: GameEventMapping,
+    paths: Paths,
+    baselines: std::collections::HashMap<u32, Vec<u8>>,
+    serializers: std::collections::HashMap<String, sendtables::Serializer>,
+    /// Whether the SendTables were already loaded, which the serializers can not tell once the
+    /// ClassInfo moved them into the classes
+    seen_send_tables: bool,
+    qf_mapper: decoder::QfMapper,
+    prop_controller: propcontroller::PropController,
+    entity_ctx: entities::EntityContext,
+    string_tables: crate::StringTables,
+    roster: super::Roster,
+
+    // The raw messages the state above was built from, which are stored in checkpoints
+    send_tables: Option<Vec<u8>>,
+    class_info: Option<Vec<u8>>,
+    game_events: Option<Vec<u8>>,
+}
+
+impl Engine {
+    pub fn new(filter: entities::EntityFilter) -> Self {
+        Self {
+            buffer: Vec::new(),
+            current_tick: 0,
+            frames: 0,
+            offset: 0,
+            context: ErrorContext::default(),
+            diagnostics: Diagnostics::new(),
+
+            event_mapper: GameEventMapping {
+                mapping: std::collections::HashMap::new(),
+            },
+            paths: Paths::new(),
+            baselines: std::collections::HashMap::new(),
+            serializers: std::collections::HashMap::new(),
+            seen_send_tables: false,
+            qf_mapper: decoder::QfMapper {
+                idx: 0,
+                map: std::collections::HashMap::new(),
+            },
+            prop_controller: propcontroller::PropController::new(),
+            entity_ctx: entities::EntityContext {
+                entities: std::collections::HashMap::new(),
+                cls_to_class: std::collections::HashMap::new(),
+                filter,
+            },
+            string_tables: crate::StringTables::new(),
+            roster: super::Roster::new(),
+
+            send_tables: None,
+            class_info: None,
+            game_events: None,
+        }
+    }
+
+    /// Restores the state of the [`Checkpoint`], so that the engine can continue with the frame
+    /// following it.
+    ///
+    /// The filter is not part of the checkpoint, but the entities can only be decoded if they
+    /// were also enabled when the checkpoint was created
+    pub fn restore(
+        checkpoint: &Checkpoint,
+        filter: entities::EntityFilter,
+    ) -> Result<Self, FirstPassError> {
+        let mut engine = Self::new(filter);
+
+        engine.frames = checkpoint.frame;
+        engine.offset = checkpoint.offset;
+        engine.current_tick = checkpoint.tick;
+        engine.diagnostics.set_tick(checkpoint.tick);
+
+        // Same as for the frames, the classes are only needed for the entities
+        if engine.entity_ctx.filter.enabled {
+            if let Some(send_tables) = checkpoint.send_tables.as_ref() {
+                engine.load_send_tables(send_tables)?;
+            }
+            if let Some(class_info) = checkpoint.class_info.as_ref() {
+                engine.load_class_info(class_info)?;
+            }
+        }
+        if let Some(game_events) = checkpoint.game_events.as_ref() {
+            engine.load_game_events(game_events)?;
+        }
+        engine.seen_send_tables = checkpoint.seen_send_tables;
+        engine.send_tables = checkpoint.send_tables.clone();
+        engine.class_info = checkpoint.class_info.clone();
+        engine.game_events = checkpoint.game_events.clone();
+        engine.baselines = checkpoint.baselines.iter().cloned().collect();
+        engine.string_tables = checkpoint.string_tables.clone();
+        engine.roster = checkpoint.roster.clone();
+        engine.entity_ctx.entities = checkpoint.entities.iter().cloned().collect();
+
+        Ok(engine)
+    }
+
+    /// Captures the current state, which can be restored using [`Engine::restore`]
+    pub fn checkpoint(&self) -> Checkpoint {
+        let mut baselines: Vec<_> = self
+            .baselines
+            .iter()
+            .map(|(cls, data)| (*cls, data.clone()))
+            .collect();
+        baselines.sort_unstable_by_key(|(cls, _)| *cls);
+
+        let mut entities: Vec<_> = self
+            .entity_ctx
+            .entities
+            .iter()
+            .map(|(id, entity)| (*id, entity.clone()))
+            .collect();
+        entities.sort_unstable_by_key(|(id, _)| *id);
+
+        Checkpoint {
+            frame: self.frames,
+            offset: self.offset,
+            tick: self.current_tick,
+            seq: 0,
+            seen_send_tables: self.seen_send_tables,
+            send_tables: self.send_tables.clone(),
+            class_info: self.class_info.clone(),
+            game_events: self.game_events.clone(),
+            baselines,
+            entities,
+            string_tables: self.string_tables.clone(),
+            roster: self.roster.clone(),
+        }
+    }
+
+    /// Configures how anomalies of the demo are handled, see [`ParseOptions`]
+    pub fn with_options(mut self, options: ParseOptions) -> Self {
+        self.diagnostics.set_mode(options.mode);
+        self
+    }
+
+    /// The highest tick seen so far
+    pub fn current_tick(&self) -> u32 {
+        self.current_tick
+    }
+
+    /// The warnings recorded so far
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Forgets the warnings recorded so far, for when they are collected from somewhere else
+    pub(crate) fn clear_diagnostics(&mut self) {
+        self.diagnostics.clear();
+    }
+
+    /// The current state of all the string tables
+    pub fn string_tables(&self) -> &crate::StringTables {
+        &self.string_tables
+    }
+
+    /// The players of the demo up to the current tick
+    pub fn roster(&self) -> &super::Roster {
+        &self.roster
+    }
+
+    /// The complete current state of the entity, with every prop it got since it was created.
+    ///
+    /// The props are only kept for entities that pass the [`EntityFilter`](entities::EntityFilter)
+    pub fn entity(&self, entity_id: i32) -> Option<entities::EntityState> {
+        self.entity_ctx.state(entity_id)
+    }
+
+    /// The state the entity data of `svc_PacketEntities` messages is decoded with, for
+    /// decoding it outside of the engine
+    pub(crate) fn entity_decoding(
+        &mut self,
+    ) -> (
+        &entities::EntityContext,
+        &propcontroller::PropController,
+        &mut decoder::QfMapper,
+    ) {
+        (&self.entity_ctx, &self.prop_controller, &mut self.qf_mapper)
+    }
+
+    /// The ids of all the entities that currently exist
+    pub fn entity_ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.entity_ctx.entities.keys().copied()
+    }
+
+    /// Sets the number of frames that were already parsed and the byte offset of the next one,
+    /// for when the engine did not see all the frames before the current one
+    pub(crate) fn set_position(&mut self, frames: usize, offset: usize) {
+        self.frames = frames;
+        self.offset = offset;
+    }
+
+    /// Creates the error for a frame that could not be parsed in the first place, which counts
+    /// as the next frame for the context of the error
+    pub(crate) fn frame_error(&mut self, error: crate::FrameParseError) -> ParseError {
+        self.context = ErrorContext {
+            frame: Some(self.frames),
+            ..Default::default()
+        };
+        self.frames += 1;
+
+        self.error(FirstPassError::Frame(error))
+    }
+
+    /// Attaches the context of the current position in the demo to the error
+    fn error(&mut self, error: FirstPassError) -> ParseError {
+        let mut context = std::mem::take(&mut self.context);
+        context.tick = self.current_tick;
+        context.class = context
+            .entity_id
+            .and_then(|id| self.entity_ctx.entities.get(&id))
+            .and_then(|entity| self.entity_ctx.cls_to_class.get(&entity.cls))
+            .map(|class| class.name.clone());
+
+        ParseError { error, context }
+    }
+
+    /// Decodes all the given frames
+    pub fn run<'b, FI, H>(&mut self, frames: FI, handler: &mut H) -> Result<(), ParseError>
+    where
+        FI: IntoIterator<Item = Frame<'b>>,
+        H: DemoHandler,
+    {
+        for frame in frames.into_iter() {
+            self.handle_frame(&frame, handler)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes a single frame, which allows for stopping at any point, like the lazy parsers do
+    pub fn handle_frame<H>(&mut self, frame: &Frame<'_>, handler: &mut H) -> Result<(), ParseError>
+    where
+        H: DemoHandler,
+    {
+        self.context = ErrorContext {
+            frame: Some(self.frames),
+            ..Default::default()
+        };
+        self.frames += 1;
+        self.offset += frame.encoded_len();
+
+        self.decode_frame(frame, handler)
+            .map_err(|error| self.error(error))
+    }
+
+    fn decode_frame<H>(&mut self, frame: &Frame<'_>, handler: &mut H) -> Result<(), FirstPassError>
+    where
+        H: DemoHandler,
+    {
+        // The meta data about entities is expensive to decode, so it is skipped if the entities
+        // are not needed anyway
+        let entities = self.entity_ctx.filter.enabled;
+        if matches!(frame.cmd, DemoCommand::SendTables | DemoCommand::ClassInfo) && !entities {
+            return Ok(());
+        }
+
+        let data = frame
+            .decompress_with_buf(&mut self.buffer)
+            .map_err(FirstPassError::DecompressFrame)?;
+
+        match frame.cmd {
+            DemoCommand::FileHeader => {
+                let raw: crate::csgo_proto::CDemoFileHeader = prost::Message::decode(data)?;
+                handler.on_file_header(raw)?;
+            }
+            DemoCommand::FileInfo => {
+                let raw: crate::csgo_proto::CDemoFileInfo = prost::Message::decode(data)?;
+                handler.on_file_info(raw)?;
+            }
+            DemoCommand::SignonPacket | DemoCommand::Packet => {
+                let raw: crate::csgo_proto::CDemoPacket = prost::Message::decode(data)?;
+                self.parse_packet(&raw, handler)?;
+            }
+            DemoCommand::FullPacket => {
+                let raw: crate::csgo_proto::CDemoFullPacket = prost::Message::decode(data)?;
+
+                // A FullPacket contains a snapshot of all the string tables, so the baselines need
+                // to be updated before parsing the packet itself
+                if let Some(string_tables) = raw.string_table.as_ref() {
+                    self.handle_string_table(StringTableMessage::Snapshot(string_tables), handler)?;
+                }
+
+                if let Some(packet) = raw.packet.as_ref() {
+                    self.parse_packet(packet, handler)?;
+                }
+            }
+            // TODO
+            DemoCommand::AnimationData => {}
+            DemoCommand::AnimationHeader => {}
+            DemoCommand::StringTables => {
+                let raw: crate::csgo_proto::CDemoStringTables = prost::Message::decode(data)?;
+
+                self.handle_string_table(StringTableMessage::Snapshot(&raw), handler)?;
+            }
+            DemoCommand::SendTables => {
+                let tables: crate::csgo_proto::CDemoSendTables = prost::Message::decode(data)?;
+
+                let mut bitreader = crate::bitreader::Bitreader::new(tables.data());
+
+                let n_bytes = bitreader.read_varint()?;
+                let bytes = bitreader.read_n_bytes(n_bytes as usize)?;
+
+                if self.seen_send_tables {
+                    return Err(FirstPassError::DuplicateSendTables);
+                }
+                self.load_send_tables(&bytes)?;
+                self.seen_send_tables = true;
+                self.send_tables = Some(bytes);
+            }
+            DemoCommand::ClassInfo => {
+                let class_info = data.to_vec();
+                self.load_class_info(&class_info)?;
+                self.class_info = Some(class_info);
+            }
+            _ => {
+                // dbg!(other);
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Applies the message to the string tables and keeps the baselines and the roster in sync
+    /// with the `instancebaseline` and `userinfo` tables
+    fn handle_string_table<H>(
+        &mut self,
+        message: StringTableMessage<'_>,
+        handler: &mut H,
+    ) -> Result<(), FirstPassError>
+    where
+        H: DemoHandler,
+    {
+        let changed = match self.string_tables.apply(&message) {
+            Ok(changed) => changed,
+            Err(error) => {
+                let name = match (message, &error) {
+                    (_, crate::StringTableError::UnknownTableName(name)) => name.as_str(),
+                    (StringTableMessage::Create(raw), _) => raw.name(),
+                    (StringTableMessage::Update(raw), _) => usize::try_from(raw.table_id())
+                        .ok()
+                        .and_then(|id| self.string_tables.by_id(id))
+                        .map_or("", |table| table.name()),
+                    _ => "",
+                };
+                self.diagnostics
+                    .report(Warning::InvalidStringTable(name.to_owned()))?;
+                Vec::new()
+            }
+        };
+
+        if let StringTableMessage::Snapshot(raw) = message {
+            update_baselines(raw, &mut self.baselines);
+        }
+        handler.on_string_table(self.current_tick, message)?;
+
+        for (id, entries) in changed {
+            let Some(table) = self.string_tables.by_id(id) else {
+                continue;
+            };
+
+            if table.name() == "instancebaseline"
+                && !matches!(message, StringTableMessage::Snapshot(_))
+            {
+                for entry in entries.iter() {
+                    if let (Some(key), Some(value)) = (entry.key.as_ref(), entry.value.as_ref()) {
+                        let cls = key.parse::<u32>().unwrap_or(u32::MAX);
+                        self.baselines.insert(cls, value.clone());
+                    }
+                }
+            }
+
+            if table.name() == "userinfo" {
+                for entry in entries.iter() {
+                    let value = entry.value.as_deref().unwrap_or_default();
+                    if self
+                        .roster
+                        .update_userinfo(self.current_tick, entry.index, value)
+                        .is_err()
+                    {
+                        self.diagnostics
+                            .report(Warning::InvalidUserInfo(entry.index))?;
+                    }
+                }
+
+                if matches!(message, StringTableMessage::Snapshot(_)) {
+                    self.roster.disconnect_missing(self.current_tick, |slot| {
+                        table
+                            .get(slot)
+                            .and_then(|entry| entry.value.as_ref())
+                            .is_some_and(|value| !value.is_empty())
+                    });
+                }
+            }
+
+            handler.on_string_table_update(self.current_tick, table, &entries)?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the serializers from the `CsvcMsgFlattenedSerializer` stored in the SendTables
+    fn load_send_tables(&mut self, bytes: &[u8]) -> Result<(), FirstPassError> {
+        let serializer_msg: crate::csgo_proto::CsvcMsgFlattenedSerializer =
+            prost::Message::decode(bytes)?;
+
+        self.serializers = sendtables::get_serializers(
+            &serializer_msg,
+            &mut self.qf_mapper,
+            &mut self.prop_controller,
+        )?;
+        Ok(())
+    }
+
+    fn load_class_info(&mut self, data: &[u8]) -> Result<(), FirstPassError> {
+        let raw: crate::csgo_proto::CDemoClassInfo = prost::Message::decode(data)?;
+
+        self.entity_ctx.cls_to_class.clear();
+
+        for class_t in raw.classes {
+            let cls_id = class_t.class_id();
+            let network_name = class_t.network_name();
+
+            if let Some(ser) = self.serializers.remove(network_name) {
+                self.entity_ctx.cls_to_class.insert(
+                    cls_id as u32,
+                    Class {
+                        name: network_name.into(),
+                        serializer: ser,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn load_game_events(&mut self, msg_bytes: &[u8]) -> Result<(), FirstPassError> {
+        let event_list: crate::csgo_proto::CsvcMsgGameEventList =
+            prost::Message::decode(msg_bytes)?;
+
+        self.event_mapper.mapping.clear();
+        for event in event_list.descriptors {
+            self.event_mapper
+                .mapping
+                .insert(event.eventid(), (event.name().to_owned(), event.keys));
+        }
+        Ok(())
+    }
+
+    fn parse_packet<H>(
+        &mut self,
+        raw: &crate::csgo_proto::CDemoPacket,
+        handler: &mut H,
+    ) -> Result<(), FirstPassError>
+    where
+        H: DemoHandler,
+    {
+        let mut bitreader = crate::bitreader::Bitreader::new(raw.data());
+
+        let mut msg_bytes = Vec::new();
+
+        while bitreader.bits_remaining().unwrap_or(0) > 8 {
+            let msg_type = bitreader.read_u_bit_var()?;
+            let size = bitreader.read_varint()?;
+            if size as usize > bitreader.bits_remaining().unwrap_or(0) / 8 {
+                return Err(crate::bitreader::BitReadError::NotEnoughBits.into());
+            }
+            msg_bytes.clear();
+            msg_bytes.resize(size as usize, 0);
+            bitreader.read_n_bytes_mut(size as usize, &mut msg_bytes)?;
+
+            let net_msg_type = match NetmessageType::try_from(msg_type as i32) {
+                Ok(v) => v,
+                Err(e) => {
+                    self.diagnostics.report(Warning::UnknownMessageType(e))?;
+                    continue;
+                }
+            };
+
+            self.context.message = Some(net_msg_type);
+            self.parse_message(net_msg_type, &msg_bytes, handler)?;
+            self.context.message = None;
+        }
+
+        Ok(())
+    }
+
+    fn parse_message<H>(
+        &mut self,
+        net_msg_type: NetmessageType,
+        msg_bytes: &[u8],
+        handler: &mut H,
+    ) -> Result<(), FirstPassError>
+    where
+        H: DemoHandler,
+    {
+        match net_msg_type {
+            NetmessageType::svc_ClearAllStringTables => {
+                self.handle_string_table(StringTableMessage::ClearAll, handler)?;
+            }
+            NetmessageType::svc_CreateStringTable => {
+                let raw: crate::csgo_proto::CsvcMsgCreateStringTable =
+                    prost::Message::decode(msg_bytes)?;
+
+                self.handle_string_table(StringTableMessage::Create(&raw), handler)?;
+            }
+            NetmessageType::svc_UpdateStringTable => {
+                let raw: crate::csgo_proto::CsvcMsgUpdateStringTable =
+                    prost::Message::decode(msg_bytes)?;
+
+                self.handle_string_table(StringTableMessage::Update(&raw), handler)?;
+            }
+            NetmessageType::GE_Source1LegacyGameEventList => {
+                self.load_game_events(msg_bytes)?;
+                self.game_events = Some(msg_bytes.to_vec());
+            }
+            NetmessageType::svc_ServerInfo => {
+                let raw: crate::csgo_proto::CsvcMsgServerInfo = prost::Message::decode(msg_bytes)?;
+
+                handler.on_server_info(raw)?;
+            }
+            NetmessageType::net_Tick => {
+                let raw: crate::csgo_proto::CnetMsgTick = prost::Message::decode(msg_bytes)?;
+
+                if raw.tick() < self.current_tick {
+                    self.diagnostics.report(Warning::TickOutOfOrder {
+                        current: self.current_tick,
+                        tick: raw.tick(),
+                    })?;
+                    return Ok(());
+                }
+                self.current_tick = raw.tick();
+                self.diagnostics.set_tick(self.current_tick);
+
+                handler.on_tick(raw)?;
+            }
+            NetmessageType::svc_PacketEntities => {
+                if self.entity_ctx.filter.enabled {
+                    let raw: crate::csgo_proto::CsvcMsgPacketEntities =
+                        prost::Message::decode(msg_bytes)?;
+
+                    self.parse_entities(&raw, handler)?;
+                }
+            }
+            NetmessageType::GE_Source1LegacyGameEvent => {
+                let raw: crate::csgo_proto::CMsgSource1LegacyGameEvent =
+                    prost::Message::decode(msg_bytes)?;
+
+                match self.event_mapper.mapping.get(&raw.eventid()) {
+                    Some((name, keys)) => {
+                        match crate::game_event::EVENT_PARSERS.get(name) {
+                            Some(parser) => match parser.parse(keys.as_slice(), raw) {
+                                Ok(parsed) => {
+                                    self.roster.handle_event(self.current_tick, &parsed);
+                                    handler.on_game_event(self.current_tick, parsed)?;
+                                }
+                                Err(
+                                    crate::game_event::ParseGameEventError::MismatchedKeysFields,
+                                ) => {
+                                    self.diagnostics
+                                        .report(Warning::MismatchedGameEventKeys(name.clone()))?;
+                                }
+                            },
+                            // Not every game event has a parser yet, which is not an anomaly of
+                            // the demo itself
+                            None => {
+                                self.diagnostics
+                                    .record(Warning::UnknownGameEvent(name.clone()));
+                            }
+                        };
+                    }
+                    None => {
+                        self.diagnostics
+                            .report(Warning::UnknownGameEventId(raw.eventid()))?;
+                    }
+                };
+            }
+            other => {
+                handler.on_user_message(self.current_tick, other, msg_bytes)?;
+            }
+        };
+
+        Ok(())
+    }
+
+    fn parse_entities<H>(
+        &mut self,
+        raw: &crate::csgo_proto::CsvcMsgPacketEntities,
+        handler: &mut H,
+    ) -> Result<(), FirstPassError>
+    where
+        H: DemoHandler,
+    {
+        let mut bitreader = crate::bitreader::Bitreader::new(raw.entity_data());
+        let mut entity_id: i32 = -1;
+        for _ in 0..raw.updated_entries() {
+            entity_id = entity_id.wrapping_add(1 + bitreader.read_u_bit_var()? as i32);
+            self.context.entity_id = Some(entity_id);
+
+            // The first bit is set when the entity leaves the PVS, the second one when it is
+            // deleted or, without the first one, created
+            match bitreader.read_nbits(2)? {
+                0b01 => {
+                    if let Some(state) = self.entity_ctx.leave_pvs(entity_id) {
+                        handler.on_entity_left_pvs(self.current_tick, state)?;
+                    }
+                }
+                0b11 => {
+                    if let Some(state) = self.entity_ctx.delete_entity(entity_id) {
+                        handler.on_entity_deleted(self.current_tick, state)?;
+                    }
+                }
+                0b10 => {
+                    let (cls, op) = self.entity_ctx.create_entity(entity_id, &mut bitreader)?;
+
+                    if let Some(baseline_bytes) = self.baselines.get(&cls) {
+                        let mut br = crate::bitreader::Bitreader::new(baseline_bytes);
+
+                        // The props of the baseline are stored in the entity, the create
+                        // packet only contains the props that differ from it
+                        let baseline = update_entity(
+                            entity_id,
+                            &mut br,
+                            &mut self.entity_ctx,
+                            &mut self.paths,
+                            &mut self.qf_mapper,
+                            &self.prop_controller,
+                            &mut self.diagnostics,
+                        );
+                        match baseline {
+                            Err(e) if self.diagnostics.is_strict() => return Err(e),
+                            Err(_) => self.diagnostics.record(Warning::BaselineDecode(cls)),
+                            Ok(_) => {}
+                        }
+                    }
+
+                    let state = update_entity(
+                        entity_id,
+                        &mut bitreader,
+                        &mut self.entity_ctx,
+                        &mut self.paths,
+                        &mut self.qf_mapper,
+                        &self.prop_controller,
+                        &mut self.diagnostics,
+                    )?;
+                    if state.is_some() {
+                        if let Some(mut state) = self.entity_ctx.state(entity_id) {
+                            state.op = op;
+                            match op {
+                                entities::EntityOp::EnteredPVS => {
+                                    handler.on_entity_entered_pvs(self.current_tick, state)?
+                                }
+                                _ => handler.on_entity_created(self.current_tick, state)?,
+                            }
+                        }
+                    }
+                }
+                0b00 => {
+                    if raw.has_pvs_vis_bits() > 0 && bitreader.read_nbits(2)? & 0x01 == 1 {
+                        continue;
+                    }
+
+                    let state = update_entity(
+                        entity_id,
+                        &mut bitreader,
+                        &mut self.entity_ctx,
+                        &mut self.paths,
+                        &mut self.qf_mapper,
+                        &self.prop_controller,
+                        &mut self.diagnostics,
+                    )?;
+                    if let Some(state) = state {
+                        handler.on_entity(self.current_tick, state)?;
+                    }
+                }
+                unknown => {
+                    return Err(FirstPassError::InvalidEntityOperation(unknown));
+                }
+            };
+        }
+        self.context.entity_id = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Nothing;
+    impl DemoHandler for Nothing {}
+
+    #[test]
+    fn error_context() {
+        // A FileHeader followed by a Packet containing garbage
+        let data = [1, 0, 0, 7, 5, 1, 0xff];
+        let frames = crate::FrameIterator::parse(&data);
+
+        let mut engine = Engine::new(entities::EntityFilter::disabled());
+        let error = engine.run(frames, &mut Nothing).unwrap_err();
+
+        assert!(matches!(error.error, FirstPassError::DecodeProtobuf(_)));
+        assert_eq!(Some(1), error.context.frame);
+        assert_eq!(None, error.context.message);
+        assert!(error.to_string().contains("frame 1"));
+    }
+
+    #[test]
+    fn tick_out_of_order() {
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        for tick in [10, 5] {
+            let bytes = prost::Message::encode_to_vec(&crate::csgo_proto::CnetMsgTick {
+                tick: Some(tick),
+                ..Default::default()
+            });
+            // The id of net_Tick
+            writer.write_u_bit_var(4);
+            writer.write_varint(bytes.len() as u32);
+            writer.write_bytes(&bytes);
+        }
+        let packet = crate::csgo_proto::CDemoPacket {
+            data: Some(writer.into_bytes()),
+        };
+        let frame = Frame {
+            cmd: DemoCommand::Packet,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(&packet)),
+        };
+
+        let mut engine =
+            Engine::new(entities::EntityFilter::disabled()).with_options(ParseOptions::strict());
+        let error = engine.handle_frame(&frame, &mut Nothing).unwrap_err();
+
+        assert!(matches!(
+            error.error,
+            FirstPassError::Anomaly(Warning::TickOutOfOrder {
+                current: 10,
+                tick: 5
+            })
+        ));
+        assert_eq!(Some(NetmessageType::net_Tick), error.context.message);
+
+        let mut engine =
+            Engine::new(entities::EntityFilter::disabled()).with_options(ParseOptions::lenient());
+        engine.handle_frame(&frame, &mut Nothing).unwrap();
+
+        assert_eq!(10, engine.current_tick());
+        assert_eq!(
+            1,
+            engine
+                .diagnostics()
+                .get(&Warning::TickOutOfOrder {
+                    current: 10,
+                    tick: 5
+                })
+                .unwrap()
+                .count
+        );
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        for _ in 0..2 {
+            // There is no net message with the id 2
+            writer.write_u_bit_var(2);
+            writer.write_varint(0);
+        }
+        let packet = crate::csgo_proto::CDemoPacket {
+            data: Some(writer.into_bytes()),
+        };
+        let frame = Frame {
+            cmd: DemoCommand::Packet,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(&packet)),
+        };
+
+        let mut engine = Engine::new(entities::EntityFilter::disabled());
+        engine.handle_frame(&frame, &mut Nothing).unwrap();
+
+        let stats = engine
+            .diagnostics()
+            .get(&Warning::UnknownMessageType(2))
+            .unwrap();
+        assert_eq!(2, stats.count);
+        assert_eq!(0, stats.first_tick);
+    }
+
+    /// A Packet frame containing the given messages
+    fn packet_frame(messages: &[(u32, Vec<u8>)]) -> Frame<'static> {
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        for (id, bytes) in messages {
+            writer.write_u_bit_var(*id);
+            writer.write_varint(bytes.len() as u32);
+            writer.write_bytes(bytes);
+        }
+        let packet = crate::csgo_proto::CDemoPacket {
+            data: Some(writer.into_bytes()),
+        };
+        Frame {
+            cmd: DemoCommand::Packet,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(&packet)),
+        }
+    }
+
+    #[test]
+    fn checkpoint_restore() {
+        use crate::csgo_proto::{c_demo_string_tables, csvc_msg_game_event_list};
+
+        let string_tables = crate::csgo_proto::CDemoStringTables {
+            tables: vec![c_demo_string_tables::TableT {
+                table_name: Some("instancebaseline".to_owned()),
+                items: vec![c_demo_string_tables::ItemsT {
+                    str: Some("3".to_owned()),
+                    data: Some(vec![1, 2]),
+                }],
+                ..Default::default()
+            }],
+        };
+        let string_tables = Frame {
+            cmd: DemoCommand::StringTables,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(&string_tables)),
+        };
+
+        let event_list = crate::csgo_proto::CsvcMsgGameEventList {
+            descriptors: vec![csvc_msg_game_event_list::DescriptorT {
+                eventid: Some(1),
+                name: Some("test_event".to_owned()),
+                keys: Vec::new(),
+            }],
+        };
+        let tick = crate::csgo_proto::CnetMsgTick {
+            tick: Some(7),
+            ..Default::default()
+        };
+        // The ids of GE_Source1LegacyGameEventList and net_Tick
+        let signon = packet_frame(&[
+            (205, prost::Message::encode_to_vec(&event_list)),
+            (4, prost::Message::encode_to_vec(&tick)),
+        ]);
+
+        let mut engine = Engine::new(entities::EntityFilter::disabled());
+        engine.handle_frame(&string_tables, &mut Nothing).unwrap();
+        engine.handle_frame(&signon, &mut Nothing).unwrap();
+
+        let checkpoint = engine.checkpoint();
+        assert_eq!(2, checkpoint.frame());
+        assert_eq!(
+            string_tables.encoded_len() + signon.encoded_len(),
+            checkpoint.offset()
+        );
+        assert_eq!(7, checkpoint.tick());
+
+        let checkpoint = Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+        let mut restored =
+            Engine::restore(&checkpoint, entities::EntityFilter::disabled()).unwrap();
+        assert_eq!(checkpoint, restored.checkpoint());
+        assert_eq!(7, restored.current_tick());
+
+        // The event is known from the restored event list, but there is no parser for it
+        let event = crate::csgo_proto::CMsgSource1LegacyGameEvent {
+            eventid: Some(1),
+            ..Default::default()
+        };
+        // The id of GE_Source1LegacyGameEvent
+        let frame = packet_frame(&[(207, prost::Message::encode_to_vec(&event))]);
+        restored.handle_frame(&frame, &mut Nothing).unwrap();
+
+        let diagnostics = restored.diagnostics();
+        assert!(diagnostics
+            .get(&Warning::UnknownGameEvent("test_event".to_owned()))
+            .is_some());
+        assert!(diagnostics.get(&Warning::UnknownGameEventId(1)).is_none());
+    }
+
+    #[test]
+    fn string_table_baselines() {
+        let info = crate::StringTableInfo {
+            name: "instancebaseline".to_owned(),
+            user_data_fixed_size: false,
+            user_data_size_bits: 0,
+            flags: 0,
+            using_varint_bitcounts: true,
+        };
+        let entry = |index, key: &str, value: Vec<u8>| crate::StringTableEntry {
+            index,
+            key: Some(key.to_owned()),
+            value: Some(value),
+        };
+
+        let created = [entry(0, "3", vec![1]), entry(1, "5", vec![2])];
+        let create = crate::csgo_proto::CsvcMsgCreateStringTable {
+            name: Some(info.name.clone()),
+            num_entries: Some(created.len() as i32),
+            user_data_fixed_size: Some(false),
+            flags: Some(0),
+            using_varint_bitcounts: Some(true),
+            string_data: Some(info.write_entries(&created)),
+            ..Default::default()
+        };
+        let updated = [entry(1, "5", vec![3])];
+        let update = crate::csgo_proto::CsvcMsgUpdateStringTable {
+            table_id: Some(0),
+            num_changed_entries: Some(updated.len() as i32),
+            string_data: Some(info.write_entries(&updated)),
+        };
+        // An update of a table that does not exist
+        let invalid = crate::csgo_proto::CsvcMsgUpdateStringTable {
+            table_id: Some(4),
+            num_changed_entries: Some(0),
+            string_data: Some(Vec::new()),
+        };
+        // The ids of svc_CreateStringTable and svc_UpdateStringTable
+        let frame = packet_frame(&[
+            (44, prost::Message::encode_to_vec(&create)),
+            (45, prost::Message::encode_to_vec(&update)),
+            (45, prost::Message::encode_to_vec(&invalid)),
+        ]);
+
+        let mut engine =
+            Engine::new(entities::EntityFilter::disabled()).with_options(ParseOptions::lenient());
+        engine.handle_frame(&frame, &mut Nothing).unwrap();
+
+        let table = engine.string_tables().get("instancebaseline").unwrap();
+        assert_eq!(2, table.len());
+        assert_eq!(Some(&vec![3]), table.find("5").unwrap().value.as_ref());
+        assert_eq!(Some(&vec![1]), engine.baselines.get(&3));
+        assert_eq!(Some(&vec![3]), engine.baselines.get(&5));
+        assert!(engine
+            .diagnostics()
+            .get(&Warning::InvalidStringTable(String::new()))
+            .is_some());
+    }
+
+    #[test]
+    fn duplicate_send_tables() {
+        // SendTables without any serializers, which leaves the serializers empty
+        let mut data = crate::bitwriter::Bitwriter::new();
+        data.write_varint(0);
+        let tables = crate::csgo_proto::CDemoSendTables {
+            data: Some(data.into_bytes()),
+        };
+        let frame = Frame {
+            cmd: DemoCommand::SendTables,
+            tick: 0,
+            compressed: false,
+            inner: std::borrow::Cow::Owned(prost::Message::encode_to_vec(&tables)),
+        };
+
+        let mut engine = Engine::new(entities::EntityFilter::all());
+        engine.handle_frame(&frame, &mut Nothing).unwrap();
+
+        let mut restored =
+            Engine::restore(&engine.checkpoint(), entities::EntityFilter::all()).unwrap();
+        for engine in [&mut engine, &mut restored] {
+            let error = engine.handle_frame(&frame, &mut Nothing).unwrap_err();
+            assert!(matches!(error.error, FirstPassError::DuplicateSendTables));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::{decoder, propcontroller, Class, Entity, FirstPassError, Paths};
+use super::{decoder, propcontroller, Class, Diagnostics, Entity, FirstPassError, Paths, Warning};
 
 use std::sync::Arc;
 
@@ -8,11 +8,29 @@ pub struct EntityContext {
     pub filter: EntityFilter,
 }
 
+/// What happened to an entity in an [`EntityState`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntityOp {
+    /// The entity was created, the state contains all of its props
+    Created,
+    /// Some props of the entity changed, the state only contains the changed props
+    Updated,
+    /// The entity is visible again after it left the PVS, the state contains all of its props
+    EnteredPVS,
+    /// The entity is no longer visible, but still exists. The state contains no props
+    LeftPVS,
+    /// The entity was deleted, the state contains no props
+    Deleted,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct EntityState {
     pub id: i32,
     pub class: Arc<str>,
     pub cls: u32,
+    /// Tells apart the entities that use the same id one after the other
+    pub serial: u32,
+    pub op: EntityOp,
     pub props: Vec<EntityProp>,
 }
 
@@ -24,7 +42,7 @@ pub struct EntityProp {
 
 pub struct EntityFilter {
     pub enabled: bool,
-    entity: Box<dyn FnMut(&str) -> bool>,
+    entity: Box<dyn FnMut(&str) -> bool + Send>,
 }
 
 impl EntityFilter {
@@ -44,21 +62,86 @@ impl EntityFilter {
 }
 
 impl EntityContext {
-    /// Returns the `cls_id`
+    /// Returns the `cls_id` and whether the entity was created or only entered the PVS again,
+    /// in which case it keeps its serial
     pub fn create_entity(
         &mut self,
         entity_id: i32,
         bitreader: &mut crate::bitreader::Bitreader,
-    ) -> Result<u32, super::FirstPassError> {
+    ) -> Result<(u32, EntityOp), super::FirstPassError> {
         let cls_id: u32 = bitreader.read_nbits(8)?;
-        let _serial = bitreader.read_nbits(17)?;
+        let serial = bitreader.read_nbits(17)?;
         let _unknown = bitreader.read_varint()?;
 
-        self.entities.insert(entity_id, Entity { cls: cls_id });
+        let op = match self.entities.get(&entity_id) {
+            Some(entity) if !entity.in_pvs && entity.serial == serial && entity.cls == cls_id => {
+                EntityOp::EnteredPVS
+            }
+            _ => EntityOp::Created,
+        };
 
-        Ok(cls_id)
+        self.entities.insert(
+            entity_id,
+            Entity {
+                cls: cls_id,
+                serial,
+                in_pvs: true,
+                props: std::collections::BTreeMap::new(),
+            },
+        );
+
+        Ok((cls_id, op))
+    }
+
+    /// Marks the entity as no longer being in the PVS and returns its state, if the entity
+    /// passes the filter
+    pub fn leave_pvs(&mut self, entity_id: i32) -> Option<EntityState> {
+        let entity = self.entities.get_mut(&entity_id)?;
+        entity.in_pvs = false;
+        self.lifecycle_state(entity_id, EntityOp::LeftPVS)
     }
 
+    /// Removes the entity and returns its last state, if the entity passes the filter
+    pub fn delete_entity(&mut self, entity_id: i32) -> Option<EntityState> {
+        let state = self.lifecycle_state(entity_id, EntityOp::Deleted);
+        self.entities.remove(&entity_id);
+        state
+    }
+
+    /// The state of the entity without any props, for the operations that do not change them
+    fn lifecycle_state(&mut self, entity_id: i32, op: EntityOp) -> Option<EntityState> {
+        let entity = self.entities.get(&entity_id)?;
+        let class = self.cls_to_class.get(&entity.cls)?;
+
+        if !(self.filter.entity)(class.name.as_ref()) {
+            return None;
+        }
+        Some(EntityState {
+            id: entity_id,
+            class: class.name.clone(),
+            cls: entity.cls,
+            serial: entity.serial,
+            op,
+            props: Vec::new(),
+        })
+    }
+
+    /// The complete current state of the entity, with every prop it got since it was created
+    pub fn state(&self, entity_id: i32) -> Option<EntityState> {
+        let entity = self.entities.get(&entity_id)?;
+        let class = self.cls_to_class.get(&entity.cls)?;
+
+        Some(EntityState {
+            id: entity_id,
+            class: class.name.clone(),
+            cls: entity.cls,
+            serial: entity.serial,
+            op: EntityOp::Updated,
+            props: entity.props.values().cloned().collect(),
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn decode_entity_update(
         &mut self,
         entity_id: i32,
@@ -67,14 +150,15 @@ impl EntityContext {
         paths: &mut Paths,
         qf_mapper: &mut decoder::QfMapper,
         prop_controller: &propcontroller::PropController,
+        diagnostics: &mut Diagnostics,
     ) -> Result<Option<(usize, EntityState)>, FirstPassError> {
         let entity = match self.entities.get_mut(&entity_id) {
             Some(e) => e,
-            None => panic!("ID: {:?} - Entities: {:?}", entity_id, self.entities),
+            None => return Err(FirstPassError::UnknownEntity(entity_id)),
         };
         let class = match self.cls_to_class.get_mut(&entity.cls) {
             Some(c) => c,
-            None => panic!(),
+            None => return Err(FirstPassError::UnknownClass(entity.cls)),
         };
 
         let mut fields = Vec::with_capacity(n_updates);
@@ -91,7 +175,7 @@ impl EntityContext {
                         value: result,
                     });
                 } else {
-                    // println!("Missing PropInfo for {:?} = {:?}", fi, result);
+                    diagnostics.report(Warning::MissingPropInfo(fi.prop_id))?;
                 }
             } else {
                 // println!("Missing Field Info for {:?} with {:?} = {:?}", field, path, result);
@@ -102,12 +186,18 @@ impl EntityContext {
             return Ok(None);
         }
 
+        for field in fields.iter() {
+            entity.props.insert(field.prop_info.id, field.clone());
+        }
+
         Ok(Some((
             n_updates,
             EntityState {
                 id: entity_id,
                 class: class.name.clone(),
                 cls: entity.cls,
+                serial: entity.serial,
+                op: EntityOp::Updated,
                 props: fields,
             },
         )))
@@ -121,3 +211,61 @@ impl EntityState {
             .find(|p| p.prop_info.prop_name.as_ref() == name)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The header of an entity create, with its class and serial
+    fn create(ctx: &mut EntityContext, entity_id: i32, cls: u32, serial: u32) -> EntityOp {
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        writer.write_nbits(cls, 8);
+        writer.write_nbits(serial, 17);
+        writer.write_varint(0);
+        let data = writer.into_bytes();
+
+        let mut bitreader = crate::bitreader::Bitreader::new(&data);
+        let (created_cls, op) = ctx.create_entity(entity_id, &mut bitreader).unwrap();
+        assert_eq!(cls, created_cls);
+        op
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut ctx = EntityContext {
+            entities: std::collections::HashMap::new(),
+            cls_to_class: [(
+                3,
+                Class {
+                    name: "CSmokeGrenadeProjectile".into(),
+                    serializer: super::super::sendtables::Serializer {
+                        name: "CSmokeGrenadeProjectile".to_owned(),
+                        fields: Vec::new(),
+                    },
+                },
+            )]
+            .into_iter()
+            .collect(),
+            filter: EntityFilter::all(),
+        };
+
+        assert_eq!(EntityOp::Created, create(&mut ctx, 5, 3, 10));
+
+        let left = ctx.leave_pvs(5).unwrap();
+        assert_eq!(EntityOp::LeftPVS, left.op);
+        assert_eq!(10, left.serial);
+        assert!(ctx.entities.contains_key(&5));
+
+        assert_eq!(EntityOp::EnteredPVS, create(&mut ctx, 5, 3, 10));
+
+        let deleted = ctx.delete_entity(5).unwrap();
+        assert_eq!(EntityOp::Deleted, deleted.op);
+        assert_eq!("CSmokeGrenadeProjectile", deleted.class.as_ref());
+        assert!(!ctx.entities.contains_key(&5));
+
+        // The id is reused by a new entity
+        assert_eq!(EntityOp::Created, create(&mut ctx, 5, 3, 11));
+        ctx.leave_pvs(5).unwrap();
+        assert_eq!(EntityOp::Created, create(&mut ctx, 5, 3, 12));
+    }
+}
@@ -55,7 +55,15 @@ pub fn parse_paths(
         }
 
         let peeked_bits = bitreader.peek(17);
-        let (symbol, code_len) = super::HUFFMAN_LOOKUP_TABLE[peeked_bits as usize];
+        let (symbol, code_len) = match super::HUFFMAN_LOOKUP_TABLE.get(peeked_bits as usize) {
+            Some(entry) => *entry,
+            None => return Err(super::FirstPassError::InvalidFieldPath(path)),
+        };
+        // The peeked bits are padded with zeros at the end of the data, so the code might not
+        // actually be there
+        if code_len == 0 || bitreader.bits_left < code_len as u32 {
+            return Err(crate::bitreader::BitReadError::NotEnoughBits.into());
+        }
         bitreader.consume(code_len as u32);
 
         if symbol == 39 {
@@ -74,25 +82,36 @@ impl FieldPath {
     pub fn pop_special(&mut self, n: usize) -> Result<(), super::FirstPassError> {
         for _ in 0..n {
             *self.get_entry_mut(self.last)? = 0;
-            self.last -= 1;
+            self.last = match self.last.checked_sub(1) {
+                Some(last) => last,
+                None => return Err(super::FirstPassError::InvalidFieldPath(*self)),
+            };
         }
         Ok(())
     }
 
     pub fn get_entry_mut(&mut self, idx: usize) -> Result<&mut i32, super::FirstPassError> {
+        let path = *self;
         match self.path.get_mut(idx) {
             Some(e) => Ok(e),
-            None => panic!(),
+            None => Err(super::FirstPassError::InvalidFieldPath(path)),
         }
     }
 
+    /// Adds to an entry of the path, wrapping around instead of overflowing for malformed data
+    pub fn add_entry(&mut self, idx: usize, n: i32) -> Result<(), super::FirstPassError> {
+        let entry = self.get_entry_mut(idx)?;
+        *entry = entry.wrapping_add(n);
+        Ok(())
+    }
+
     pub fn find<'ser>(
         &self,
         ser: &'ser super::sendtables::Serializer,
     ) -> Result<&'ser super::sendtables::Field, super::FirstPassError> {
         let f = match ser.fields.get(self.path[0] as usize) {
             Some(entry) => entry,
-            None => panic!("Field-Len: {:?} - Path: {:?}", ser.fields.len(), self.path),
+            None => return Err(super::FirstPassError::InvalidFieldPath(*self)),
         };
 
         match self.last {
@@ -105,7 +124,7 @@ impl FieldPath {
                 .get_inner(self.path[1] as usize)?
                 .get_inner(self.path[2] as usize)?
                 .get_inner(self.path[3] as usize)?),
-            other => panic!("{:?}", other),
+            _ => Err(super::FirstPassError::InvalidFieldPath(*self)),
         }
     }
 
@@ -156,7 +175,7 @@ impl FieldPath {
             36 => non_topo_complex(bitreader, self),
             37 => non_topo_penultimate_plus_one(bitreader, self),
             38 => non_topo_complex_pack4_bits(bitreader, self),
-            other => todo!("Other OP: {:?}", other),
+            _ => Err(super::FirstPassError::InvalidFieldPath(*self)),
         }
     }
 }
@@ -169,7 +188,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         Ok(())
     }
 
@@ -177,7 +196,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 2;
+        field_path.add_entry(field_path.last, 2)?;
         Ok(())
     }
 
@@ -185,7 +204,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 3;
+        field_path.add_entry(field_path.last, 3)?;
         Ok(())
     }
 
@@ -193,7 +212,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 4;
+        field_path.add_entry(field_path.last, 4)?;
         Ok(())
     }
 
@@ -201,7 +220,10 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32 + 5;
+        field_path.add_entry(
+            field_path.last,
+            (bitreader.read_ubit_var_fp()? as i32).wrapping_add(5),
+        )?;
         Ok(())
     }
 
@@ -219,7 +241,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -227,7 +249,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
         *field_path.get_entry_mut(field_path.last)? = 0;
         Ok(())
@@ -237,7 +259,7 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
         *field_path.get_entry_mut(field_path.last)? = bitreader.read_ubit_var_fp()? as i32;
         Ok(())
@@ -247,7 +269,7 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
         *field_path.get_entry_mut(field_path.last)? = 0;
         Ok(())
@@ -257,9 +279,13 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32 + 2;
+        field_path.add_entry(
+            field_path.last,
+            (bitreader.read_ubit_var_fp()? as i32).wrapping_add(2),
+        )?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? = bitreader.read_ubit_var_fp()? as i32 + 1;
+        *field_path.get_entry_mut(field_path.last)? =
+            (bitreader.read_ubit_var_fp()? as i32).wrapping_add(1);
         Ok(())
     }
 
@@ -267,7 +293,7 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_nbits(3)? + 2) as i32;
+        field_path.add_entry(field_path.last, (bitreader.read_nbits(3)? + 2) as i32)?;
         field_path.last += 1;
         *field_path.get_entry_mut(field_path.last)? = (bitreader.read_nbits(3)? + 1) as i32;
         Ok(())
@@ -277,7 +303,7 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_nbits(4)? + 2) as i32;
+        field_path.add_entry(field_path.last, (bitreader.read_nbits(4)? + 2) as i32)?;
         field_path.last += 1;
         *field_path.get_entry_mut(field_path.last)? = (bitreader.read_nbits(4)? + 1) as i32;
         Ok(())
@@ -288,9 +314,9 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -310,11 +336,11 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -335,11 +361,11 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -347,11 +373,11 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         Ok(())
     }
 
@@ -359,13 +385,13 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -373,13 +399,13 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         Ok(())
     }
 
@@ -387,11 +413,14 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_u_bit_var()? + 2) as i32;
+        field_path.add_entry(
+            field_path.last,
+            bitreader.read_u_bit_var()?.wrapping_add(2) as i32,
+        )?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -399,11 +428,14 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_u_bit_var()? + 2) as i32;
+        field_path.add_entry(
+            field_path.last,
+            bitreader.read_u_bit_var()?.wrapping_add(2) as i32,
+        )?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         Ok(())
     }
 
@@ -411,13 +443,16 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_u_bit_var()? + 2) as i32;
+        field_path.add_entry(
+            field_path.last,
+            bitreader.read_u_bit_var()?.wrapping_add(2) as i32,
+        )?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         Ok(())
     }
 
@@ -425,13 +460,16 @@ pub mod ops {
         bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last)? += (bitreader.read_u_bit_var()? + 2) as i32;
+        field_path.add_entry(
+            field_path.last,
+            bitreader.read_u_bit_var()?.wrapping_add(2) as i32,
+        )?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         field_path.last += 1;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_nbits(5)? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_nbits(5)? as i32)?;
         Ok(())
     }
 
@@ -440,10 +478,10 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         let n = bitreader.read_u_bit_var()? as i32;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_u_bit_var()? as i32;
+        field_path.add_entry(field_path.last, bitreader.read_u_bit_var()? as i32)?;
         for _ in 0..n {
             field_path.last += 1;
-            *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32;
+            field_path.add_entry(field_path.last, bitreader.read_ubit_var_fp()? as i32)?;
         }
         Ok(())
     }
@@ -454,7 +492,7 @@ pub mod ops {
     ) -> Result<(), FirstPassError> {
         for i in 0..field_path.last + 1 {
             if bitreader.read_boolean()? {
-                *field_path.get_entry_mut(i)? += bitreader.read_varint32()? + 1;
+                field_path.add_entry(i, bitreader.read_varint32()?.wrapping_add(1))?;
             }
         }
         let count = bitreader.read_u_bit_var()?;
@@ -470,7 +508,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(1)?;
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         Ok(())
     }
 
@@ -479,7 +517,10 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(1)?;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_ubit_var_fp()? as i32 + 1;
+        field_path.add_entry(
+            field_path.last,
+            (bitreader.read_ubit_var_fp()? as i32).wrapping_add(1),
+        )?;
         Ok(())
     }
 
@@ -488,7 +529,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(field_path.last)?;
-        *field_path.get_entry_mut(0)? += 1;
+        field_path.add_entry(0, 1)?;
         Ok(())
     }
 
@@ -497,7 +538,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(field_path.last)?;
-        *field_path.get_entry_mut(0)? += bitreader.read_ubit_var_fp()? as i32 + 1;
+        field_path.add_entry(0, (bitreader.read_ubit_var_fp()? as i32).wrapping_add(1))?;
         Ok(())
     }
 
@@ -506,7 +547,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(field_path.last)?;
-        *field_path.get_entry_mut(0)? += bitreader.read_nbits(3)? as i32 + 1;
+        field_path.add_entry(0, bitreader.read_nbits(3)? as i32 + 1)?;
         Ok(())
     }
 
@@ -515,7 +556,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(field_path.last)?;
-        *field_path.get_entry_mut(0)? += bitreader.read_nbits(6)? as i32 + 1;
+        field_path.add_entry(0, bitreader.read_nbits(6)? as i32 + 1)?;
         Ok(())
     }
 
@@ -524,7 +565,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(bitreader.read_ubit_var_fp()? as usize)?;
-        *field_path.get_entry_mut(field_path.last)? += 1;
+        field_path.add_entry(field_path.last, 1)?;
         Ok(())
     }
 
@@ -533,7 +574,7 @@ pub mod ops {
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
         field_path.pop_special(bitreader.read_ubit_var_fp()? as usize)?;
-        *field_path.get_entry_mut(field_path.last)? += bitreader.read_varint32()?;
+        field_path.add_entry(field_path.last, bitreader.read_varint32()?)?;
         Ok(())
     }
 
@@ -544,7 +585,7 @@ pub mod ops {
         field_path.pop_special(bitreader.read_ubit_var_fp()? as usize)?;
         for i in 0..field_path.last + 1 {
             if bitreader.read_boolean()? {
-                *field_path.get_entry_mut(i)? += bitreader.read_varint32()?;
+                field_path.add_entry(i, bitreader.read_varint32()?)?;
             }
         }
         Ok(())
@@ -556,7 +597,7 @@ pub mod ops {
     ) -> Result<(), FirstPassError> {
         for i in 0..field_path.last + 1 {
             if bitreader.read_boolean()? {
-                *field_path.get_entry_mut(i)? += bitreader.read_varint32()?;
+                field_path.add_entry(i, bitreader.read_varint32()?)?;
             }
         }
         Ok(())
@@ -566,7 +607,7 @@ pub mod ops {
         _bitreader: &mut Bitreader,
         field_path: &mut FieldPath,
     ) -> Result<(), FirstPassError> {
-        *field_path.get_entry_mut(field_path.last - 1)? += 1;
+        field_path.add_entry(field_path.last.wrapping_sub(1), 1)?;
         Ok(())
     }
 
@@ -576,9 +617,36 @@ pub mod ops {
     ) -> Result<(), FirstPassError> {
         for i in 0..field_path.last + 1 {
             if bitreader.read_boolean()? {
-                *field_path.get_entry_mut(i)? += bitreader.read_nbits(4)? as i32 - 7;
+                field_path.add_entry(i, bitreader.read_nbits(4)? as i32 - 7)?;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_paths_truncated() {
+        // Only plus_one ops and no terminating symbol, so the data runs out
+        let mut bitreader = crate::bitreader::Bitreader::new(&[0; 4]);
+        let mut paths = Paths::new();
+
+        assert!(matches!(
+            parse_paths(&mut bitreader, &mut paths),
+            Err(super::super::FirstPassError::Bitreader(
+                crate::bitreader::BitReadError::NotEnoughBits
+            ))
+        ));
+    }
+
+    #[test]
+    fn pop_below_root() {
+        let mut path = Paths::new_path();
+
+        assert!(path.pop_special(1).is_err());
+        assert!(path.pop_special(0).is_ok());
+    }
+}
@@ -0,0 +1,37 @@
+/// How the parser deals with anomalies in a demo, like unknown messages or ticks going backwards
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Every anomaly is an error, which stops the parsing
+    Strict,
+    /// Anomalies are recorded in the [`Diagnostics`](super::Diagnostics) and the affected
+    /// message or prop is skipped
+    #[default]
+    Lenient,
+}
+
+/// Configures the parsing, the default is the [`Lenient`](ParseMode::Lenient) mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Options for the [`Strict`](ParseMode::Strict) mode
+    pub fn strict() -> Self {
+        Self::new().mode(ParseMode::Strict)
+    }
+
+    /// Options for the [`Lenient`](ParseMode::Lenient) mode
+    pub fn lenient() -> Self {
+        Self::new().mode(ParseMode::Lenient)
+    }
+}
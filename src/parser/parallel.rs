@@ -0,0 +1,668 @@
+//! Parses a demo on multiple threads, by splitting it into segments at the FullPackets, which
+//! contain a complete snapshot of the entities and string tables
+
+use super::{
+    Diagnostics, EagerHandler, Engine, EntityFilter, ErrorContext, FirstPassError, FirstPassOutput,
+    ParseError, ParseOptions, Roster,
+};
+use crate::{DemoCommand, Frame};
+
+/// Like [`parse`](super::parse), but decodes the segments of the demo between two FullPackets
+/// on up to `threads` threads at the same time.
+///
+/// The data is the [`Container::inner`](crate::Container::inner) of the demo. Every thread needs
+/// its own [`EntityFilter`], so the filter is created once per segment.
+///
+/// The signon data at the start of the demo is decoded once and every segment starts from that
+/// state together with its FullPacket, which usually re-creates all the entities and contains
+/// the complete string tables. Every segment also decodes the FullPacket of the next one, so
+/// the state it ends with can be compared to the one the next segment started from. If they
+/// differ anywhere, the next segment depends on something from before its FullPacket and the
+/// demo is parsed sequentially instead, so the result is always the same as the one of
+/// [`parse`](super::parse)
+pub fn parse_parallel<F>(
+    data: &[u8],
+    filter: F,
+    threads: usize,
+) -> Result<FirstPassOutput, FirstPassError>
+where
+    F: Fn() -> EntityFilter + Sync,
+{
+    parse_parallel_with_options(data, filter, threads, ParseOptions::default()).map_err(|e| e.error)
+}
+
+/// Like [`parse_parallel`], but with the given [`ParseOptions`], see
+/// [`parse_with_options`](super::parse_with_options)
+pub fn parse_parallel_with_options<F>(
+    data: &[u8],
+    filter: F,
+    threads: usize,
+    options: ParseOptions,
+) -> Result<FirstPassOutput, ParseError>
+where
+    F: Fn() -> EntityFilter + Sync,
+{
+    let sequential =
+        || super::parse_with_options(crate::FrameIterator::parse(data), filter(), options);
+
+    let frames: Vec<Frame<'_>> = crate::FrameIterator::parse(data).collect();
+
+    let boundaries: Vec<usize> = frames
+        .iter()
+        .enumerate()
+        .filter(|(_, frame)| frame.cmd == DemoCommand::FullPacket)
+        .map(|(idx, _)| idx)
+        .collect();
+    let signon_end = boundaries.first().copied().unwrap_or(frames.len());
+
+    let mut handler = EagerHandler::new(0);
+    let mut engine = Engine::new(filter()).with_options(options);
+    for frame in frames[..signon_end].iter() {
+        engine.handle_frame(frame, &mut handler)?;
+    }
+    let signon = engine.checkpoint();
+    let mut roster = engine.roster().clone();
+
+    // The first FullPacket is decoded here, like every other one is decoded by the segment
+    // before it
+    if let Some(full_packet) = frames.get(signon_end) {
+        engine.handle_frame(full_packet, &mut handler)?;
+    }
+    let mut diagnostics = engine.diagnostics().clone();
+    let mut expected = engine.checkpoint();
+    let mut tick = engine.current_tick();
+
+    let segments: Vec<std::ops::Range<usize>> = boundaries
+        .iter()
+        .zip(
+            boundaries
+                .iter()
+                .skip(1)
+                .chain(std::iter::once(&frames.len())),
+        )
+        .map(|(start, end)| *start..*end)
+        .collect();
+
+    let next_segment = std::sync::atomic::AtomicUsize::new(0);
+    let results: Result<Vec<_>, _> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.clamp(1, segments.len().max(1)))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let idx = next_segment.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        let Some(segment) = segments.get(idx) else {
+                            break;
+                        };
+
+                        let mut checkpoint = signon.clone();
+                        checkpoint.frame = segment.start;
+                        checkpoint.offset =
+                            frames[..segment.start].iter().map(Frame::encoded_len).sum();
+                        if segment.start > signon_end {
+                            checkpoint.tick = frames[signon_end..segment.start]
+                                .iter()
+                                .rev()
+                                .find_map(last_tick)
+                                .unwrap_or(signon.tick);
+                        }
+                        // The FullPacket at the start of the segment lists everyone who is
+                        // connected, which is merged with the roster of the previous segments
+                        checkpoint.roster = Roster::new();
+
+                        let result = parse_segment(
+                            &frames[segment.clone()],
+                            frames.get(segment.end),
+                            &checkpoint,
+                            filter(),
+                            options,
+                        );
+                        results.push((idx, checkpoint.tick, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join())
+            .collect::<Result<Vec<_>, _>>()
+            .map(|results| results.into_iter().flatten().collect())
+    });
+    // Whatever made a worker panic is left to the sequential parse
+    let Ok(mut results) = results else {
+        return sequential();
+    };
+    results.sort_unstable_by_key(|(idx, _, _)| *idx);
+
+    for (_, segment_tick, segment) in results {
+        let segment = match segment {
+            Some(segment) if same_state(&expected, &segment.start) => segment,
+            _ => return sequential(),
+        };
+        let output = segment.output?;
+
+        handler.append(output.handler);
+        roster.append(segment_tick, output.roster);
+        diagnostics.merge(output.diagnostics);
+        tick = output.tick;
+        if let Some(next) = output.next {
+            expected = next;
+        }
+    }
+
+    handler
+        .into_output(roster, diagnostics)
+        .and_then(|output| output.complete())
+        .map_err(|error| ParseError {
+            error,
+            // The header and the FileInfo are only known to be missing at the end of the demo
+            context: ErrorContext {
+                tick,
+                ..Default::default()
+            },
+        })
+}
+
+/// A segment of the demo, starting at its FullPacket
+struct Segment {
+    /// The state after the FullPacket, which is only correct if it is the same as the one the
+    /// previous segment ended with
+    start: super::Checkpoint,
+    /// Everything after the FullPacket, which is the same as for a sequential parse as long as
+    /// the start of the segment is
+    output: Result<SegmentOutput, ParseError>,
+}
+
+struct SegmentOutput {
+    handler: EagerHandler,
+    /// The roster up to the next FullPacket
+    roster: Roster,
+    diagnostics: Diagnostics,
+    /// The state after the FullPacket of the next segment, if there is one
+    next: Option<super::Checkpoint>,
+    /// The tick at the end of the segment
+    tick: u32,
+}
+
+/// Whether the segment starts from the state the previous one ended with, except for the
+/// roster, which every segment collects on its own
+fn same_state(expected: &super::Checkpoint, start: &super::Checkpoint) -> bool {
+    let without_roster = |checkpoint: &super::Checkpoint| super::Checkpoint {
+        roster: Roster::new(),
+        ..checkpoint.clone()
+    };
+    without_roster(expected) == without_roster(start)
+}
+
+/// Decodes the frames of the segment and the FullPacket of the next one, or returns `None` if
+/// the FullPacket of the segment could not be decoded from the state in the checkpoint
+fn parse_segment(
+    frames: &[Frame<'_>],
+    next: Option<&Frame<'_>>,
+    checkpoint: &super::Checkpoint,
+    filter: EntityFilter,
+    options: ParseOptions,
+) -> Option<Segment> {
+    let mut engine = Engine::restore(checkpoint, filter)
+        .ok()?
+        .with_options(options);
+
+    // The output of the FullPacket comes from the previous segment instead
+    let (full_packet, frames) = frames.split_first()?;
+    engine
+        .handle_frame(full_packet, &mut EagerHandler::new(checkpoint.tick))
+        .ok()?;
+    let start = engine.checkpoint();
+    engine.clear_diagnostics();
+
+    Some(Segment {
+        start,
+        output: parse_segment_frames(&mut engine, frames, next),
+    })
+}
+
+fn parse_segment_frames(
+    engine: &mut Engine,
+    frames: &[Frame<'_>],
+    next: Option<&Frame<'_>>,
+) -> Result<SegmentOutput, ParseError> {
+    let mut handler = EagerHandler::new(engine.current_tick());
+    for frame in frames {
+        engine.handle_frame(frame, &mut handler)?;
+    }
+    let roster = engine.roster().clone();
+
+    let next = match next {
+        Some(full_packet) => {
+            engine.handle_frame(full_packet, &mut handler)?;
+            Some(engine.checkpoint())
+        }
+        None => None,
+    };
+
+    Ok(SegmentOutput {
+        handler,
+        roster,
+        diagnostics: engine.diagnostics().clone(),
+        next,
+        tick: engine.current_tick(),
+    })
+}
+
+/// The highest tick of the `net_Tick` messages in the frame, which is the current tick of the
+/// engine after the frame
+fn last_tick(frame: &Frame<'_>) -> Option<u32> {
+    let mut buffer = Vec::new();
+    let data = frame.decompress_with_buf(&mut buffer).ok()?;
+
+    let packet: crate::csgo_proto::CDemoPacket = match frame.cmd {
+        DemoCommand::SignonPacket | DemoCommand::Packet => prost::Message::decode(data).ok()?,
+        DemoCommand::FullPacket => {
+            let raw: crate::csgo_proto::CDemoFullPacket = prost::Message::decode(data).ok()?;
+            raw.packet?
+        }
+        _ => return None,
+    };
+
+    let mut bitreader = crate::bitreader::Bitreader::new(packet.data());
+    let mut tick = None;
+    while bitreader.bits_remaining().unwrap_or(0) > 8 {
+        let msg_type = bitreader.read_u_bit_var().ok()?;
+        let size = bitreader.read_varint().ok()?;
+        let msg_bytes = bitreader.read_n_bytes(size as usize).ok()?;
+
+        if let Ok(crate::NetmessageType::net_Tick) =
+            crate::NetmessageType::try_from(msg_type as i32)
+        {
+            let raw: crate::csgo_proto::CnetMsgTick =
+                prost::Message::decode(msg_bytes.as_slice()).ok()?;
+            tick = tick.max(Some(raw.tick()));
+        }
+    }
+    tick
+}
+
+impl EagerHandler {
+    /// Appends the output of the following segment of the demo
+    fn append(&mut self, other: EagerHandler) {
+        if self.header.is_none() {
+            self.header = other.header;
+        }
+        if other.file_info.is_some() {
+            self.file_info = other.file_info;
+        }
+
+        // The events of a tick can be split across both segments
+        for event in other.events {
+            let event = self.clock.timed(event.tick, event.event);
+            self.events.push(event);
+        }
+
+        self.player_info.extend(other.player_info);
+
+        for states in other.entity_states.ticks {
+            match self.entity_states.ticks.last_mut() {
+                Some(last) if last.tick == states.tick => last.states.extend(states.states),
+                _ => self.entity_states.ticks.push(states),
+            }
+        }
+        self.current_tick = other.current_tick;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame with a packet of the given messages
+    fn packet(cmd: DemoCommand, messages: &[(u32, Vec<u8>)]) -> (DemoCommand, Vec<u8>) {
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        for (id, bytes) in messages {
+            writer.write_u_bit_var(*id);
+            writer.write_varint(bytes.len() as u32);
+            writer.write_bytes(bytes);
+        }
+        let packet = crate::csgo_proto::CDemoPacket {
+            data: Some(writer.into_bytes()),
+        };
+
+        let data = match cmd {
+            DemoCommand::FullPacket => {
+                prost::Message::encode_to_vec(&crate::csgo_proto::CDemoFullPacket {
+                    packet: Some(packet),
+                    ..Default::default()
+                })
+            }
+            _ => prost::Message::encode_to_vec(&packet),
+        };
+        (cmd, data)
+    }
+
+    /// A FullPacket with a snapshot of the `userinfo` table, with the given players in their slots
+    fn full_packet(
+        messages: &[(u32, Vec<u8>)],
+        players: &[Option<(&str, u64)>],
+    ) -> (DemoCommand, Vec<u8>) {
+        use crate::csgo_proto::c_demo_string_tables;
+
+        let (cmd, data) = packet(DemoCommand::FullPacket, messages);
+        let mut raw: crate::csgo_proto::CDemoFullPacket =
+            prost::Message::decode(data.as_slice()).unwrap();
+        raw.string_table = Some(crate::csgo_proto::CDemoStringTables {
+            tables: vec![c_demo_string_tables::TableT {
+                table_name: Some("userinfo".to_owned()),
+                items: players
+                    .iter()
+                    .map(|player| c_demo_string_tables::ItemsT {
+                        str: None,
+                        data: Some(player.map_or(Vec::new(), |(name, xuid)| {
+                            prost::Message::encode_to_vec(&crate::csgo_proto::CMsgPlayerInfo {
+                                name: Some(name.to_owned()),
+                                xuid: Some(xuid),
+                                ..Default::default()
+                            })
+                        })),
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
+        });
+        (cmd, prost::Message::encode_to_vec(&raw))
+    }
+
+    fn tick(tick: u32) -> (u32, Vec<u8>) {
+        let raw = crate::csgo_proto::CnetMsgTick {
+            tick: Some(tick),
+            ..Default::default()
+        };
+        // The id of net_Tick
+        (4, prost::Message::encode_to_vec(&raw))
+    }
+
+    /// Creates the empty `userinfo` table, which the FullPackets contain the snapshots of
+    fn create_userinfo() -> (u32, Vec<u8>) {
+        let raw = crate::csgo_proto::CsvcMsgCreateStringTable {
+            name: Some("userinfo".to_owned()),
+            num_entries: Some(0),
+            string_data: Some(Vec::new()),
+            ..Default::default()
+        };
+        // The id of svc_CreateStringTable
+        (44, prost::Message::encode_to_vec(&raw))
+    }
+
+    fn server_info() -> (u32, Vec<u8>) {
+        let raw = crate::csgo_proto::CsvcMsgServerInfo::default();
+        // The id of svc_ServerInfo
+        (40, prost::Message::encode_to_vec(&raw))
+    }
+
+    /// The SendTables and ClassInfo of a single class `CTest` without any fields
+    fn classes() -> [(DemoCommand, Vec<u8>); 2] {
+        let serializers = crate::csgo_proto::CsvcMsgFlattenedSerializer {
+            serializers: vec![crate::csgo_proto::ProtoFlattenedSerializerT {
+                serializer_name_sym: Some(0),
+                serializer_version: Some(0),
+                ..Default::default()
+            }],
+            symbols: vec!["CTest".to_owned()],
+            ..Default::default()
+        };
+        let serializers = prost::Message::encode_to_vec(&serializers);
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        writer.write_varint(serializers.len() as u32);
+        writer.write_bytes(&serializers);
+        let send_tables = crate::csgo_proto::CDemoSendTables {
+            data: Some(writer.into_bytes()),
+        };
+
+        let class_info = crate::csgo_proto::CDemoClassInfo {
+            classes: vec![crate::csgo_proto::c_demo_class_info::ClassT {
+                class_id: Some(CLS),
+                network_name: Some("CTest".to_owned()),
+                ..Default::default()
+            }],
+        };
+
+        [
+            (
+                DemoCommand::SendTables,
+                prost::Message::encode_to_vec(&send_tables),
+            ),
+            (
+                DemoCommand::ClassInfo,
+                prost::Message::encode_to_vec(&class_info),
+            ),
+        ]
+    }
+
+    const CLS: i32 = 1;
+
+    /// The operations of the entities in a svc_PacketEntities message
+    const UPDATE: u32 = 0b00;
+    const LEAVE_PVS: u32 = 0b01;
+    const CREATE: u32 = 0b10;
+    const DELETE: u32 = 0b11;
+
+    /// A svc_PacketEntities message with the given operation for every entity, in order of their
+    /// ids, which never change any props
+    fn packet_entities(entities: &[(i32, u32)]) -> (u32, Vec<u8>) {
+        // The field path operation that ends the field paths of an entity
+        let (finish, code_len) = super::super::HUFFMAN_LOOKUP_TABLE
+            .iter()
+            .enumerate()
+            .find(|(_, (symbol, _))| *symbol == 39)
+            .map(|(code, (_, code_len))| (code as u32, *code_len as u32))
+            .unwrap();
+
+        let mut writer = crate::bitwriter::Bitwriter::new();
+        let mut last_id = -1;
+        for (id, op) in entities {
+            writer.write_u_bit_var((id - last_id - 1) as u32);
+            writer.write_nbits(*op, 2);
+            if *op == CREATE {
+                writer.write_nbits(CLS as u32, 8);
+                // The serial
+                writer.write_nbits(1, 17);
+                writer.write_varint(0);
+            }
+            if *op == CREATE || *op == UPDATE {
+                writer.write_nbits(finish & ((1 << code_len) - 1), code_len);
+            }
+            last_id = *id;
+        }
+
+        let raw = crate::csgo_proto::CsvcMsgPacketEntities {
+            updated_entries: Some(entities.len() as i32),
+            entity_data: Some(writer.into_bytes()),
+            ..Default::default()
+        };
+        // The id of svc_PacketEntities
+        (55, prost::Message::encode_to_vec(&raw))
+    }
+
+    /// A demo with the given frames and a FileHeader and FileInfo around them
+    fn demo(frames: &[(DemoCommand, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = crate::DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+        writer
+            .write_data(
+                DemoCommand::FileHeader,
+                0,
+                &prost::Message::encode_to_vec(&crate::csgo_proto::CDemoFileHeader::default()),
+            )
+            .unwrap();
+        for (cmd, data) in frames.iter() {
+            writer.write_data(*cmd, 0, data).unwrap();
+        }
+        writer
+            .write_data(
+                DemoCommand::FileInfo,
+                0,
+                &prost::Message::encode_to_vec(&crate::csgo_proto::CDemoFileInfo::default()),
+            )
+            .unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Parses the demo sequentially and in parallel, which has to give the same output
+    fn assert_same_as_sequential(demo: &[u8]) -> FirstPassOutput {
+        let container = crate::Container::parse(demo).unwrap();
+
+        let sequential = super::super::parse(
+            crate::FrameIterator::parse(container.inner),
+            EntityFilter::all(),
+        )
+        .unwrap();
+
+        for threads in [1, 2, 8] {
+            let parallel = parse_parallel(container.inner, EntityFilter::all, threads).unwrap();
+
+            assert_eq!(sequential.header, parallel.header);
+            assert_eq!(sequential.info, parallel.info);
+            assert_eq!(sequential.events, parallel.events);
+            assert_eq!(sequential.roster, parallel.roster);
+            assert_eq!(sequential.diagnostics, parallel.diagnostics);
+
+            let ticks = |output: &FirstPassOutput| -> Vec<_> {
+                output
+                    .entity_states
+                    .ticks
+                    .iter()
+                    .map(|states| (states.tick, states.states.clone()))
+                    .collect()
+            };
+            assert_eq!(ticks(&sequential), ticks(&parallel));
+        }
+
+        sequential
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let [send_tables, class_info] = classes();
+        let demo = demo(&[
+            send_tables,
+            class_info,
+            packet(
+                DemoCommand::SignonPacket,
+                &[server_info(), create_userinfo(), tick(1)],
+            ),
+            full_packet(
+                &[
+                    tick(2),
+                    packet_entities(&[(1, CREATE), (2, CREATE), (3, CREATE)]),
+                ],
+                &[Some(("a", 1)), Some(("b", 2))],
+            ),
+            packet(
+                DemoCommand::Packet,
+                &[
+                    tick(5),
+                    server_info(),
+                    packet_entities(&[(1, LEAVE_PVS), (3, UPDATE)]),
+                ],
+            ),
+            // The events of tick 5 continue in the next segment, where the entity 1 enters the
+            // PVS again and the entity 2 is deleted, which only makes sense with the state from
+            // before the FullPacket
+            full_packet(
+                &[
+                    server_info(),
+                    packet_entities(&[(1, CREATE), (2, DELETE), (3, CREATE)]),
+                ],
+                &[Some(("a", 1)), None],
+            ),
+            packet(
+                DemoCommand::Packet,
+                &[tick(6), packet_entities(&[(3, UPDATE)])],
+            ),
+            full_packet(
+                &[
+                    tick(7),
+                    server_info(),
+                    packet_entities(&[(1, CREATE), (3, CREATE)]),
+                ],
+                &[Some(("a", 1)), Some(("c", 3))],
+            ),
+        ]);
+
+        let sequential = assert_same_as_sequential(&demo);
+        assert_eq!(3, sequential.roster.players().len());
+
+        let ops: Vec<_> = sequential
+            .entity_states
+            .ticks
+            .iter()
+            .flat_map(|states| states.states.iter().map(|state| (state.id, state.op)))
+            .collect();
+        assert!(ops.contains(&(1, super::super::EntityOp::EnteredPVS)));
+        assert!(ops.contains(&(2, super::super::EntityOp::Deleted)));
+    }
+
+    #[test]
+    fn entity_from_before_full_packet() {
+        let [send_tables, class_info] = classes();
+        let demo = demo(&[
+            send_tables,
+            class_info,
+            packet(DemoCommand::SignonPacket, &[create_userinfo(), tick(1)]),
+            full_packet(&[tick(2), packet_entities(&[(1, CREATE)])], &[]),
+            packet(
+                DemoCommand::Packet,
+                &[tick(3), packet_entities(&[(2, CREATE)])],
+            ),
+            // The entity 2 is still there, even though the FullPacket does not contain it
+            full_packet(&[tick(4), packet_entities(&[(1, CREATE)])], &[]),
+            packet(
+                DemoCommand::Packet,
+                &[tick(5), packet_entities(&[(2, UPDATE)])],
+            ),
+        ]);
+
+        let sequential = assert_same_as_sequential(&demo);
+        let last = sequential.entity_states.ticks.last().unwrap();
+        assert_eq!(5, last.tick);
+        assert_eq!(
+            vec![2],
+            last.states.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn with_options() {
+        let demo = demo(&[
+            packet(
+                DemoCommand::SignonPacket,
+                &[server_info(), create_userinfo(), tick(1)],
+            ),
+            full_packet(&[tick(2)], &[]),
+            // An unknown message type, which is only an error in the strict mode
+            packet(DemoCommand::Packet, &[tick(3), (9999, Vec::new())]),
+            full_packet(&[tick(4)], &[]),
+        ]);
+        let container = crate::Container::parse(&demo).unwrap();
+
+        let sequential = super::super::parse_with_options(
+            crate::FrameIterator::parse(container.inner),
+            EntityFilter::all(),
+            ParseOptions::strict(),
+        )
+        .unwrap_err();
+
+        let parallel = parse_parallel_with_options(
+            container.inner,
+            EntityFilter::all,
+            2,
+            ParseOptions::strict(),
+        )
+        .unwrap_err();
+
+        assert!(matches!(parallel.error, FirstPassError::Anomaly(_)));
+        assert_eq!(sequential.context, parallel.context);
+
+        let lenient = parse_parallel(container.inner, EntityFilter::all, 2).unwrap();
+        assert_eq!(1, lenient.diagnostics.len());
+    }
+}
@@ -153,6 +153,11 @@ impl PropController {
     }
 
     fn traverse_fields(&mut self, fields: &mut [Field], ser_name: String, path_og: Vec<i32>) {
+        // A FieldPath has at most 7 entries, so anything nested deeper can never be referenced
+        if path_og.len() >= 7 {
+            return;
+        }
+
         for (idx, f) in fields.iter_mut().enumerate() {
             let mut path = path_og.clone();
             path.push(idx as i32);
@@ -249,8 +254,8 @@ impl PropController {
             false => full_name.to_string(),
         };
         let mut a = [0, 0, 0, 0, 0, 0, 0];
-        for (idx, v) in path.iter().enumerate() {
-            a[idx] = *v;
+        for (entry, v) in a.iter_mut().zip(path.iter()) {
+            *entry = *v;
         }
         self.path_to_name.insert(a, prop_name.to_string());
 
@@ -0,0 +1,363 @@
+//! Keeps track of the players on the server throughout a demo, using the `userinfo` string table
+//! and the `player_connect`/`player_disconnect` events
+
+use crate::UserId;
+
+/// One continuous time a player was connected to the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presence {
+    /// The slot of the player, which is the [`UserId`] used by the game events
+    pub slot: i32,
+    pub start: u32,
+    /// The tick the player disconnected, `None` while the player is still connected
+    pub end: Option<u32>,
+}
+
+impl Presence {
+    pub fn contains(&self, tick: u32) -> bool {
+        self.start <= tick && self.end.is_none_or(|end| tick <= end)
+    }
+}
+
+/// A player that was connected to the server at some point of the demo
+#[derive(Debug, Clone, PartialEq)]
+pub struct RosterPlayer {
+    /// The slot of the latest connection of the player
+    pub slot: i32,
+    /// The user id the server assigned to the connection of the player
+    pub userid: i32,
+    pub name: String,
+    pub xuid: u64,
+    pub is_bot: bool,
+    pub is_hltv: bool,
+    /// Every time the player was connected, in order
+    pub presence: Vec<Presence>,
+}
+
+impl RosterPlayer {
+    pub fn user_id(&self) -> UserId {
+        UserId(self.slot)
+    }
+
+    /// The id of the player controller entity of the player
+    pub fn entity_id(&self) -> i32 {
+        self.slot + 1
+    }
+
+    /// Whether the player was connected at the given tick
+    pub fn present_at(&self, tick: u32) -> bool {
+        self.presence.iter().any(|presence| presence.contains(tick))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.presence.last(), Some(presence) if presence.end.is_none())
+    }
+
+    /// Bots and GOTV have no xuid, so they are told apart by their name
+    fn same_as(&self, other: &RosterPlayer) -> bool {
+        if self.xuid != 0 || other.xuid != 0 {
+            return self.xuid == other.xuid;
+        }
+        self.is_bot == other.is_bot && self.is_hltv == other.is_hltv && self.name == other.name
+    }
+
+    fn open(&mut self, tick: u32) {
+        self.presence.push(Presence {
+            slot: self.slot,
+            start: tick,
+            end: None,
+        });
+    }
+
+    fn close(&mut self, tick: u32) {
+        if let Some(presence) = self.presence.last_mut() {
+            presence.end.get_or_insert(tick);
+        }
+    }
+}
+
+/// All the players of a demo, which can be looked up at any tick
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Roster {
+    players: Vec<RosterPlayer>,
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every player that was connected at some point, in the order they first connected
+    pub fn players(&self) -> &[RosterPlayer] {
+        &self.players
+    }
+
+    /// The players that are connected at the moment
+    pub fn connected(&self) -> impl Iterator<Item = &RosterPlayer> {
+        self.players.iter().filter(|player| player.is_connected())
+    }
+
+    /// The players that were connected at the given tick
+    pub fn at(&self, tick: u32) -> impl Iterator<Item = &RosterPlayer> {
+        self.players
+            .iter()
+            .filter(move |player| player.present_at(tick))
+    }
+
+    /// The player in the slot of the user at the given tick, like the `userid` of a game event.
+    ///
+    /// If a player left and another one joined in the same tick, this is the one that joined
+    pub fn get(&self, user: UserId, tick: u32) -> Option<&RosterPlayer> {
+        self.players.iter().rev().find(|player| {
+            player
+                .presence
+                .iter()
+                .any(|presence| presence.slot == user.0 && presence.contains(tick))
+        })
+    }
+
+    pub fn by_xuid(&self, xuid: u64) -> Option<&RosterPlayer> {
+        self.players.iter().find(|player| player.xuid == xuid)
+    }
+
+    /// Updates the player in the slot from the value of its `userinfo` entry, where an empty
+    /// value means that the slot is free now
+    pub(crate) fn update_userinfo(
+        &mut self,
+        tick: u32,
+        slot: i32,
+        value: &[u8],
+    ) -> Result<(), prost::DecodeError> {
+        if value.is_empty() {
+            self.disconnect(tick, slot);
+            return Ok(());
+        }
+
+        let raw: crate::csgo_proto::CMsgPlayerInfo = prost::Message::decode(value)?;
+        self.connect(
+            tick,
+            RosterPlayer {
+                slot,
+                userid: raw.userid(),
+                name: raw.name().to_owned(),
+                xuid: raw.xuid(),
+                is_bot: raw.fakeplayer(),
+                is_hltv: raw.ishltv(),
+                presence: Vec::new(),
+            },
+            true,
+        );
+        Ok(())
+    }
+
+    pub(crate) fn handle_event(&mut self, tick: u32, event: &crate::game_event::GameEvent) {
+        use crate::{game_event::GameEvent, RawValue};
+
+        match event {
+            GameEvent::PlayerConnect(connect) => {
+                let Some(slot) = connect.userid else {
+                    return;
+                };
+
+                self.connect(
+                    tick,
+                    RosterPlayer {
+                        slot,
+                        userid: slot,
+                        name: match connect.name.as_ref() {
+                            Some(RawValue::String(name)) => name.clone(),
+                            _ => String::new(),
+                        },
+                        xuid: match connect.xuid.as_ref() {
+                            Some(RawValue::U64(xuid)) => *xuid,
+                            _ => 0,
+                        },
+                        is_bot: matches!(connect.bot, Some(RawValue::Bool(true))),
+                        is_hltv: false,
+                        presence: Vec::new(),
+                    },
+                    false,
+                );
+            }
+            GameEvent::PlayerDisconnect(disconnect) => {
+                if let Some(user) = disconnect.userid {
+                    self.disconnect(tick, user.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Marks the player as connected in its slot, replacing whoever was in the slot before.
+    ///
+    /// Only the `userinfo` table has all the details of a player, so a player from a connect
+    /// event neither overwrites an existing one nor replaces the one in its slot
+    fn connect(&mut self, tick: u32, mut player: RosterPlayer, complete: bool) {
+        let current = self
+            .players
+            .iter()
+            .position(|p| p.slot == player.slot && p.is_connected());
+        if let Some(idx) = current {
+            if !complete {
+                return;
+            }
+            if self.players[idx].same_as(&player) {
+                player.presence = std::mem::take(&mut self.players[idx].presence);
+                self.players[idx] = player;
+                return;
+            }
+            self.players[idx].close(tick);
+        }
+
+        match self.players.iter_mut().find(|p| p.same_as(&player)) {
+            Some(existing) => {
+                // The player might still be connected in a different slot
+                existing.close(tick);
+                if complete {
+                    player.presence = std::mem::take(&mut existing.presence);
+                    *existing = player;
+                } else {
+                    existing.slot = player.slot;
+                }
+                existing.open(tick);
+            }
+            None => {
+                player.open(tick);
+                self.players.push(player);
+            }
+        }
+    }
+
+    fn disconnect(&mut self, tick: u32, slot: i32) {
+        for player in self.players.iter_mut() {
+            if player.slot == slot {
+                player.close(tick);
+            }
+        }
+    }
+
+    /// Disconnects the players whose slot is not part of a snapshot of the `userinfo` table
+    pub(crate) fn disconnect_missing<F>(&mut self, tick: u32, present: F)
+    where
+        F: Fn(i32) -> bool,
+    {
+        for player in self.players.iter_mut() {
+            if !present(player.slot) {
+                player.close(tick);
+            }
+        }
+    }
+
+    /// Appends the roster of the following part of the demo, which started with no players at
+    /// the given tick and a snapshot of everyone who was connected at that point
+    pub(crate) fn append(&mut self, tick: u32, other: Roster) {
+        let continues = |player: &RosterPlayer, other: &RosterPlayer| {
+            player.is_connected()
+                && player.same_as(other)
+                && matches!(
+                    other.presence.first(),
+                    Some(first) if first.start == tick && first.slot == player.slot
+                )
+        };
+
+        for player in self.players.iter_mut() {
+            if !other.players.iter().any(|p| continues(player, p)) {
+                player.close(tick);
+            }
+        }
+
+        for mut player in other.players {
+            let Some(existing) = self.players.iter_mut().find(|p| p.same_as(&player)) else {
+                self.players.push(player);
+                continue;
+            };
+
+            // The player was still connected at the end of our part, so the first presence in
+            // the other part only continues it
+            let joins = continues(existing, &player);
+            let mut presence = std::mem::take(&mut player.presence);
+            if joins {
+                let first = presence.remove(0);
+                if let Some(last) = existing.presence.last_mut() {
+                    last.end = first.end;
+                }
+            }
+
+            player.presence = std::mem::take(&mut existing.presence);
+            player.presence.append(&mut presence);
+            *existing = player;
+        }
+    }
+
+    pub(crate) fn insert(&mut self, player: RosterPlayer) {
+        self.players.push(player);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn userinfo(name: &str, xuid: u64, userid: i32) -> Vec<u8> {
+        prost::Message::encode_to_vec(&crate::csgo_proto::CMsgPlayerInfo {
+            name: Some(name.to_owned()),
+            xuid: Some(xuid),
+            userid: Some(userid),
+            fakeplayer: Some(xuid == 0),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn presence() {
+        let mut roster = Roster::new();
+        roster.update_userinfo(10, 0, &userinfo("a", 1, 2)).unwrap();
+        roster
+            .update_userinfo(10, 1, &userinfo("Bot", 0, 3))
+            .unwrap();
+        // A name change is still the same player
+        roster.update_userinfo(15, 0, &userinfo("b", 1, 2)).unwrap();
+        roster.update_userinfo(20, 0, &[]).unwrap();
+        // Someone else joins in the same slot, then the first player comes back in another one
+        roster.update_userinfo(25, 0, &userinfo("c", 4, 5)).unwrap();
+        roster.update_userinfo(30, 2, &userinfo("b", 1, 6)).unwrap();
+
+        assert_eq!(3, roster.players().len());
+        let presence: Vec<_> = roster.players()[0]
+            .presence
+            .iter()
+            .map(|presence| (presence.slot, presence.start, presence.end))
+            .collect();
+        assert_eq!(vec![(0, 10, Some(20)), (2, 30, None)], presence);
+        assert_eq!(6, roster.players()[0].userid);
+        assert!(roster.players()[1].is_bot);
+
+        assert_eq!("b", roster.get(UserId(0), 20).unwrap().name);
+        assert_eq!("c", roster.get(UserId(0), 25).unwrap().name);
+        assert!(roster.get(UserId(2), 25).is_none());
+        assert_eq!(1, roster.at(22).count());
+        assert_eq!(3, roster.connected().count());
+    }
+
+    #[test]
+    fn append() {
+        let mut first = Roster::new();
+        first.update_userinfo(10, 0, &userinfo("a", 1, 2)).unwrap();
+        first.update_userinfo(10, 1, &userinfo("b", 3, 4)).unwrap();
+        first.update_userinfo(40, 1, &[]).unwrap();
+
+        let mut second = Roster::new();
+        second.update_userinfo(50, 0, &userinfo("a", 1, 2)).unwrap();
+        second.update_userinfo(60, 0, &[]).unwrap();
+        second.update_userinfo(70, 1, &userinfo("b", 3, 4)).unwrap();
+
+        let mut sequential = first.clone();
+        sequential.update_userinfo(60, 0, &[]).unwrap();
+        sequential
+            .update_userinfo(70, 1, &userinfo("b", 3, 4))
+            .unwrap();
+
+        first.append(50, second);
+        assert_eq!(sequential, first);
+    }
+}
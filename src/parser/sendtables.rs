@@ -133,9 +133,9 @@ fn generate_field_data(
     field_type_map: &mut std::collections::HashMap<String, FieldType>,
     qf_mapper: &mut decoder::QfMapper,
 ) -> Result<ConstructorField, super::FirstPassError> {
-    let name = msg.symbols.get(field.var_type_sym() as usize).unwrap();
+    let name = symbol(msg, field.var_type_sym())?;
 
-    let ft = find_field_type(name, field_type_map)?;
+    let ft = find_field_type(name, field_type_map, 0)?;
     let mut field = field_from_msg(field, msg, ft.clone())?;
 
     field.category = find_category(&field);
@@ -167,10 +167,7 @@ fn generate_serializer(
     msg: &crate::csgo_proto::CsvcMsgFlattenedSerializer,
     serializers: &mut std::collections::HashMap<String, Serializer>,
 ) -> Result<Serializer, super::FirstPassError> {
-    let symbol = match msg.symbols.get(serializer.serializer_name_sym() as usize) {
-        Some(s) => s,
-        None => panic!(),
-    };
+    let name = symbol(msg, serializer.serializer_name_sym())?;
 
     let mut fields_this_ser: Vec<Field> = vec![Field::None; serializer.fields_index.len()];
     for (idx, field_this_ser) in fields_this_ser.iter_mut().enumerate() {
@@ -195,7 +192,7 @@ fn generate_serializer(
     }
 
     Ok(Serializer {
-        name: symbol.to_owned(),
+        name: name.to_owned(),
         fields: fields_this_ser,
     })
 }
@@ -251,13 +248,39 @@ const POINTER_TYPES: &[&str] = &[
     "CPlayerLocalData",
 ];
 
+/// How deep generic types can be nested, which is way more than any real type needs but prevents
+/// malformed types from overflowing the stack
+const MAX_FIELD_TYPE_DEPTH: usize = 32;
+
+/// Looks up the symbol with the given index
+fn symbol(
+    msg: &crate::csgo_proto::CsvcMsgFlattenedSerializer,
+    idx: i32,
+) -> Result<&str, super::FirstPassError> {
+    match msg.symbols.get(idx as usize) {
+        Some(s) => Ok(s.as_str()),
+        None => Err(super::FirstPassError::InvalidSendTables("Missing symbol")),
+    }
+}
+
 fn find_field_type(
     name: &str,
     field_type_map: &mut std::collections::HashMap<String, FieldType>,
+    depth: usize,
 ) -> Result<FieldType, super::FirstPassError> {
+    if depth > MAX_FIELD_TYPE_DEPTH {
+        return Err(super::FirstPassError::InvalidSendTables(
+            "Field type is nested too deeply",
+        ));
+    }
+
     let captures = match RE.captures(name) {
         Some(c) => c,
-        None => panic!("No captures found"),
+        None => {
+            return Err(super::FirstPassError::InvalidSendTables(
+                "Field type does not match the expected format",
+            ))
+        }
     };
 
     let base_type = match captures.get(1) {
@@ -285,7 +308,11 @@ fn find_field_type(
     };
 
     if let Some(generic) = captures.get(3) {
-        ft.generic_type = Some(Box::new(find_field_type(generic.as_str(), field_type_map)?));
+        ft.generic_type = Some(Box::new(find_field_type(
+            generic.as_str(),
+            field_type_map,
+            depth + 1,
+        )?));
     }
     if let Some(count) = captures.get(6) {
         ft.count = Some(count.as_str().parse::<i32>().unwrap_or(0));
@@ -293,7 +320,7 @@ fn find_field_type(
 
     if ft.count.is_some() {
         let ft_string = ft.to_string(true);
-        let for_string_res = for_string(field_type_map, ft_string)?;
+        let for_string_res = for_string(field_type_map, ft_string, depth + 1)?;
         ft.element_type = Some(Box::new(for_string_res));
     }
 
@@ -306,33 +333,18 @@ fn field_from_msg(
     ft: FieldType,
 ) -> Result<ConstructorField, super::FirstPassError> {
     let ser_name = match field.field_serializer_name_sym {
-        Some(idx) => match msg.symbols.get(idx as usize) {
-            Some(entry) => Some(entry.to_owned()),
-            None => panic!(),
-        },
+        Some(idx) => Some(symbol(msg, idx)?.to_owned()),
         None => None,
     };
 
     let enc_name = match field.var_encoder_sym {
-        Some(idx) => match msg.symbols.get(idx as usize) {
-            Some(enc_name) => enc_name.to_owned(),
-            None => panic!(),
-        },
+        Some(idx) => symbol(msg, idx)?.to_owned(),
         None => String::new(),
     };
 
-    let var_name = match msg.symbols.get(field.var_name_sym() as usize) {
-        Some(n) => n.to_owned(),
-        None => panic!(),
-    };
-    let var_type = match msg.symbols.get(field.var_type_sym() as usize) {
-        Some(n) => n.to_owned(),
-        None => panic!(),
-    };
-    let send_node = match msg.symbols.get(field.send_node_sym() as usize) {
-        Some(n) => n.to_owned(),
-        None => panic!(),
-    };
+    let var_name = symbol(msg, field.var_name_sym())?.to_owned();
+    let var_type = symbol(msg, field.var_type_sym())?.to_owned();
+    let send_node = symbol(msg, field.send_node_sym())?.to_owned();
 
     Ok(ConstructorField {
         field_enum_type: None,
@@ -436,11 +448,12 @@ impl FieldType {
 fn for_string(
     field_type_map: &mut std::collections::HashMap<String, FieldType>,
     field_type_string: String,
+    depth: usize,
 ) -> Result<FieldType, super::FirstPassError> {
     match field_type_map.get(&field_type_string) {
         Some(s) => Ok(s.clone()),
         None => {
-            let result = find_field_type(&field_type_string, field_type_map)?;
+            let result = find_field_type(&field_type_string, field_type_map, depth)?;
             field_type_map.insert(field_type_string, result.clone());
             Ok(result)
         }
@@ -455,7 +468,11 @@ fn create_field(
         Some(name) => {
             let ser = match serializers.get(name.as_str()) {
                 Some(ser) => ser,
-                None => panic!(),
+                None => {
+                    return Err(super::FirstPassError::InvalidSendTables(
+                        "Field refers to an unknown serializer",
+                    ))
+                }
             };
             if fd.category == FieldCategory::Pointer {
                 Field::Pointer(PointerField::new(ser))
@@ -469,7 +486,7 @@ fn create_field(
     match fd.category {
         FieldCategory::Array => Ok(Field::Array(ArrayField::new(
             element_field,
-            fd.field_type.count.unwrap_or(0) as usize,
+            fd.field_type.count.unwrap_or(0).max(0) as usize,
         ))),
         FieldCategory::Vector => Ok(Field::Vector(VectorField::new(element_field))),
         _ => Ok(element_field),
@@ -483,15 +500,23 @@ impl Field {
             Field::Vector(inner) => Ok(&inner.field_enum),
             Field::Serializer(inner) => match inner.serializer.fields.get(idx) {
                 Some(f) => Ok(f),
-                None => panic!(),
+                None => Err(super::FirstPassError::InvalidSendTables(
+                    "Missing field in serializer",
+                )),
             },
             Field::Pointer(inner) => match inner.serializer.fields.get(idx) {
                 Some(f) => Ok(f),
-                None => panic!(),
+                None => Err(super::FirstPassError::InvalidSendTables(
+                    "Missing field in serializer",
+                )),
             },
             // Illegal
-            Field::Value(_) => panic!("Can not get inner of Field::Value"),
-            Field::None => panic!("Can not get inner of Field::None"),
+            Field::Value(_) => Err(super::FirstPassError::InvalidSendTables(
+                "Can not get inner of Field::Value",
+            )),
+            Field::None => Err(super::FirstPassError::InvalidSendTables(
+                "Can not get inner of Field::None",
+            )),
         }
     }
 
@@ -501,14 +526,23 @@ impl Field {
             Field::Vector(inner) => Ok(&mut inner.field_enum),
             Field::Serializer(inner) => match inner.serializer.fields.get_mut(idx) {
                 Some(f) => Ok(f),
-                None => panic!(),
+                None => Err(super::FirstPassError::InvalidSendTables(
+                    "Missing field in serializer",
+                )),
             },
             Field::Pointer(inner) => match inner.serializer.fields.get_mut(idx) {
                 Some(f) => Ok(f),
-                None => panic!(),
-            }, // Illegal
-            Field::Value(_) => panic!(),
-            Field::None => panic!(),
+                None => Err(super::FirstPassError::InvalidSendTables(
+                    "Missing field in serializer",
+                )),
+            },
+            // Illegal
+            Field::Value(_) => Err(super::FirstPassError::InvalidSendTables(
+                "Can not get inner of Field::Value",
+            )),
+            Field::None => Err(super::FirstPassError::InvalidSendTables(
+                "Can not get inner of Field::None",
+            )),
         }
     }
 
@@ -542,37 +576,39 @@ impl Field {
         if fi.prop_id == MY_WEAPONS_OFFSET {
             if path.last == 1 {
             } else {
-                fi.prop_id = MY_WEAPONS_OFFSET + path.path[2] as u32 + 1;
+                fi.prop_id = MY_WEAPONS_OFFSET
+                    .wrapping_add(path.path[2] as u32)
+                    .wrapping_add(1);
             }
         }
         if fi.prop_id == WEAPON_SKIN_ID {
-            fi.prop_id = WEAPON_SKIN_ID + path.path[1] as u32;
+            fi.prop_id = WEAPON_SKIN_ID.wrapping_add(path.path[1] as u32);
         }
         if path.path[1] != 1 {
             if fi.prop_id >= ITEM_PURCHASE_COUNT
                 && fi.prop_id < ITEM_PURCHASE_COUNT + FLATTENED_VEC_MAX_LEN
             {
-                fi.prop_id = ITEM_PURCHASE_COUNT + path.path[2] as u32;
+                fi.prop_id = ITEM_PURCHASE_COUNT.wrapping_add(path.path[2] as u32);
             }
             if fi.prop_id >= ITEM_PURCHASE_DEF_IDX
                 && fi.prop_id < ITEM_PURCHASE_DEF_IDX + FLATTENED_VEC_MAX_LEN
             {
-                fi.prop_id = ITEM_PURCHASE_DEF_IDX + path.path[2] as u32;
+                fi.prop_id = ITEM_PURCHASE_DEF_IDX.wrapping_add(path.path[2] as u32);
             }
             if fi.prop_id >= ITEM_PURCHASE_COST
                 && fi.prop_id < ITEM_PURCHASE_COST + FLATTENED_VEC_MAX_LEN
             {
-                fi.prop_id = ITEM_PURCHASE_COST + path.path[2] as u32;
+                fi.prop_id = ITEM_PURCHASE_COST.wrapping_add(path.path[2] as u32);
             }
             if fi.prop_id >= ITEM_PURCHASE_HANDLE
                 && fi.prop_id < ITEM_PURCHASE_HANDLE + FLATTENED_VEC_MAX_LEN
             {
-                fi.prop_id = ITEM_PURCHASE_HANDLE + path.path[2] as u32;
+                fi.prop_id = ITEM_PURCHASE_HANDLE.wrapping_add(path.path[2] as u32);
             }
             if fi.prop_id >= ITEM_PURCHASE_NEW_DEF_IDX
                 && fi.prop_id < ITEM_PURCHASE_NEW_DEF_IDX + FLATTENED_VEC_MAX_LEN
             {
-                fi.prop_id = ITEM_PURCHASE_NEW_DEF_IDX + path.path[2] as u32;
+                fi.prop_id = ITEM_PURCHASE_NEW_DEF_IDX.wrapping_add(path.path[2] as u32);
             }
         }
 
@@ -584,7 +620,9 @@ impl Field {
             Self::Value(inner) => Ok(inner.decoder),
             Self::Pointer(inner) => Ok(inner.decoder),
             Self::Vector(_) => Ok(decoder::Decoder::UnsignedDecoder),
-            _ => panic!(),
+            _ => Err(super::FirstPassError::InvalidSendTables(
+                "Field does not have a decoder",
+            )),
         }
     }
 }
@@ -0,0 +1,630 @@
+//! Encoding and decoding of the entries in string table updates, as they are sent in
+//! `svc_CreateStringTable` and `svc_UpdateStringTable` messages
+
+use crate::bitreader::{BitReadError, Bitreader};
+use crate::bitwriter::Bitwriter;
+
+/// The number of previous keys that entries can refer back to
+const KEY_HISTORY_SIZE: usize = 32;
+
+/// The properties of a string table, that are needed to decode its entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringTableInfo {
+    pub name: String,
+    pub user_data_fixed_size: bool,
+    pub user_data_size_bits: i32,
+    pub flags: i32,
+    pub using_varint_bitcounts: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringTableEntry {
+    pub index: i32,
+    pub key: Option<String>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl StringTableInfo {
+    pub fn from_create(raw: &crate::csgo_proto::CsvcMsgCreateStringTable) -> Self {
+        Self {
+            name: raw.name().to_owned(),
+            user_data_fixed_size: raw.user_data_fixed_size(),
+            user_data_size_bits: raw.user_data_size_bits(),
+            flags: raw.flags(),
+            using_varint_bitcounts: raw.using_varint_bitcounts(),
+        }
+    }
+
+    /// Returns the uncompressed entry data of a `svc_CreateStringTable` message
+    pub fn create_data(
+        raw: &crate::csgo_proto::CsvcMsgCreateStringTable,
+    ) -> Result<std::borrow::Cow<'_, [u8]>, snap::Error> {
+        if !raw.data_compressed() {
+            return Ok(std::borrow::Cow::Borrowed(raw.string_data()));
+        }
+
+        snap::raw::Decoder::new()
+            .decompress_vec(raw.string_data())
+            .map(std::borrow::Cow::Owned)
+    }
+
+    pub fn parse_entries(
+        &self,
+        data: &[u8],
+        n_entries: usize,
+    ) -> Result<Vec<StringTableEntry>, BitReadError> {
+        let mut bitreader = Bitreader::new(data);
+
+        // The number of entries comes from the demo, but every entry takes at least a bit
+        let mut entries = Vec::with_capacity(n_entries.min(data.len() * 8));
+        let mut history: std::collections::VecDeque<String> =
+            std::collections::VecDeque::with_capacity(KEY_HISTORY_SIZE);
+        let mut index: i32 = -1;
+
+        for _ in 0..n_entries {
+            let delta = if bitreader.read_boolean()? {
+                1
+            } else {
+                i32::try_from(bitreader.read_varint()?)
+                    .ok()
+                    .and_then(|delta| delta.checked_add(1))
+                    .ok_or(BitReadError::MalformedMessage)?
+            };
+            index = index
+                .checked_add(delta)
+                .ok_or(BitReadError::MalformedMessage)?;
+
+            let key = if bitreader.read_boolean()? {
+                let key = if bitreader.read_boolean()? {
+                    let position = bitreader.read_nbits(5)? as usize;
+                    let length = bitreader.read_nbits(5)? as usize;
+
+                    let mut key = match history.get(position) {
+                        Some(previous) => previous
+                            .get(..length)
+                            .unwrap_or(previous.as_str())
+                            .to_owned(),
+                        None => String::new(),
+                    };
+                    key.push_str(&read_string(&mut bitreader)?);
+                    key
+                } else {
+                    read_string(&mut bitreader)?
+                };
+
+                if history.len() >= KEY_HISTORY_SIZE {
+                    history.pop_front();
+                }
+                history.push_back(key.clone());
+
+                Some(key)
+            } else {
+                None
+            };
+
+            let value = if bitreader.read_boolean()? {
+                let mut compressed = false;
+                let bits = if self.user_data_fixed_size {
+                    self.user_data_size_bits as u32
+                } else {
+                    if self.flags & 0x1 != 0 {
+                        compressed = bitreader.read_boolean()?;
+                    }
+                    let bytes = if self.using_varint_bitcounts {
+                        bitreader.read_u_bit_var()?
+                    } else {
+                        bitreader.read_nbits(17)?
+                    };
+                    bytes.checked_mul(8).ok_or(BitReadError::MalformedMessage)?
+                };
+
+                let (full_bytes, rest_bits) = (bits / 8, bits % 8);
+                let mut value = bitreader.read_n_bytes(full_bytes as usize)?;
+                if rest_bits > 0 {
+                    value.push(bitreader.read_nbits(rest_bits)? as u8);
+                }
+
+                if compressed {
+                    value = snap::raw::Decoder::new()
+                        .decompress_vec(&value)
+                        .map_err(|_| BitReadError::MalformedMessage)?;
+                }
+
+                Some(value)
+            } else {
+                None
+            };
+
+            entries.push(StringTableEntry { index, key, value });
+        }
+
+        Ok(entries)
+    }
+
+    /// Encodes the entries, so that they can be decoded again using
+    /// [`StringTableInfo::parse_entries`].
+    ///
+    /// Keys are always written in full and values are never compressed, which is less compact
+    /// than what the game produces, but is still understood by it
+    pub fn write_entries(&self, entries: &[StringTableEntry]) -> Vec<u8> {
+        let mut writer = Bitwriter::new();
+
+        let mut index = -1;
+        for entry in entries {
+            if entry.index == index + 1 {
+                writer.write_boolean(true);
+            } else {
+                writer.write_boolean(false);
+                writer.write_varint((entry.index - index - 1) as u32);
+            }
+            index = entry.index;
+
+            match entry.key.as_ref() {
+                Some(key) => {
+                    writer.write_boolean(true);
+                    writer.write_boolean(false);
+                    writer.write_string(key);
+                }
+                None => writer.write_boolean(false),
+            };
+
+            match entry.value.as_ref() {
+                Some(value) => {
+                    writer.write_boolean(true);
+
+                    if self.user_data_fixed_size {
+                        // Fixed size values have to be exactly as large as the table expects
+                        let bits = self.user_data_size_bits as usize;
+                        let (full_bytes, rest_bits) = (bits / 8, bits % 8);
+                        for i in 0..full_bytes {
+                            writer.write_nbits(value.get(i).copied().unwrap_or(0) as u32, 8);
+                        }
+                        if rest_bits > 0 {
+                            let last = value.get(full_bytes).copied().unwrap_or(0);
+                            writer.write_nbits(last as u32, rest_bits as u32);
+                        }
+                    } else {
+                        if self.flags & 0x1 != 0 {
+                            writer.write_boolean(false);
+                        }
+                        if self.using_varint_bitcounts {
+                            writer.write_u_bit_var(value.len() as u32);
+                        } else {
+                            writer.write_nbits(value.len() as u32, 17);
+                        }
+                        writer.write_bytes(value);
+                    }
+                }
+                None => writer.write_boolean(false),
+            };
+        }
+
+        writer.into_bytes()
+    }
+}
+
+#[derive(Debug)]
+pub enum StringTableError {
+    Bitreader(BitReadError),
+    Decompress(snap::Error),
+    /// An update refers to a table that was never created
+    UnknownTable(i32),
+    /// A snapshot contains a table that was never created, so it is unknown how the updates of
+    /// the table are encoded
+    UnknownTableName(String),
+}
+
+impl From<BitReadError> for StringTableError {
+    fn from(value: BitReadError) -> Self {
+        Self::Bitreader(value)
+    }
+}
+
+impl From<snap::Error> for StringTableError {
+    fn from(value: snap::Error) -> Self {
+        Self::Decompress(value)
+    }
+}
+
+/// A single string table with all of its current entries
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringTable {
+    pub info: StringTableInfo,
+    entries: std::collections::BTreeMap<i32, StringTableEntry>,
+}
+
+impl StringTable {
+    pub fn new(info: StringTableInfo) -> Self {
+        Self {
+            info,
+            entries: std::collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn get(&self, index: i32) -> Option<&StringTableEntry> {
+        self.entries.get(&index)
+    }
+
+    /// The first entry with the given key
+    pub fn find(&self, key: &str) -> Option<&StringTableEntry> {
+        self.entries
+            .values()
+            .find(|entry| entry.key.as_deref() == Some(key))
+    }
+
+    /// All the entries, ordered by their index
+    pub fn entries(&self) -> impl Iterator<Item = &StringTableEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Applies the changed entries and returns them as they are stored afterwards.
+    ///
+    /// A change without a key or value keeps the current key or value of the entry
+    pub(crate) fn apply(&mut self, changes: Vec<StringTableEntry>) -> Vec<StringTableEntry> {
+        changes
+            .into_iter()
+            .map(|change| {
+                let entry = self
+                    .entries
+                    .entry(change.index)
+                    .or_insert(StringTableEntry {
+                        index: change.index,
+                        key: None,
+                        value: None,
+                    });
+                if change.key.is_some() {
+                    entry.key = change.key;
+                }
+                if change.value.is_some() {
+                    entry.value = change.value;
+                }
+                entry.clone()
+            })
+            .collect()
+    }
+}
+
+/// All the string tables of a demo, which are kept up to date by applying the string table
+/// messages of the demo in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StringTables {
+    /// The tables in the order they were created, which is how updates refer to them
+    tables: Vec<StringTable>,
+}
+
+impl StringTables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&StringTable> {
+        self.tables.iter().find(|table| table.name() == name)
+    }
+
+    /// The table with the given id, which is the order in which the tables were created
+    pub fn by_id(&self, id: usize) -> Option<&StringTable> {
+        self.tables.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StringTable> {
+        self.tables.iter()
+    }
+
+    pub(crate) fn push(&mut self, table: StringTable) {
+        self.tables.push(table);
+    }
+
+    /// Applies the message and returns the id of every changed table, together with its changed
+    /// entries
+    pub fn apply(
+        &mut self,
+        message: &crate::parser::StringTableMessage<'_>,
+    ) -> Result<Vec<(usize, Vec<StringTableEntry>)>, StringTableError> {
+        use crate::parser::StringTableMessage;
+
+        match message {
+            StringTableMessage::ClearAll => {
+                self.tables.clear();
+                Ok(Vec::new())
+            }
+            StringTableMessage::Create(raw) => {
+                let info = StringTableInfo::from_create(raw);
+                let data = StringTableInfo::create_data(raw)?;
+                let changes = info.parse_entries(&data, raw.num_entries().max(0) as usize)?;
+
+                let mut table = StringTable::new(info);
+                let changes = table.apply(changes);
+                self.tables.push(table);
+
+                Ok(vec![(self.tables.len() - 1, changes)])
+            }
+            StringTableMessage::Update(raw) => {
+                let id = raw.table_id();
+                let table = usize::try_from(id)
+                    .ok()
+                    .and_then(|id| self.tables.get_mut(id))
+                    .ok_or(StringTableError::UnknownTable(id))?;
+
+                let changes = table
+                    .info
+                    .parse_entries(raw.string_data(), raw.num_changed_entries().max(0) as usize)?;
+
+                Ok(vec![(id as usize, table.apply(changes))])
+            }
+            StringTableMessage::Snapshot(raw) => {
+                let ids = raw
+                    .tables
+                    .iter()
+                    .map(|snapshot| {
+                        self.tables
+                            .iter()
+                            .position(|table| table.name() == snapshot.table_name())
+                            .ok_or_else(|| {
+                                StringTableError::UnknownTableName(snapshot.table_name().to_owned())
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut changed = Vec::with_capacity(raw.tables.len());
+                for (snapshot, id) in raw.tables.iter().zip(ids) {
+                    let table = &mut self.tables[id];
+                    table.entries.clear();
+                    let changes = snapshot
+                        .items
+                        .iter()
+                        .enumerate()
+                        .map(|(index, item)| StringTableEntry {
+                            index: index as i32,
+                            key: item.str.clone(),
+                            value: item.data.clone(),
+                        })
+                        .collect();
+
+                    changed.push((id, table.apply(changes)));
+                }
+                Ok(changed)
+            }
+        }
+    }
+}
+
+fn read_string(bitreader: &mut Bitreader) -> Result<String, BitReadError> {
+    let mut bytes = Vec::new();
+    loop {
+        match bitreader.read_nbits(8)? as u8 {
+            0 => break,
+            b => bytes.push(b),
+        };
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let entries = vec![
+            StringTableEntry {
+                index: 0,
+                key: Some("0".to_owned()),
+                value: Some(vec![1, 2, 3]),
+            },
+            StringTableEntry {
+                index: 1,
+                key: Some("1".to_owned()),
+                value: None,
+            },
+            StringTableEntry {
+                index: 7,
+                key: None,
+                value: Some(vec![]),
+            },
+        ];
+
+        for using_varint_bitcounts in [true, false] {
+            let info = StringTableInfo {
+                name: "userinfo".to_owned(),
+                user_data_fixed_size: false,
+                user_data_size_bits: 0,
+                flags: 1,
+                using_varint_bitcounts,
+            };
+
+            let data = info.write_entries(&entries);
+            assert_eq!(entries, info.parse_entries(&data, entries.len()).unwrap());
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        let info = StringTableInfo {
+            name: "userinfo".to_owned(),
+            user_data_fixed_size: false,
+            user_data_size_bits: 0,
+            flags: 0,
+            using_varint_bitcounts: true,
+        };
+
+        // The index of the second entry does not fit into an i32
+        let mut writer = Bitwriter::new();
+        for delta in [i32::MAX as u32 - 1, 5] {
+            writer.write_boolean(false);
+            writer.write_varint(delta);
+            writer.write_boolean(false);
+            writer.write_boolean(false);
+        }
+        assert!(matches!(
+            info.parse_entries(&writer.into_bytes(), 2),
+            Err(BitReadError::MalformedMessage)
+        ));
+
+        // The length of the value in bits does not fit into an u32
+        let mut writer = Bitwriter::new();
+        writer.write_boolean(true);
+        writer.write_boolean(false);
+        writer.write_boolean(true);
+        writer.write_u_bit_var(u32::MAX / 4);
+        assert!(matches!(
+            info.parse_entries(&writer.into_bytes(), 1),
+            Err(BitReadError::MalformedMessage)
+        ));
+    }
+
+    fn create(
+        name: &str,
+        entries: &[StringTableEntry],
+    ) -> crate::csgo_proto::CsvcMsgCreateStringTable {
+        let info = StringTableInfo {
+            name: name.to_owned(),
+            user_data_fixed_size: false,
+            user_data_size_bits: 0,
+            flags: 0,
+            using_varint_bitcounts: true,
+        };
+
+        crate::csgo_proto::CsvcMsgCreateStringTable {
+            name: Some(name.to_owned()),
+            num_entries: Some(entries.len() as i32),
+            user_data_fixed_size: Some(false),
+            flags: Some(0),
+            using_varint_bitcounts: Some(true),
+            string_data: Some(info.write_entries(entries)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tables() {
+        use crate::parser::StringTableMessage;
+
+        let mut tables = StringTables::new();
+
+        let raw = create(
+            "userinfo",
+            &[StringTableEntry {
+                index: 0,
+                key: Some("0".to_owned()),
+                value: Some(vec![1]),
+            }],
+        );
+        tables.apply(&StringTableMessage::Create(&raw)).unwrap();
+        let raw = create("modelprecache", &[]);
+        tables.apply(&StringTableMessage::Create(&raw)).unwrap();
+
+        // Only changes the value of the existing entry and adds a new one
+        let changes = [
+            StringTableEntry {
+                index: 0,
+                key: None,
+                value: Some(vec![2]),
+            },
+            StringTableEntry {
+                index: 3,
+                key: Some("3".to_owned()),
+                value: None,
+            },
+        ];
+        let raw = crate::csgo_proto::CsvcMsgUpdateStringTable {
+            table_id: Some(0),
+            num_changed_entries: Some(changes.len() as i32),
+            string_data: Some(tables.by_id(0).unwrap().info.write_entries(&changes)),
+        };
+        let changed = tables.apply(&StringTableMessage::Update(&raw)).unwrap();
+
+        assert_eq!(1, changed.len());
+        assert_eq!(0, changed[0].0);
+        assert_eq!(Some("0"), changed[0].1[0].key.as_deref());
+
+        let userinfo = tables.get("userinfo").unwrap();
+        assert_eq!(2, userinfo.len());
+        assert_eq!(Some(&vec![2]), userinfo.get(0).unwrap().value.as_ref());
+        assert_eq!(3, userinfo.find("3").unwrap().index);
+        assert!(tables.get("modelprecache").unwrap().is_empty());
+
+        let raw = crate::csgo_proto::CsvcMsgUpdateStringTable {
+            table_id: Some(2),
+            ..Default::default()
+        };
+        assert!(matches!(
+            tables.apply(&StringTableMessage::Update(&raw)),
+            Err(StringTableError::UnknownTable(2))
+        ));
+
+        // A snapshot replaces all the entries, but can not create a table
+        let snapshot = |names: &[&str]| crate::csgo_proto::CDemoStringTables {
+            tables: names
+                .iter()
+                .map(|name| crate::csgo_proto::c_demo_string_tables::TableT {
+                    table_name: Some(name.to_string()),
+                    items: vec![crate::csgo_proto::c_demo_string_tables::ItemsT {
+                        str: Some("5".to_owned()),
+                        data: None,
+                    }],
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        assert!(matches!(
+            tables.apply(&StringTableMessage::Snapshot(&snapshot(&["modelprecache", "unknown"]))),
+            Err(StringTableError::UnknownTableName(name)) if name == "unknown"
+        ));
+        assert!(tables.get("modelprecache").unwrap().is_empty());
+
+        tables
+            .apply(&StringTableMessage::Snapshot(&snapshot(&["userinfo"])))
+            .unwrap();
+        let userinfo = tables.get("userinfo").unwrap();
+        assert_eq!(1, userinfo.len());
+        assert_eq!(0, userinfo.find("5").unwrap().index);
+
+        tables.apply(&StringTableMessage::ClearAll).unwrap();
+        assert!(tables.get("userinfo").is_none());
+    }
+
+    #[test]
+    fn key_history() {
+        let mut writer = Bitwriter::new();
+        // "userinfo" as a normal key
+        writer.write_boolean(true);
+        writer.write_boolean(true);
+        writer.write_boolean(false);
+        writer.write_string("userinfo");
+        writer.write_boolean(false);
+        // "user" from the history followed by "cmd"
+        writer.write_boolean(true);
+        writer.write_boolean(true);
+        writer.write_boolean(true);
+        writer.write_nbits(0, 5);
+        writer.write_nbits(4, 5);
+        writer.write_string("cmd");
+        writer.write_boolean(false);
+        let data = writer.into_bytes();
+
+        let info = StringTableInfo {
+            name: "test".to_owned(),
+            user_data_fixed_size: false,
+            user_data_size_bits: 0,
+            flags: 0,
+            using_varint_bitcounts: true,
+        };
+        let entries = info.parse_entries(&data, 2).unwrap();
+
+        assert_eq!(Some("userinfo"), entries[0].key.as_deref());
+        assert_eq!(Some("usercmd"), entries[1].key.as_deref());
+        assert_eq!(1, entries[1].index);
+    }
+}
@@ -13,6 +13,70 @@ pub fn parse_varint(input: &[u8]) -> Result<(&[u8], u32), ()> {
     Ok((&input[5..], result))
 }
 
+/// Reads a single varint from the given reader, returns `Ok(None)` if the reader was already at
+/// its end before reading the first byte
+pub fn read_varint<R>(reader: &mut R) -> std::io::Result<Option<u32>>
+where
+    R: std::io::Read,
+{
+    let mut result: u32 = 0;
+
+    for count in 0..5 {
+        let mut buf = [0; 1];
+        let read = loop {
+            match reader.read(&mut buf) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+        };
+        if read == 0 {
+            if count == 0 {
+                return Ok(None);
+            }
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let b = buf[0] as u32;
+        result |= (b & 127) << (7 * count);
+
+        if b & 0x80 == 0 {
+            return Ok(Some(result));
+        }
+    }
+
+    Ok(Some(result))
+}
+
+/// Writes the value as a varint, the counterpart to [`read_varint`]
+pub fn write_varint<W>(writer: &mut W, mut value: u32) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    let mut buf = [0; 5];
+    let mut len = 0;
+    loop {
+        let b = (value & 127) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            buf[len] = b;
+            len += 1;
+            break;
+        }
+
+        buf[len] = b | 0x80;
+        len += 1;
+    }
+
+    writer.write_all(&buf[..len])
+}
+
+/// The number of bytes [`write_varint`] uses for the value
+pub fn varint_len(value: u32) -> usize {
+    (32 - value.leading_zeros() as usize).max(1).div_ceil(7)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,4 +126,43 @@ mod tests {
 
         assert_eq!(&[0xff], remaining);
     }
+
+    #[test]
+    fn read_from_reader() {
+        let mut input: &[u8] = &[0x87, 0x60, 0xff];
+
+        let value = read_varint(&mut input).unwrap();
+
+        assert_eq!(Some(0x3007), value);
+        assert_eq!(&[0xff], input);
+    }
+
+    #[test]
+    fn read_from_empty_reader() {
+        let mut input: &[u8] = &[];
+
+        assert_eq!(None, read_varint(&mut input).unwrap());
+    }
+
+    #[test]
+    fn read_from_truncated_reader() {
+        let mut input: &[u8] = &[0x87];
+
+        let err = read_varint(&mut input).unwrap_err();
+
+        assert_eq!(std::io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn write_roundtrip() {
+        for value in [0, 1, 127, 128, 0x3007, u32::MAX / 3, u32::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value).unwrap();
+
+            let (remaining, parsed) = parse_varint(&buf).unwrap();
+            assert!(remaining.is_empty());
+            assert_eq!(value, parsed);
+            assert_eq!(buf.len(), varint_len(value));
+        }
+    }
 }
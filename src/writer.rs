@@ -0,0 +1,169 @@
+use crate::{container::HEADER_SIZE, DemoCommand, Frame, MAGIC};
+
+/// The counterpart to [`Container`](crate::Container) and [`Frame`], which writes frames into a
+/// new demo.
+///
+/// The header is only completed once all frames were written, using [`DemoWriter::finish`],
+/// which is why the underlying writer also needs to be seekable
+pub struct DemoWriter<W> {
+    writer: W,
+    compress: bool,
+    /// The number of bytes written after the header
+    written: usize,
+    file_info_offset: Option<u32>,
+    spawngroups_offset: Option<u32>,
+    encoder: snap::raw::Encoder,
+}
+
+impl<W> DemoWriter<W>
+where
+    W: std::io::Write + std::io::Seek,
+{
+    /// Starts a new demo by writing the header, with placeholders for the fields that are only
+    /// known once all the frames were written
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[0; HEADER_SIZE - MAGIC.len()])?;
+
+        Ok(Self {
+            writer,
+            compress: false,
+            written: 0,
+            file_info_offset: None,
+            spawngroups_offset: None,
+            encoder: snap::raw::Encoder::new(),
+        })
+    }
+
+    /// Whether or not uncompressed frames should be compressed before writing them, which is
+    /// what the game does for most frames. Frames that are already compressed are always written
+    /// as they are.
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Writes the frame, keeping the data exactly as it is if it was already compressed
+    pub fn write_frame(&mut self, frame: &Frame<'_>) -> std::io::Result<()> {
+        if frame.compressed {
+            return self.write_parts(frame.cmd, frame.tick, true, &frame.inner);
+        }
+
+        self.write_data(frame.cmd, frame.tick, &frame.inner)
+    }
+
+    /// Writes a new frame containing the given uncompressed data, like an encoded protobuf
+    /// message
+    pub fn write_data(&mut self, cmd: DemoCommand, tick: i32, data: &[u8]) -> std::io::Result<()> {
+        if !self.compress {
+            return self.write_parts(cmd, tick, false, data);
+        }
+
+        let compressed = self
+            .encoder
+            .compress_vec(data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.write_parts(cmd, tick, true, &compressed)
+    }
+
+    fn write_parts(
+        &mut self,
+        cmd: DemoCommand,
+        tick: i32,
+        compressed: bool,
+        data: &[u8],
+    ) -> std::io::Result<()> {
+        let offset = u32::try_from(HEADER_SIZE + self.written).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The demo is too large to be stored",
+            )
+        })?;
+        match cmd {
+            DemoCommand::FileInfo => self.file_info_offset = Some(offset),
+            DemoCommand::SpawnGroups => self.spawngroups_offset = Some(offset),
+            _ => {}
+        }
+
+        let mut raw_cmd = i32::from(cmd) as u32;
+        if compressed {
+            raw_cmd |= 64;
+        }
+
+        let mut frame_header = Vec::with_capacity(15);
+        crate::varint::write_varint(&mut frame_header, raw_cmd)?;
+        crate::varint::write_varint(&mut frame_header, tick as u32)?;
+        crate::varint::write_varint(&mut frame_header, data.len() as u32)?;
+
+        self.writer.write_all(&frame_header)?;
+        self.writer.write_all(data)?;
+        self.written += frame_header.len() + data.len();
+
+        Ok(())
+    }
+
+    /// Fills in the remaining fields of the header and returns the underlying writer.
+    ///
+    /// The FileInfo frame has to be the last frame written, otherwise the demo is not accepted by
+    /// [`Container::parse`](crate::Container::parse)
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let end = self.writer.stream_position()?;
+        self.writer
+            .seek(std::io::SeekFrom::Start(MAGIC.len() as u64))?;
+        self.writer
+            .write_all(&self.file_info_offset.unwrap_or(0).to_le_bytes())?;
+        self.writer
+            .write_all(&self.spawngroups_offset.unwrap_or(0).to_le_bytes())?;
+        self.writer.seek(std::io::SeekFrom::Start(end))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three small frames, a FileHeader with 2 bytes, a Packet with 1 byte and an empty FileInfo
+    const FRAMES: &[u8] = &[1, 0, 2, 0xaa, 0xbb, 7, 5, 1, 0xcc, 2, 9, 0];
+
+    #[test]
+    fn roundtrip() {
+        let mut writer = DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+        for frame in crate::FrameIterator::parse(FRAMES) {
+            writer.write_frame(&frame).unwrap();
+        }
+        let output = writer.finish().unwrap().into_inner();
+
+        let container = crate::Container::parse(&output).unwrap();
+        assert_eq!(FRAMES, container.inner);
+        assert_eq!(
+            HEADER_SIZE as u32 + 9,
+            container.file_info_offset,
+            "The offset points at the FileInfo frame"
+        );
+        assert!(container.file_info_frame().is_some());
+    }
+
+    #[test]
+    fn compressed() {
+        let mut writer = DemoWriter::new(std::io::Cursor::new(Vec::new()))
+            .unwrap()
+            .compress(true);
+        for frame in crate::FrameIterator::parse(FRAMES) {
+            writer.write_frame(&frame).unwrap();
+        }
+        let output = writer.finish().unwrap().into_inner();
+
+        let container = crate::Container::parse(&output).unwrap();
+        let mut buffer = Vec::new();
+        let frames: Vec<_> = crate::FrameIterator::parse(container.inner).collect();
+        assert_eq!(3, frames.len());
+        assert!(frames.iter().all(|f| f.compressed));
+        assert_eq!(
+            &[0xaa, 0xbb],
+            frames[0].decompress_with_buf(&mut buffer).unwrap()
+        );
+    }
+}
@@ -13,6 +13,7 @@ fn cmp_lazy_nonlazy_events() {
         csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
 
     assert_eq!(demo.player_info, lazy_demo.player_info());
+    assert_eq!(demo.roster, lazy_demo.roster().unwrap());
 
     for (normal, lazied) in demo
         .events
@@ -53,3 +54,292 @@ fn cmp_lazy_nonlazy_entities() {
     }
     assert_eq!(None, lazy_iter.next());
 }
+
+#[test]
+fn cmp_lazy_reader_events() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let file = std::fs::File::open("testfiles/mirage.dem").unwrap();
+    let container = csdemo::ContainerReader::new(file).unwrap();
+    let reader_events: Vec<_> =
+        csdemo::lazyparser::LazyEventIterator::from_fallible_frames(container.frames())
+            .map(|e| e.unwrap())
+            .collect();
+
+    let slice_events: Vec<_> = lazy_demo.events().map(|e| e.unwrap()).collect();
+    assert_eq!(slice_events, reader_events);
+}
+
+#[test]
+fn cmp_lazy_nonlazy_file_info() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+    assert!(container.file_info_frame().is_some());
+
+    let demo = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(container.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    assert_eq!(Some(demo.info), lazy_demo.file_info());
+}
+
+#[test]
+fn lazy_seek_entities() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let target = 50000;
+    let mut seeked = lazy_demo.seek(target).unwrap().filter_map(|e| e.ok());
+
+    let (tick, first) = seeked.next().unwrap();
+    assert!(tick as i32 >= target - 1);
+    assert!(!first.props.is_empty());
+
+    // Everything after the tick we seeked to, should be the same as when going through the
+    // entire demo
+    let skip_to = target as u32 + 1;
+    let mut seeked = seeked.skip_while(|(t, _)| *t <= skip_to);
+    let full = lazy_demo
+        .entities()
+        .filter_map(|e| e.ok())
+        .skip_while(|(t, _)| *t <= skip_to);
+
+    let mut count = 0;
+    for normal in full {
+        assert_eq!(Some(normal), seeked.next());
+        count += 1;
+    }
+    assert_eq!(None, seeked.next());
+    assert!(count > 0);
+}
+
+#[test]
+fn lazy_seek_position() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+    let inner = container.inner;
+    let lazy_demo = csdemo::lazyparser::LazyParser::new(container);
+
+    let mut seeked = lazy_demo.seek(50000).unwrap();
+    let checkpoint = loop {
+        if let Some(checkpoint) = seeked.checkpoint() {
+            break checkpoint;
+        }
+        seeked.next().unwrap().unwrap();
+    };
+
+    // The frame of the checkpoint counts every frame before it, even the skipped ones
+    let frames = csdemo::FrameIterator::parse(&inner[..checkpoint.offset()]).count();
+    assert_eq!(frames, checkpoint.frame());
+}
+
+#[test]
+fn lazy_seek_string_tables() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let seeked = lazy_demo.seek(50000).unwrap();
+    assert!(seeked.string_tables().iter().count() > 0);
+    assert!(!seeked.string_tables().get("userinfo").unwrap().is_empty());
+
+    // The properties of the tables are only part of the signon, but are needed to decode updates
+    let mut full = lazy_demo.entities();
+    for entity in full.by_ref() {
+        entity.unwrap();
+    }
+    for table in full.string_tables().iter() {
+        let seeked = seeked.string_tables().get(table.name()).unwrap();
+        assert_eq!(table.info, seeked.info);
+    }
+}
+
+#[test]
+fn lazy_entity_state() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let mut iter = lazy_demo.entities();
+    let mut count = 0;
+    while let Some((_, update)) = iter.next().and_then(|e| e.ok()) {
+        let Some(state) = iter.entity(update.id) else {
+            // Deleted later in the same frame
+            continue;
+        };
+        if state.cls != update.cls {
+            continue;
+        }
+
+        // The complete state contains at least the props of every update, but the update might
+        // be older than the last parsed frame
+        for prop in update.props.iter() {
+            assert!(state.get_prop(&prop.prop_info.prop_name).is_some());
+        }
+        count += 1;
+        if count > 10000 {
+            break;
+        }
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn follow_growing_demo() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let path = std::env::temp_dir().join(format!("csdemo-follow-{}.dem", std::process::id()));
+    std::fs::write(&path, &content[..16]).unwrap();
+
+    // Simulates the game still writing the demo, while we are already parsing it
+    let writer = {
+        let path = path.clone();
+        let content = content.clone();
+        std::thread::spawn(move || {
+            use std::io::Write;
+
+            let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+            for chunk in content[16..].chunks(content.len() / 20 + 1) {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                file.write_all(chunk).unwrap();
+                file.flush().unwrap();
+            }
+        })
+    };
+
+    let file = std::fs::File::open(&path).unwrap();
+    let follower = csdemo::ContainerReader::new(file)
+        .unwrap()
+        .follow()
+        .unwrap()
+        .poll_interval(std::time::Duration::from_millis(1))
+        .idle_timeout(Some(std::time::Duration::from_secs(10)));
+    let followed: Vec<_> = csdemo::lazyparser::LazyEventIterator::from_fallible_frames(follower)
+        .map(|e| e.unwrap())
+        .collect();
+
+    writer.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let expected: Vec<_> = lazy_demo.events().filter_map(|e| e.ok()).collect();
+    assert_eq!(expected, followed);
+}
+
+#[test]
+fn cmp_lazy_stream() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let mut events = Vec::new();
+    let mut entities = Vec::new();
+    let mut deleted = 0;
+    let mut table_updates = 0;
+    let mut user_messages = 0;
+    for (tick, item) in lazy_demo
+        .stream(csdemo::parser::EntityFilter::all())
+        .filter_map(|e| e.ok())
+    {
+        match item {
+            csdemo::lazyparser::StreamItem::Event(event) => events.push((tick, event)),
+            csdemo::lazyparser::StreamItem::Entity(state) => {
+                if state.op == csdemo::parser::EntityOp::Deleted {
+                    deleted += 1;
+                }
+                entities.push((tick, state));
+            }
+            csdemo::lazyparser::StreamItem::StringTableUpdate { .. } => table_updates += 1,
+            csdemo::lazyparser::StreamItem::UserMessage { .. } => user_messages += 1,
+        }
+    }
+
+    let expected_events: Vec<_> = lazy_demo
+        .events()
+        .filter_map(|e| e.ok())
+        .map(|e| (e.tick, e.event))
+        .collect();
+    assert_eq!(expected_events, events);
+
+    let expected_entities: Vec<_> = lazy_demo.entities().filter_map(|e| e.ok()).collect();
+    assert_eq!(expected_entities, entities);
+
+    assert!(deleted > 0);
+    assert!(table_updates > 0);
+    assert!(user_messages > 0);
+}
+
+#[test]
+fn cmp_lazy_owned_events() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+    let owned_demo = csdemo::lazyparser::OwnedLazyParser::new(content.clone()).unwrap();
+
+    let owned_events = std::thread::spawn(move || {
+        owned_demo
+            .events()
+            .filter_map(|e| e.ok())
+            .collect::<Vec<_>>()
+    })
+    .join()
+    .unwrap();
+
+    let expected: Vec<_> = lazy_demo.events().filter_map(|e| e.ok()).collect();
+    assert_eq!(expected, owned_events);
+}
+
+#[test]
+fn resume_lazy_events() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let lazy_demo =
+        csdemo::lazyparser::LazyParser::new(csdemo::Container::parse(&content).unwrap());
+
+    let expected: Vec<_> = lazy_demo.events().filter_map(|e| e.ok()).collect();
+
+    let mut events = lazy_demo.events();
+    let mut parsed = Vec::new();
+    let checkpoint = loop {
+        parsed.push(events.next().unwrap().unwrap());
+        if parsed.len() < expected.len() / 2 {
+            continue;
+        }
+        if let Some(checkpoint) = events.checkpoint() {
+            break checkpoint;
+        }
+    };
+
+    let checkpoint = csdemo::parser::Checkpoint::from_bytes(&checkpoint.to_bytes()).unwrap();
+    let mut frames =
+        csdemo::FallibleFrameIterator::parse(csdemo::Container::parse(&content).unwrap().inner);
+    frames.by_ref().take(checkpoint.frame()).for_each(drop);
+    assert_eq!(frames.offset(), checkpoint.offset());
+
+    parsed.extend(
+        lazy_demo
+            .resume_events(&checkpoint)
+            .unwrap()
+            .filter_map(|e| e.ok()),
+    );
+
+    assert_eq!(expected, parsed);
+}
@@ -18,7 +18,7 @@ fn mirage_1() {
     assert_eq!("de_mirage", output.header.map_name());
 
     for event in output.events.iter() {
-        if let DemoEvent::GameEvent(gevent) = event {
+        if let DemoEvent::GameEvent(gevent) = &event.event {
             if let GameEvent::PlayerDeath(death) = gevent.as_ref() {
                 assert!(
                     death.remaining.is_empty(),
@@ -27,13 +27,17 @@ fn mirage_1() {
                 );
 
                 let died_user = output
-                    .player_info
-                    .get(death.userid.as_ref().unwrap())
+                    .roster
+                    .get(*death.userid.as_ref().unwrap(), event.tick)
                     .unwrap();
                 // dbg!(died_user);
             }
         };
     }
+
+    for player in output.player_info.values() {
+        assert!(output.roster.by_xuid(player.xuid).is_some());
+    }
 }
 
 #[test]
@@ -53,3 +57,410 @@ fn ancient_1() {
 
     assert_eq!("de_ancient", output.header.map_name());
 }
+
+#[test]
+fn mirage_reader() {
+    let file = std::fs::File::open("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::ContainerReader::new(file).unwrap();
+    assert_eq!(
+        123333,
+        container
+            .frames()
+            .inspect(|frame| assert!(frame.is_ok()))
+            .count()
+    );
+
+    let file = std::fs::File::open("testfiles/mirage.dem").unwrap();
+    let container = csdemo::ContainerReader::new(file).unwrap();
+
+    let output = csdemo::parser::parse_fallible(
+        container.frames(),
+        csdemo::parser::EntityFilter::disabled(),
+        csdemo::parser::ParseOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!("de_mirage", output.header.map_name());
+}
+
+#[test]
+fn truncated_reader() {
+    // A complete FileHeader frame, followed by a frame that claims more data than there is
+    let mut demo = csdemo::MAGIC.to_vec();
+    demo.extend(0u32.to_le_bytes());
+    demo.extend(0u32.to_le_bytes());
+    demo.extend([1, 0, 0, 7, 0, 10, 0xaa]);
+
+    let container = csdemo::ContainerReader::new(demo.as_slice()).unwrap();
+    let err = csdemo::parser::parse_fallible(
+        container.frames(),
+        csdemo::parser::EntityFilter::disabled(),
+        csdemo::parser::ParseOptions::default(),
+    )
+    .unwrap_err();
+
+    assert!(matches!(
+        err.error,
+        csdemo::parser::FirstPassError::Frame(csdemo::FrameParseError::NotEnoughBytes)
+    ));
+    assert_eq!(Some(1), err.context.frame);
+}
+
+#[test]
+fn mirage_fallible_frames() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+
+    let mut count = 0;
+    for frame in csdemo::FallibleFrameIterator::parse(container.inner) {
+        let (position, _) = frame.unwrap();
+        assert_eq!(count, position.index);
+        count += 1;
+    }
+    assert_eq!(123333, count);
+}
+
+#[test]
+fn mirage_truncated() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+    let truncated = &content[..content.len() / 2];
+
+    assert!(csdemo::Container::parse(truncated).is_err());
+
+    let container = csdemo::Container::parse_lenient(truncated).unwrap();
+    let truncation = container.truncation.unwrap();
+    // Only the data up to the missing FileInfo frame is known to be missing
+    let missing = content.len() - truncated.len();
+    assert!(truncation.missing_bytes() > 0 && truncation.missing_bytes() < missing);
+
+    assert!(matches!(
+        csdemo::parser::parse(
+            csdemo::FrameIterator::parse(container.inner),
+            csdemo::parser::EntityFilter::disabled(),
+        ),
+        Err(csdemo::parser::FirstPassError::MissingFileInfo)
+    ));
+
+    let output = csdemo::parser::parse_partial(
+        csdemo::FrameIterator::parse(container.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+
+    assert_eq!("de_mirage", output.header.map_name());
+    assert!(output.info.is_none());
+    assert!(!output.events.is_empty());
+
+    for pair in output.events.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if prev.tick == next.tick {
+            assert_eq!(prev.seq + 1, next.seq);
+        } else {
+            assert!(prev.tick < next.tick);
+            assert_eq!(0, next.seq);
+        }
+    }
+}
+
+#[test]
+fn mirage_frame_index() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+    let index = csdemo::FrameIndex::build(container.inner).unwrap();
+
+    assert!(!index.full_packets.is_empty());
+    assert!(!index.send_tables.is_empty());
+
+    let full_packet = index.full_packet_before(i32::MAX).unwrap();
+    let (_, frame) = csdemo::Frame::parse(&container.inner[full_packet.offset..]).unwrap();
+    assert_eq!(csdemo::DemoCommand::FullPacket, frame.cmd);
+    assert_eq!(full_packet.tick, frame.tick);
+
+    let mut buf = Vec::new();
+    index.write(&mut buf).unwrap();
+    assert_eq!(index, csdemo::FrameIndex::read(buf.as_slice()).unwrap());
+}
+
+#[test]
+fn mirage_rewrite() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+
+    let mut writer = csdemo::DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+    for frame in csdemo::FrameIterator::parse(container.inner) {
+        writer.write_frame(&frame).unwrap();
+    }
+    let output = writer.finish().unwrap().into_inner();
+
+    let rewritten = csdemo::Container::parse(&output).unwrap();
+    assert_eq!(container.file_info_offset, rewritten.file_info_offset);
+    assert_eq!(
+        csdemo::FrameIterator::parse(container.inner).count(),
+        csdemo::FrameIterator::parse(rewritten.inner).count()
+    );
+
+    let output = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(rewritten.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+    assert_eq!("de_mirage", output.header.map_name());
+}
+
+#[test]
+fn mirage_cut() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+
+    let writer = csdemo::DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+    let output = csdemo::cutter::cut(&container, 50000, 52000, writer)
+        .unwrap()
+        .into_inner();
+    assert!(output.len() < content.len());
+
+    let cut = csdemo::Container::parse(&output).unwrap();
+    assert!(cut.file_info_frame().is_some());
+
+    let first = csdemo::FrameIterator::parse(cut.inner)
+        .find(|f| {
+            matches!(
+                f.cmd,
+                csdemo::DemoCommand::Packet | csdemo::DemoCommand::FullPacket
+            )
+        })
+        .unwrap();
+    assert_eq!(csdemo::DemoCommand::FullPacket, first.cmd);
+    assert!(first.tick <= 50000);
+
+    let output = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(cut.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+    assert_eq!("de_mirage", output.header.map_name());
+    assert!(output.info.playback_ticks() >= 2000);
+}
+
+#[test]
+fn mirage_anonymize() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+
+    let mut anonymizer = csdemo::anonymizer::Anonymizer::new();
+    let writer = csdemo::DemoWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+    let output = anonymizer
+        .anonymize(&container, writer)
+        .unwrap()
+        .into_inner();
+    assert!(!anonymizer.mappings().is_empty());
+
+    let anonymized = csdemo::Container::parse(&output).unwrap();
+    let output = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(anonymized.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+
+    assert!(!output.player_info.is_empty());
+    for player in output.player_info.values() {
+        assert!(player.name.starts_with("Player "), "{:?}", player);
+        assert!(anonymizer
+            .mappings()
+            .iter()
+            .all(|m| m.xuid != Some(player.xuid)));
+    }
+
+    // No prop of any entity contains the name or xuid of a player anymore
+    let lazy = csdemo::lazyparser::LazyParser::new(anonymized);
+    let mut controller_names = 0;
+    for entity in lazy.entities() {
+        let (_, state) = entity.unwrap();
+        for prop in state.props.iter() {
+            match &prop.value {
+                csdemo::parser::Variant::String(value) => {
+                    assert!(
+                        anonymizer
+                            .mappings()
+                            .iter()
+                            .all(|m| m.name.as_ref() != Some(value)),
+                        "{:?}",
+                        prop
+                    );
+                    if prop.prop_info.prop_name.as_ref() == "CCSPlayerController.m_iszPlayerName"
+                        && value.starts_with("Player ")
+                    {
+                        controller_names += 1;
+                    }
+                }
+                csdemo::parser::Variant::U64(value) => {
+                    assert!(
+                        anonymizer.mappings().iter().all(|m| m.xuid != Some(*value)),
+                        "{:?}",
+                        prop
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+    assert!(controller_names > 0);
+
+    let mut report = Vec::new();
+    anonymizer.write_report(&mut report).unwrap();
+    let report = String::from_utf8(report).unwrap();
+    assert_eq!(anonymizer.mappings().len() + 1, report.lines().count());
+}
+
+#[test]
+fn mirage_handler() {
+    struct Counter {
+        game_events: usize,
+        user_messages: usize,
+        last_tick: u32,
+    }
+
+    impl csdemo::parser::DemoHandler for Counter {
+        fn on_game_event(
+            &mut self,
+            tick: u32,
+            _event: GameEvent,
+        ) -> Result<(), csdemo::parser::FirstPassError> {
+            assert!(tick >= self.last_tick);
+            self.last_tick = tick;
+            self.game_events += 1;
+            Ok(())
+        }
+
+        fn on_user_message(
+            &mut self,
+            _tick: u32,
+            _msg_type: csdemo::NetmessageType,
+            _data: &[u8],
+        ) -> Result<(), csdemo::parser::FirstPassError> {
+            self.user_messages += 1;
+            Ok(())
+        }
+    }
+
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+    let container = csdemo::Container::parse(&content).unwrap();
+
+    let output = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(container.inner),
+        csdemo::parser::EntityFilter::disabled(),
+    )
+    .unwrap();
+
+    let mut counter = Counter {
+        game_events: 0,
+        user_messages: 0,
+        last_tick: 0,
+    };
+    csdemo::parser::Engine::new(csdemo::parser::EntityFilter::disabled())
+        .run(csdemo::FrameIterator::parse(container.inner), &mut counter)
+        .unwrap();
+
+    let game_events = output
+        .events
+        .iter()
+        .filter(|e| matches!(e.event, DemoEvent::GameEvent(_)))
+        .count();
+    assert_eq!(game_events, counter.game_events);
+    assert!(counter.user_messages > 0);
+}
+
+#[test]
+fn mirage_parallel() {
+    let content = std::fs::read("testfiles/mirage.dem").unwrap();
+
+    let container = csdemo::Container::parse(&content).unwrap();
+    let sequential = csdemo::parser::parse(
+        csdemo::FrameIterator::parse(container.inner),
+        csdemo::parser::EntityFilter::all(),
+    )
+    .unwrap();
+    let parallel =
+        csdemo::parser::parse_parallel(container.inner, csdemo::parser::EntityFilter::all, 4)
+            .unwrap();
+
+    assert_eq!(sequential.header, parallel.header);
+    assert_eq!(sequential.info, parallel.info);
+    assert_eq!(sequential.player_info, parallel.player_info);
+    assert_eq!(sequential.roster, parallel.roster);
+    assert_eq!(sequential.events, parallel.events);
+
+    assert_eq!(
+        sequential.entity_states.ticks.len(),
+        parallel.entity_states.ticks.len()
+    );
+    for (seq_tick, par_tick) in sequential
+        .entity_states
+        .ticks
+        .iter()
+        .zip(parallel.entity_states.ticks.iter())
+    {
+        assert_eq!(seq_tick.tick, par_tick.tick);
+        assert_eq!(seq_tick.states, par_tick.states);
+    }
+}
+
+#[test]
+fn parse_error_context() {
+    // A FileHeader followed by a compressed Packet containing garbage
+    let data = [1, 0, 0, 7 | 64, 5, 1, 0xff];
+
+    let error = csdemo::parser::parse_with_options(
+        csdemo::FrameIterator::parse(&data),
+        csdemo::parser::EntityFilter::disabled(),
+        csdemo::parser::ParseOptions::default(),
+    )
+    .unwrap_err();
+
+    assert_eq!(Some(1), error.context.frame);
+    assert!(matches!(
+        error.error,
+        csdemo::parser::FirstPassError::DecompressFrame(_)
+    ));
+
+    // The whole chain of errors is available, down to the error of snappy
+    let mut chain = 0;
+    let mut source = std::error::Error::source(&error);
+    while let Some(inner) = source {
+        assert!(!inner.to_string().contains("Decompress"), "{}", inner);
+        source = inner.source();
+        chain += 1;
+    }
+    assert!(chain >= 2);
+}
+
+#[test]
+fn partial_with_options() {
+    // Only a FileHeader, like a demo that just started recording
+    let data = [1, 0, 0];
+
+    let output = csdemo::parser::parse_partial_with_options(
+        csdemo::FrameIterator::parse(&data),
+        csdemo::parser::EntityFilter::disabled(),
+        csdemo::parser::ParseOptions::strict(),
+    )
+    .unwrap();
+    assert!(output.info.is_none());
+    assert!(output.events.is_empty());
+
+    let error = csdemo::parser::parse_with_options(
+        csdemo::FrameIterator::parse(&data),
+        csdemo::parser::EntityFilter::disabled(),
+        csdemo::parser::ParseOptions::strict(),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.error,
+        csdemo::parser::FirstPassError::MissingFileInfo
+    ));
+}